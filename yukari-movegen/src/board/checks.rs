@@ -0,0 +1,81 @@
+use tinyvec::ArrayVec;
+
+use super::Board;
+use crate::{
+    chessmove::{Move, MoveType},
+    piece::Piece,
+    square::{Direction, Square, Square16x8},
+};
+
+impl Board {
+    /// Returns true if the legal move `m` puts the opponent in check, either directly or by uncovering a slider.
+    #[must_use]
+    pub fn gives_check(&self, m: Move) -> bool {
+        let Some(piece) = self.data.piece_from_square(m.from) else {
+            return false;
+        };
+        let king_square = self.data.king_square(!self.side);
+
+        // The squares the move empties and the piece that could give a direct check from its new square. When castling,
+        // that is the rook.
+        let (checker, checker_square, vacated) = match m.kind {
+            MoveType::Castle => {
+                let (rook_from, rook_dest) = if m.dest > m.from {
+                    (m.dest.east(), m.dest.west())
+                } else {
+                    (m.dest.west().and_then(Square::west), m.dest.east())
+                };
+                let (Some(rook_from), Some(rook_dest)) = (rook_from, rook_dest) else {
+                    return false;
+                };
+                (Piece::Rook, rook_dest, [Some(m.from), Some(rook_from)])
+            }
+            MoveType::EnPassant => (piece, m.dest, [Some(m.from), m.dest.relative_south(self.side)]),
+            _ => (m.prom.unwrap_or(piece), m.dest, [Some(m.from), None]),
+        };
+        let occupied = |square: Square| {
+            square == m.dest || square == checker_square || (self.data.has_piece(square) && !vacated.contains(&Some(square)))
+        };
+        let clear_between = |from: Square, dest: Square, dir: Direction| {
+            Square16x8::from_square(from).ray_attacks(dir).take_while(|&square| square != dest).all(|square| !occupied(square))
+        };
+
+        let direct = match checker {
+            Piece::Pawn => checker_square.pawn_attacks(self.side).any(|square| square == king_square),
+            Piece::Knight => checker_square.knight_attacks().any(|square| square == king_square),
+            Piece::King => false,
+            Piece::Bishop | Piece::Rook | Piece::Queen => checker_square
+                .direction(king_square)
+                .is_some_and(|dir| dir.valid_for_slider(checker) && clear_between(checker_square, king_square, dir)),
+        };
+        if direct {
+            return true;
+        }
+
+        // A discovered check comes from a slider behind one of the vacated squares, looking through it at the king.
+        vacated.into_iter().flatten().any(|square| {
+            let Some(dir) = king_square.direction(square) else {
+                return false;
+            };
+            if !dir.diagonal() && !dir.orthogonal() {
+                return false;
+            }
+            let Some(blocker) = Square16x8::from_square(king_square).ray_attacks(dir).find(|&square| occupied(square)) else {
+                return false;
+            };
+            if blocker == m.dest || blocker == checker_square || self.data.colour_from_square(blocker) != Some(self.side) {
+                return false;
+            }
+            matches!(self.data.piece_from_square(blocker), Some(slider @ (Piece::Bishop | Piece::Rook | Piece::Queen)) if dir.valid_for_slider(slider))
+        })
+    }
+
+    /// Generate the legal moves that are not captures and give check.
+    ///
+    /// These are the quiet moves worth a look in quiescence search, where a check may turn out to be mate.
+    pub fn generate_checks(&self, v: &mut ArrayVec<[Move; 256]>) {
+        let mut quiets = ArrayVec::new();
+        self.generate_quiets(&mut quiets);
+        v.extend(quiets.into_iter().filter(|&m| self.gives_check(m)));
+    }
+}
@@ -1,5 +1,5 @@
 use super::{
-    bitlist::{Bitlist, BitlistArray}, eval::Eval, index::{PieceIndex, PieceIndexArray}, piecelist::Piecelist, piecemask::Piecemask, zobrist::Zobrist
+    bitlist::{Bitlist, BitlistArray}, index::{PieceIndex, PieceIndexArray}, piecelist::Piecelist, piecemask::Piecemask, zobrist::Zobrist
 };
 use crate::{
     colour::Colour,
@@ -16,8 +16,10 @@ pub struct BoardData {
     piecemask: Piecemask,
     /// Zobrist hash.
     hash: u64,
-    /// Evaluation state.
-    eval: Eval,
+    /// Zobrist hash of the pawns alone.
+    pawn_hash: u64,
+    /// Zobrist hash of the number of pieces of each kind and colour.
+    material_hash: u64,
 }
 
 impl BoardData {
@@ -29,7 +31,8 @@ impl BoardData {
             index: PieceIndexArray::new(),
             piecemask: Piecemask::new(),
             hash: 0,
-            eval: Eval::new(),
+            pawn_hash: 0,
+            material_hash: 0,
         }
     }
 
@@ -124,14 +127,18 @@ impl BoardData {
     }
 
     /// Pawn-only Zobrist hash of this position.
-    pub fn hash_pawns(&self, zobrist: &Zobrist) -> u64 {
-        let mut hash = 0;
-        for pawn in self.pawns() {
-            let square = self.square_of_piece(pawn);
-            let colour = pawn.colour();
-            zobrist.add_piece(colour, Piece::Pawn, square, &mut hash);
-        }
-        hash
+    pub const fn pawn_hash(&self) -> u64 {
+        self.pawn_hash
+    }
+
+    /// Zobrist hash of the material on the board, regardless of where it stands.
+    pub const fn material_hash(&self) -> u64 {
+        self.material_hash
+    }
+
+    /// The number of pieces of a kind and colour on the board.
+    fn piece_count(&self, piece: Piece, colour: Colour) -> u32 {
+        (self.piecemask.pieces_of_type(piece) & Bitlist::mask_from_colour(colour)).count_ones()
     }
 
     /// Add a `Piece` to a `Square`.
@@ -140,7 +147,10 @@ impl BoardData {
         self.piecelist.add_piece(piece_index, square);
         self.index.add_piece(piece_index, square);
         zobrist.add_piece(colour, self.piece_from_bit(piece_index), square, &mut self.hash);
-        self.eval.add_piece(piece, square, colour);
+        if piece == Piece::Pawn {
+            zobrist.add_piece(colour, piece, square, &mut self.pawn_hash);
+        }
+        zobrist.toggle_material(colour, piece, self.piece_count(piece, colour) - 1, &mut self.material_hash);
 
         if update {
             self.update_attacks(square, piece_index, piece, true, None);
@@ -152,11 +162,15 @@ impl BoardData {
     pub fn remove_piece(&mut self, piece_index: PieceIndex, update: bool, zobrist: &Zobrist) {
         let square = self.square_of_piece(piece_index);
         let piece = self.piece_from_bit(piece_index);
+        let count = self.piece_count(piece, piece_index.colour());
+        zobrist.toggle_material(piece_index.colour(), piece, count - 1, &mut self.material_hash);
         self.piecemask.remove_piece(piece_index);
         self.piecelist.remove_piece(piece_index, square);
         self.index.remove_piece(piece_index, square);
         zobrist.remove_piece(piece_index.colour(), piece, square, &mut self.hash);
-        self.eval.remove_piece(piece, square, piece_index.colour());
+        if piece == Piece::Pawn {
+            zobrist.remove_piece(piece_index.colour(), piece, square, &mut self.pawn_hash);
+        }
 
         if update {
             self.update_attacks(square, piece_index, piece, false, None);
@@ -168,13 +182,7 @@ impl BoardData {
     pub fn move_piece(&mut self, from_square: Square, to_square: Square, zobrist: &Zobrist) {
         let piece_index = self.index[from_square].expect("attempted to move piece from empty square");
         let piece = self.piece_from_bit(piece_index);
-        let slide_dir = from_square.direction(to_square).and_then(|dir| {
-            if matches!(piece, Piece::Bishop | Piece::Rook | Piece::Queen) {
-                Some(dir)
-            } else {
-                None
-            }
-        });
+        let slide_dir = from_square.direction(to_square).filter(|_| matches!(piece, Piece::Bishop | Piece::Rook | Piece::Queen));
 
         self.update_attacks(from_square, piece_index, piece, false, slide_dir);
         self.update_sliders(from_square, true);
@@ -185,7 +193,9 @@ impl BoardData {
         self.piecelist.move_piece(piece_index, to_square);
         self.index.move_piece(piece_index, from_square, to_square);
         zobrist.move_piece(piece_index.colour(), piece, from_square, to_square, &mut self.hash);
-        self.eval.move_piece(piece, from_square, to_square, piece_index.colour());
+        if piece == Piece::Pawn {
+            zobrist.move_piece(piece_index.colour(), piece, from_square, to_square, &mut self.pawn_hash);
+        }
 
         if slide_dir.is_some() {
             self.bitlist.remove_piece(to_square, piece_index);
@@ -202,25 +212,20 @@ impl BoardData {
     }
 
     /// Add castling rights.
-    pub fn add_castling(&mut self, kind: usize, zobrist: &Zobrist) {
+    pub const fn add_castling(&mut self, kind: usize, zobrist: &Zobrist) {
         zobrist.add_castling(kind, &mut self.hash);
     }
 
     /// Remove castling rights.
-    pub fn remove_castling(&mut self, kind: usize, zobrist: &Zobrist) {
+    pub const fn remove_castling(&mut self, kind: usize, zobrist: &Zobrist) {
         zobrist.remove_castling(kind, &mut self.hash);
     }
 
     /// Toggle side to move.
-    pub fn toggle_side(&mut self, zobrist: &Zobrist) {
+    pub const fn toggle_side(&mut self, zobrist: &Zobrist) {
         zobrist.toggle_side(&mut self.hash);
     }
 
-    /// Evaluation from the perspective of `colour`.
-    pub fn eval(&self, colour: Colour) -> i32 {
-        self.eval.get(colour)
-    }
-
     /// Rebuild the attack set for the board.
     pub fn rebuild_attacks(&mut self) {
         for square in 0_u8..64 {
@@ -243,7 +248,7 @@ impl BoardData {
     fn update_attacks(&mut self, square: Square, bit: PieceIndex, piece: Piece, add: bool, skip_dir: Option<Direction>) {
         let update = |bitlist: &mut BitlistArray, dest: Square| {
             if add {
-                debug_assert!(dest != square);
+                debug_assert_ne!(dest, square);
                 bitlist.add_piece(dest, bit);
             } else {
                 bitlist.remove_piece(dest, bit);
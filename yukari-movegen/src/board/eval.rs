@@ -1,207 +1,267 @@
-use crate::{Colour, Piece, Square};
-
-// CREDIT: These tables come from PeSTO by Ronald Friederich.
-
-const MAT_MG: [i32; 6] = [82, 337, 365, 477, 1025, 0];
-
-const MAT_EG: [i32; 6] = [94, 281, 297, 512, 936, 0];
-
-#[rustfmt::skip]
-const PST_MG: [[i32; 64]; 6] = [
-    // Pawns
-    [
-        0,   0,   0,   0,   0,   0,  0,   0,
-        98, 134,  61,  95,  68, 126, 34, -11,
-        -6,   7,  26,  31,  65,  56, 25, -20,
-       -14,  13,   6,  21,  23,  12, 17, -23,
-       -27,  -2,  -5,  12,  17,   6, 10, -25,
-       -26,  -4,  -4, -10,   3,   3, 33, -12,
-       -35,  -1, -20, -23, -15,  24, 38, -22,
-         0,   0,   0,   0,   0,   0,  0,   0,
-    ],
-    // Knights
-    [
-        -167, -89, -34, -49,  61, -97, -15, -107,
-        -73, -41,  72,  36,  23,  62,   7,  -17,
-        -47,  60,  37,  65,  84, 129,  73,   44,
-         -9,  17,  19,  53,  37,  69,  18,   22,
-        -13,   4,  16,  13,  28,  19,  21,   -8,
-        -23,  -9,  12,  10,  19,  17,  25,  -16,
-        -29, -53, -12,  -3,  -1,  18, -14,  -19,
-       -105, -21, -58, -33, -17, -28, -19,  -23,
-    ],
-    // Bishops
-    [
-        -29,   4, -82, -37, -25, -42,   7,  -8,
-        -26,  16, -18, -13,  30,  59,  18, -47,
-        -16,  37,  43,  40,  35,  50,  37,  -2,
-         -4,   5,  19,  50,  37,  37,   7,  -2,
-         -6,  13,  13,  26,  34,  12,  10,   4,
-          0,  15,  15,  15,  14,  27,  18,  10,
-          4,  15,  16,   0,   7,  21,  33,   1,
-        -33,  -3, -14, -21, -13, -12, -39, -21,
-    ],
-    // Rooks
-    [
-        32,  42,  32,  51, 63,  9,  31,  43,
-        27,  32,  58,  62, 80, 67,  26,  44,
-        -5,  19,  26,  36, 17, 45,  61,  16,
-       -24, -11,   7,  26, 24, 35,  -8, -20,
-       -36, -26, -12,  -1,  9, -7,   6, -23,
-       -45, -25, -16, -17,  3,  0,  -5, -33,
-       -44, -16, -20,  -9, -1, 11,  -6, -71,
-       -19, -13,   1,  17, 16,  7, -37, -26,
-    ],
-    // Queens
-    [
-        -28,   0,  29,  12,  59,  44,  43,  45,
-        -24, -39,  -5,   1, -16,  57,  28,  54,
-        -13, -17,   7,   8,  29,  56,  47,  57,
-        -27, -27, -16, -16,  -1,  17,  -2,   1,
-         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
-        -14,   2, -11,  -2,  -5,   2,  14,   5,
-        -35,  -8,  11,   2,   8,  15,  -3,   1,
-         -1, -18,  -9,  10, -15, -25, -31, -50,
-    ],
-    // Kings
-    [
-        -65,  23,  16, -15, -56, -34,   2,  13,
-        29,  -1, -20,  -7,  -8,  -4, -38, -29,
-        -9,  24,   2, -16, -20,   6,  22, -22,
-       -17, -20, -12, -27, -30, -25, -14, -36,
-       -49,  -1, -27, -39, -46, -44, -33, -51,
-       -14, -14, -22, -46, -44, -30, -15, -27,
-         1,   7,  -8, -64, -43, -16,   9,   8,
-       -15,  36,  12, -54,   8, -28,  24,  14,
-    ]
-];
-
-#[rustfmt::skip]
-const PST_EG: [[i32; 64]; 6] = [
-    // Pawns
-    [
-        0,   0,   0,   0,   0,   0,   0,   0,
-        178, 173, 158, 134, 147, 132, 165, 187,
-         94, 100,  85,  67,  56,  53,  82,  84,
-         32,  24,  13,   5,  -2,   4,  17,  17,
-         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
-          4,   7,  -6,   1,   0,  -5,  -1,  -8,
-         13,   8,   8,  10,  13,   0,   2,  -7,
-          0,   0,   0,   0,   0,   0,   0,   0,
-    ],
-    // Knights
-    [
-        -58, -38, -13, -28, -31, -27, -63, -99,
-        -25,  -8, -25,  -2,  -9, -25, -24, -52,
-        -24, -20,  10,   9,  -1,  -9, -19, -41,
-        -17,   3,  22,  22,  22,  11,   8, -18,
-        -18,  -6,  16,  25,  16,  17,   4, -18,
-        -23,  -3,  -1,  15,  10,  -3, -20, -22,
-        -42, -20, -10,  -5,  -2, -20, -23, -44,
-        -29, -51, -23, -15, -22, -18, -50, -64,
-    ],
-    // Bishops
-    [
-        -14, -21, -11,  -8, -7,  -9, -17, -24,
-        -8,  -4,   7, -12, -3, -13,  -4, -14,
-         2,  -8,   0,  -1, -2,   6,   0,   4,
-        -3,   9,  12,   9, 14,  10,   3,   2,
-        -6,   3,  13,  19,  7,  10,  -3,  -9,
-       -12,  -3,   8,  10, 13,   3,  -7, -15,
-       -14, -18,  -7,  -1,  4,  -9, -15, -27,
-       -23,  -9, -23,  -5, -9, -16,  -5, -17,
-    ],
-    // Rooks
-    [
-        13, 10, 18, 15, 12,  12,   8,   5,
-        11, 13, 13, 11, -3,   3,   8,   3,
-         7,  7,  7,  5,  4,  -3,  -5,  -3,
-         4,  3, 13,  1,  2,   1,  -1,   2,
-         3,  5,  8,  4, -5,  -6,  -8, -11,
-        -4,  0, -5, -1, -7, -12,  -8, -16,
-        -6, -6,  0,  2, -9,  -9, -11,  -3,
-        -9,  2,  3, -1, -5, -13,   4, -20,
-    ],
-    // Queens
-    [
-        -9,  22,  22,  27,  27,  19,  10,  20,
-        -17,  20,  32,  41,  58,  25,  30,   0,
-        -20,   6,   9,  49,  47,  35,  19,   9,
-          3,  22,  24,  45,  57,  40,  57,  36,
-        -18,  28,  19,  47,  31,  34,  39,  23,
-        -16, -27,  15,   6,   9,  17,  10,   5,
-        -22, -23, -30, -16, -16, -23, -36, -32,
-        -33, -28, -22, -43,  -5, -32, -20, -41,
-    ],
-    // Kings
-    [
-        -74, -35, -18, -18, -11,  15,   4, -17,
-        -12,  17,  14,  17,  17,  38,  23,  11,
-         10,  17,  23,  15,  20,  45,  44,  13,
-         -8,  22,  24,  27,  26,  33,  26,   3,
-        -18,  -4,  21,  24,  27,  23,   9, -11,
-        -19,  -3,  11,  21,  23,  16,   7,  -9,
-        -27, -11,   4,  13,  14,   4,  -5, -17,
-        -53, -34, -21, -11, -28, -14, -24, -43
-    ]
-];
-
-const PHASE: [i32; 6] = [0, 1, 1, 2, 4, 0];
+use std::{fmt::Write, fs, io, path::Path};
 
+use super::{eval_tables, Board};
+use crate::{Colour, Move, MoveType, Piece, Square};
+
+/// A static evaluation that can follow the position as moves are made.
+///
+/// Searches keep one evaluator per ply alongside the board: the child's is a copy of its parent's, told about each piece
+/// the move adds, removes or moves. Evaluators cheap enough to compute from scratch can ignore the hooks and look at the
+/// board in [`Evaluator::evaluate`] instead.
+pub trait Evaluator: Clone {
+    /// The evaluation in centipawns from the point of view of the side to move.
+    fn evaluate(&self, board: &Board) -> i32;
+
+    /// Recompute any incremental state from `board`, for example when setting up the root position.
+    fn refresh(&mut self, _board: &Board) {}
+
+    /// A piece is placed on `square`.
+    fn add_piece(&mut self, _piece: Piece, _colour: Colour, _square: Square) {}
+
+    /// A piece is taken off `square`.
+    fn remove_piece(&mut self, _piece: Piece, _colour: Colour, _square: Square) {}
+
+    /// A piece goes from `from` to `dest`.
+    fn move_piece(&mut self, piece: Piece, colour: Colour, from: Square, dest: Square) {
+        self.remove_piece(piece, colour, from);
+        self.add_piece(piece, colour, dest);
+    }
+
+    /// Update the state for the legal move `m` being made on `board`, in terms of the hooks above.
+    ///
+    /// # Panics
+    /// Panics if `m` moves from an empty square.
+    fn make_move(&mut self, board: &Board, m: Move) {
+        let colour = board.side();
+        let piece = board.piece_from_square(m.from).expect("attempted to move from an empty square");
+
+        match m.kind {
+            MoveType::Normal | MoveType::DoublePush | MoveType::Promotion => {}
+            MoveType::Capture | MoveType::CapturePromotion => {
+                let captured = board.piece_from_square(m.dest).expect("attempted to capture an empty square");
+                self.remove_piece(captured, !colour, m.dest);
+            }
+            MoveType::Castle => {
+                let (rook_from, rook_dest) = if m.dest > m.from {
+                    (m.dest.east().unwrap(), m.dest.west().unwrap())
+                } else {
+                    (m.dest.west().unwrap().west().unwrap(), m.dest.east().unwrap())
+                };
+                self.move_piece(Piece::Rook, colour, rook_from, rook_dest);
+            }
+            MoveType::EnPassant => self.remove_piece(Piece::Pawn, !colour, m.dest.relative_south(colour).unwrap()),
+        }
+
+        if let Some(prom) = m.prom {
+            self.remove_piece(piece, colour, m.from);
+            self.add_piece(prom, colour, m.dest);
+        } else {
+            self.move_piece(piece, colour, m.from, m.dest);
+        }
+    }
+}
+
+/// The weights of the hand-crafted evaluation.
+///
+/// They are kept as Rust source, in the form of `eval_tables.rs`: the tuner writes them that way so that they can be
+/// built in, and [`EvalParams::parse`] reads them back to try them out without rebuilding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalParams {
+    /// Material in the middlegame, indexed by piece.
+    pub mat_mg: [i32; 6],
+    /// Material in the endgame, indexed by piece.
+    pub mat_eg: [i32; 6],
+    /// Middlegame piece-square tables, indexed by piece and then by square from a8 as white sees the board.
+    pub pst_mg: [[i32; 64]; 6],
+    /// Endgame piece-square tables, indexed like `pst_mg`.
+    pub pst_eg: [[i32; 64]; 6],
+    /// How much each piece counts towards the middlegame, out of 24 in the starting position.
+    pub phase: [i32; 6],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl EvalParams {
+    /// The built-in weights.
+    pub const DEFAULT: Self = Self {
+        mat_mg: eval_tables::MAT_MG,
+        mat_eg: eval_tables::MAT_EG,
+        pst_mg: eval_tables::PST_MG,
+        pst_eg: eval_tables::PST_EG,
+        phase: eval_tables::PHASE,
+    };
+
+    /// Write the weights as Rust source that can replace `eval_tables.rs`.
+    #[must_use]
+    pub fn to_rust(&self) -> String {
+        const PIECES: [&str; 6] = ["Pawns", "Knights", "Bishops", "Rooks", "Queens", "Kings"];
+
+        let flat = |out: &mut String, name: &str, values: &[i32; 6]| {
+            let values = values.map(|value| value.to_string()).join(", ");
+            writeln!(out, "pub const {name}: [i32; 6] = [{values}];").unwrap();
+        };
+        let tables = |out: &mut String, name: &str, tables: &[[i32; 64]; 6]| {
+            writeln!(out, "#[rustfmt::skip]\npub const {name}: [[i32; 64]; 6] = [").unwrap();
+            for (piece, table) in PIECES.iter().zip(tables) {
+                writeln!(out, "    // {piece}\n    [").unwrap();
+                for rank in table.chunks(8) {
+                    let rank = rank.iter().map(|value| format!("{value:4}")).collect::<Vec<_>>().join(", ");
+                    writeln!(out, "        {rank},").unwrap();
+                }
+                writeln!(out, "    ],").unwrap();
+            }
+            writeln!(out, "];").unwrap();
+        };
+
+        let mut out = String::from(
+            "// The weights of the hand-crafted evaluation, as written by `yukari tune`. The piece-square tables are from white's point\n\
+             // of view, starting from a8.\n\
+             //\n\
+             // CREDIT: The untuned weights come from PeSTO by Ronald Friederich.\n\n",
+        );
+        flat(&mut out, "MAT_MG", &self.mat_mg);
+        out.push('\n');
+        flat(&mut out, "MAT_EG", &self.mat_eg);
+        out.push('\n');
+        tables(&mut out, "PST_MG", &self.pst_mg);
+        out.push('\n');
+        tables(&mut out, "PST_EG", &self.pst_eg);
+        out.push('\n');
+        flat(&mut out, "PHASE", &self.phase);
+        out
+    }
+
+    /// Read weights from Rust source in the form [`EvalParams::to_rust`] writes.
+    ///
+    /// # Errors
+    /// Returns an error if a table is missing or has the wrong number of values.
+    pub fn parse(source: &str) -> io::Result<Self> {
+        let values = |name: &str, len: usize| {
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+            let start = source.find(&format!("const {name}:")).ok_or_else(|| invalid(format!("{name} is missing")))?;
+            let body = source[start..].split_once('=').and_then(|(_, rest)| rest.split_once(';'));
+            let body = body.ok_or_else(|| invalid(format!("{name} is not a constant")))?.0;
+            let values = body
+                .lines()
+                .map(|line| line.split_once("//").map_or(line, |(code, _)| code))
+                .flat_map(|line| line.split(|c: char| c != '-' && !c.is_ascii_digit()))
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<i32>().map_err(|err| invalid(format!("{name}: {err}"))))
+                .collect::<io::Result<Vec<_>>>()?;
+            if values.len() == len {
+                Ok(values)
+            } else {
+                Err(invalid(format!("{name} has {} values, expected {len}", values.len())))
+            }
+        };
+        let flat = |name: &str| values(name, 6).map(|values| std::array::from_fn(|i| values[i]));
+        let tables = |name: &str| values(name, 6 * 64).map(|values| std::array::from_fn(|piece| std::array::from_fn(|i| values[64 * piece + i])));
+
+        Ok(Self {
+            mat_mg: flat("MAT_MG")?,
+            mat_eg: flat("MAT_EG")?,
+            pst_mg: tables("PST_MG")?,
+            pst_eg: tables("PST_EG")?,
+            phase: flat("PHASE")?,
+        })
+    }
+
+    /// Read weights from a file written by [`EvalParams::to_rust`].
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or [`EvalParams::parse`] fails.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+/// Material and piece-square tables, tapered between the middlegame and the endgame.
+///
+/// The weights start out as those from `PeSTO`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Eval {
     pst_mg: i32,
     pst_eg: i32,
     phase: i32,
+    params: &'static EvalParams,
+}
+
+impl Default for Eval {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Eval {
+    /// Evaluate with the built-in weights.
+    #[must_use]
     pub const fn new() -> Self {
-        Self { pst_mg: 0, pst_eg: 0, phase: 0 }
+        Self::with_params(&EvalParams::DEFAULT)
+    }
+
+    /// Evaluate with `params` instead of the built-in weights.
+    #[must_use]
+    pub const fn with_params(params: &'static EvalParams) -> Self {
+        Self { pst_mg: 0, pst_eg: 0, phase: 0, params }
     }
+}
 
-    pub fn get(&self, colour: Colour) -> i32 {
+impl Evaluator for Eval {
+    fn evaluate(&self, board: &Board) -> i32 {
         let score = ((self.pst_mg * self.phase) + (self.pst_eg * (24 - self.phase))) / 24;
-        if colour == Colour::White {
+        if board.side() == Colour::White {
             score
         } else {
             -score
         }
     }
 
-    pub fn add_piece(&mut self, piece: Piece, square: Square, colour: Colour) {
+    fn refresh(&mut self, board: &Board) {
+        *self = Self::with_params(self.params);
+        for bit in board.pieces() {
+            self.add_piece(board.piece_from_bit(bit), bit.colour(), board.square_of_piece(bit));
+        }
+    }
+
+    fn add_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        let EvalParams { mat_mg, mat_eg, pst_mg, pst_eg, phase } = self.params;
+        let piece = piece as usize;
         if colour == Colour::White {
-            self.pst_mg += PST_MG[piece as usize][square.flip().into_inner() as usize] + MAT_MG[piece as usize];
-            self.pst_eg += PST_EG[piece as usize][square.flip().into_inner() as usize] + MAT_EG[piece as usize];
+            self.pst_mg += pst_mg[piece][square.flip().into_inner() as usize] + mat_mg[piece];
+            self.pst_eg += pst_eg[piece][square.flip().into_inner() as usize] + mat_eg[piece];
         } else {
-            self.pst_mg -= PST_MG[piece as usize][square.into_inner() as usize] + MAT_MG[piece as usize];
-            self.pst_eg -= PST_EG[piece as usize][square.into_inner() as usize] + MAT_EG[piece as usize];
+            self.pst_mg -= pst_mg[piece][square.into_inner() as usize] + mat_mg[piece];
+            self.pst_eg -= pst_eg[piece][square.into_inner() as usize] + mat_eg[piece];
         }
-        self.phase += PHASE[piece as usize];
+        self.phase += phase[piece];
     }
 
-    pub fn remove_piece(&mut self, piece: Piece, square: Square, colour: Colour) {
+    fn remove_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        let EvalParams { mat_mg, mat_eg, pst_mg, pst_eg, phase } = self.params;
+        let piece = piece as usize;
         if colour == Colour::White {
-            self.pst_mg -= PST_MG[piece as usize][square.flip().into_inner() as usize] + MAT_MG[piece as usize];
-            self.pst_eg -= PST_EG[piece as usize][square.flip().into_inner() as usize] + MAT_EG[piece as usize];
+            self.pst_mg -= pst_mg[piece][square.flip().into_inner() as usize] + mat_mg[piece];
+            self.pst_eg -= pst_eg[piece][square.flip().into_inner() as usize] + mat_eg[piece];
         } else {
-            self.pst_mg += PST_MG[piece as usize][square.into_inner() as usize] + MAT_MG[piece as usize];
-            self.pst_eg += PST_EG[piece as usize][square.into_inner() as usize] + MAT_EG[piece as usize];
+            self.pst_mg += pst_mg[piece][square.into_inner() as usize] + mat_mg[piece];
+            self.pst_eg += pst_eg[piece][square.into_inner() as usize] + mat_eg[piece];
         }
-        self.phase -= PHASE[piece as usize];
+        self.phase -= phase[piece];
     }
 
-    pub fn move_piece(&mut self, piece: Piece, from_square: Square, to_square: Square, colour: Colour) {
+    fn move_piece(&mut self, piece: Piece, colour: Colour, from: Square, dest: Square) {
+        let EvalParams { pst_mg, pst_eg, .. } = self.params;
+        let piece = piece as usize;
         if colour == Colour::White {
-            self.pst_mg += PST_MG[piece as usize][to_square.flip().into_inner() as usize]
-                - PST_MG[piece as usize][from_square.flip().into_inner() as usize];
-            self.pst_eg += PST_EG[piece as usize][to_square.flip().into_inner() as usize]
-                - PST_EG[piece as usize][from_square.flip().into_inner() as usize];
+            self.pst_mg += pst_mg[piece][dest.flip().into_inner() as usize] - pst_mg[piece][from.flip().into_inner() as usize];
+            self.pst_eg += pst_eg[piece][dest.flip().into_inner() as usize] - pst_eg[piece][from.flip().into_inner() as usize];
         } else {
-            self.pst_mg -=
-                PST_MG[piece as usize][to_square.into_inner() as usize] - PST_MG[piece as usize][from_square.into_inner() as usize];
-            self.pst_eg -=
-                PST_EG[piece as usize][to_square.into_inner() as usize] - PST_EG[piece as usize][from_square.into_inner() as usize];
+            self.pst_mg -= pst_mg[piece][dest.into_inner() as usize] - pst_mg[piece][from.into_inner() as usize];
+            self.pst_eg -= pst_eg[piece][dest.into_inner() as usize] - pst_eg[piece][from.into_inner() as usize];
         }
     }
 }
@@ -0,0 +1,447 @@
+use std::{
+    convert::TryInto,
+    error::Error,
+    fmt::{self, Display},
+};
+
+use super::{Board, Zobrist};
+use crate::{
+    colour::Colour,
+    piece::Piece,
+    square::{File, Rank, Square},
+};
+
+/// A field of a FEN string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FenField {
+    /// The piece placement.
+    Placement,
+    /// The side to move.
+    Side,
+    /// The castling rights.
+    Castling,
+    /// The en-passant square.
+    EnPassant,
+    /// The halfmove clock.
+    Halfmove,
+    /// The fullmove number.
+    Fullmove,
+}
+
+impl Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Placement => write!(f, "piece placement"),
+            Self::Side => write!(f, "side to move"),
+            Self::Castling => write!(f, "castling rights"),
+            Self::EnPassant => write!(f, "en-passant square"),
+            Self::Halfmove => write!(f, "halfmove clock"),
+            Self::Fullmove => write!(f, "fullmove number"),
+        }
+    }
+}
+
+/// An error from parsing a position in Forsyth-Edwards Notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// The FEN ended before this field.
+    MissingField(FenField),
+    /// There is more text after the last field, starting at this byte offset.
+    TrailingData(usize),
+    /// A field has an unexpected byte at this byte offset.
+    InvalidChar { field: FenField, index: usize, byte: u8 },
+    /// A rank of the piece placement does not describe exactly eight squares.
+    BadRankLength(Rank),
+    /// The piece placement does not describe exactly eight ranks.
+    BadRankCount,
+    /// A side has more than sixteen pieces or more than eight pawns.
+    TooManyPieces(Colour),
+    /// A side does not have exactly one king.
+    KingCount(Colour),
+    /// A pawn is on the first or eighth rank.
+    PawnOnBackRank(Square),
+    /// A castling right is set, but the king and rook are not on their starting squares.
+    CastlingWithoutPieces(char),
+    /// The en-passant square could not have been created by a pawn double push.
+    InvalidEnPassant(Square),
+    /// A move counter does not fit in sixteen bits.
+    CounterOutOfRange(FenField),
+    /// The side not to move is in check.
+    OpponentInCheck,
+    /// The side to move is in check from more than two pieces.
+    TooManyCheckers,
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "FEN ended before the {field}"),
+            Self::TrailingData(index) => write!(f, "unexpected text after the last field at offset {index}"),
+            Self::InvalidChar { field, index, byte } => {
+                if byte.is_ascii_graphic() {
+                    write!(f, "unexpected '{}' in the {field} at offset {index}", char::from(*byte))
+                } else {
+                    write!(f, "unexpected byte {byte:#04x} in the {field} at offset {index}")
+                }
+            }
+            Self::BadRankLength(rank) => write!(f, "rank {rank} does not have eight squares"),
+            Self::BadRankCount => write!(f, "piece placement does not have eight ranks"),
+            Self::TooManyPieces(colour) => write!(f, "{colour:?} has too many pieces"),
+            Self::KingCount(colour) => write!(f, "{colour:?} does not have exactly one king"),
+            Self::PawnOnBackRank(square) => write!(f, "pawn on back rank square {square}"),
+            Self::CastlingWithoutPieces(c) => write!(f, "castling right '{c}' without king and rook on their starting squares"),
+            Self::InvalidEnPassant(square) => write!(f, "en-passant square {square} is not behind a pawn that just double pushed"),
+            Self::CounterOutOfRange(field) => write!(f, "{field} is out of range"),
+            Self::OpponentInCheck => write!(f, "the side not to move is in check"),
+            Self::TooManyCheckers => write!(f, "the side to move is in check from more than two pieces"),
+        }
+    }
+}
+
+impl Error for FenError {}
+
+/// A `Display` adapter that prints a board in Forsyth-Edwards Notation.
+pub struct Fen<'a>(&'a Board);
+
+impl Display for Fen<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let board = self.0;
+        for rank in (0_u8..8).rev() {
+            let mut empty = 0;
+            for file in 0_u8..8 {
+                let square = Square::from_rank_file(
+                    rank.try_into().expect("rank out of bounds"),
+                    file.try_into().expect("file out of bounds"),
+                );
+                if let (Some(piece), Some(colour)) = (board.data.piece_from_square(square), board.data.colour_from_square(square)) {
+                    if empty > 0 {
+                        write!(f, "{empty}")?;
+                        empty = 0;
+                    }
+                    let c = match piece {
+                        Piece::Pawn => 'P',
+                        Piece::Knight => 'N',
+                        Piece::Bishop => 'B',
+                        Piece::Rook => 'R',
+                        Piece::Queen => 'Q',
+                        Piece::King => 'K',
+                    };
+                    let c = match colour {
+                        Colour::White => c,
+                        Colour::Black => c.to_ascii_lowercase(),
+                    };
+                    write!(f, "{c}")?;
+                } else {
+                    empty += 1;
+                }
+            }
+            if empty > 0 {
+                write!(f, "{empty}")?;
+            }
+            if rank > 0 {
+                write!(f, "/")?;
+            }
+        }
+
+        match board.side {
+            Colour::White => write!(f, " w ")?,
+            Colour::Black => write!(f, " b ")?,
+        }
+
+        let castle = board.castle;
+        if castle == (false, false, false, false) {
+            write!(f, "-")?;
+        }
+        for (allowed, c) in [(castle.0, 'K'), (castle.1, 'Q'), (castle.2, 'k'), (castle.3, 'q')] {
+            if allowed {
+                write!(f, "{c}")?;
+            }
+        }
+
+        if let Some(ep) = board.ep {
+            write!(f, " {ep}")?;
+        } else {
+            write!(f, " -")?;
+        }
+
+        write!(f, " {} {}", board.halfmove, board.fullmove)
+    }
+}
+
+/// Split a FEN into whitespace-separated fields, along with the byte offset each starts at.
+fn fields(fen: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut index = 0;
+    std::iter::from_fn(move || {
+        while index < fen.len() && fen[index].is_ascii_whitespace() {
+            index += 1;
+        }
+        if index == fen.len() {
+            return None;
+        }
+        let start = index;
+        while index < fen.len() && !fen[index].is_ascii_whitespace() {
+            index += 1;
+        }
+        Some((start, &fen[start..index]))
+    })
+}
+
+impl Board {
+    /// Return a `Display` adapter for writing this position in Forsyth-Edwards Notation.
+    #[must_use]
+    pub const fn fen(&self) -> Fen<'_> {
+        Fen(self)
+    }
+
+    /// Write this position in Forsyth-Edwards Notation.
+    #[must_use]
+    pub fn to_fen(&self) -> String {
+        self.fen().to_string()
+    }
+
+    /// Parse a position in Forsyth-Edwards Notation into a board.
+    ///
+    /// # Errors
+    /// Returns an error if `fen` is malformed or describes an illegal position.
+    pub fn from_fen(fen: &str, zobrist: &Zobrist) -> Result<Self, FenError> {
+        Self::from_fen_bytes(fen.as_bytes(), zobrist)
+    }
+
+    /// Parse a position in Forsyth-Edwards Notation into a board.
+    ///
+    /// The move counters are optional, since EPD leaves them out.
+    ///
+    /// # Errors
+    /// Returns an error if `fen` is malformed or describes an illegal position.
+    pub fn from_fen_bytes(fen: &[u8], zobrist: &Zobrist) -> Result<Self, FenError> {
+        let mut b = Self::new();
+        let mut fields = fields(fen);
+
+        let (start, placement) = fields.next().ok_or(FenError::MissingField(FenField::Placement))?;
+        b.parse_placement(start, placement, zobrist)?;
+        // Hashing the en-passant square may depend on which pawns attack it.
+        b.data.rebuild_attacks();
+
+        let (start, side) = fields.next().ok_or(FenError::MissingField(FenField::Side))?;
+        b.side = match side {
+            b"w" => Colour::White,
+            b"b" => Colour::Black,
+            _ => {
+                let index = usize::from(matches!(side[0], b'w' | b'b'));
+                return Err(FenError::InvalidChar { field: FenField::Side, index: start + index, byte: side[index] });
+            }
+        };
+        if b.side == zobrist.keyed_side() {
+            b.data.toggle_side(zobrist);
+        }
+
+        let (start, castling) = fields.next().ok_or(FenError::MissingField(FenField::Castling))?;
+        b.parse_castling(start, castling, zobrist)?;
+
+        let (start, ep) = fields.next().ok_or(FenError::MissingField(FenField::EnPassant))?;
+        b.parse_ep(start, ep, zobrist)?;
+
+        if let Some((start, halfmove)) = fields.next() {
+            b.halfmove = parse_counter(start, halfmove, FenField::Halfmove)?;
+            if let Some((start, fullmove)) = fields.next() {
+                b.fullmove = parse_counter(start, fullmove, FenField::Fullmove)?.max(1);
+            }
+        }
+
+        if let Some((start, _)) = fields.next() {
+            return Err(FenError::TrailingData(start));
+        }
+
+        if b.illegal() {
+            return Err(FenError::OpponentInCheck);
+        }
+        if b.data.attacks_to(b.data.king_square(b.side), !b.side).count_ones() > 2 {
+            return Err(FenError::TooManyCheckers);
+        }
+
+        Ok(b)
+    }
+
+    /// Parse the piece placement field, adding the pieces to the board.
+    fn parse_placement(&mut self, start: usize, placement: &[u8], zobrist: &Zobrist) -> Result<(), FenError> {
+        let mut pieces = [0; 2];
+        let mut pawns = [0; 2];
+        let mut kings = [0; 2];
+        let mut rank = 7_u8;
+        let mut file = 0_u8;
+
+        for (i, &c) in placement.iter().enumerate() {
+            let bad_rank = || FenError::BadRankLength(rank.try_into().expect("rank out of bounds"));
+            match c {
+                b'/' => {
+                    if file != 8 {
+                        return Err(bad_rank());
+                    }
+                    if rank == 0 {
+                        return Err(FenError::BadRankCount);
+                    }
+                    rank -= 1;
+                    file = 0;
+                }
+                b'1'..=b'8' => {
+                    file += c - b'0';
+                    if file > 8 {
+                        return Err(bad_rank());
+                    }
+                }
+                _ => {
+                    let piece = match c.to_ascii_lowercase() {
+                        b'k' => Piece::King,
+                        b'q' => Piece::Queen,
+                        b'r' => Piece::Rook,
+                        b'b' => Piece::Bishop,
+                        b'n' => Piece::Knight,
+                        b'p' => Piece::Pawn,
+                        _ => return Err(FenError::InvalidChar { field: FenField::Placement, index: start + i, byte: c }),
+                    };
+                    if file == 8 {
+                        return Err(bad_rank());
+                    }
+
+                    let colour = if c.is_ascii_uppercase() { Colour::White } else { Colour::Black };
+                    let square = Square::from_rank_file(
+                        rank.try_into().expect("rank out of bounds"),
+                        file.try_into().expect("file out of bounds"),
+                    );
+
+                    // The piece list only has room for sixteen pieces per side.
+                    pieces[colour as usize] += 1;
+                    if pieces[colour as usize] > 16 {
+                        return Err(FenError::TooManyPieces(colour));
+                    }
+                    if piece == Piece::Pawn {
+                        pawns[colour as usize] += 1;
+                        if pawns[colour as usize] > 8 {
+                            return Err(FenError::TooManyPieces(colour));
+                        }
+                        if rank == 0 || rank == 7 {
+                            return Err(FenError::PawnOnBackRank(square));
+                        }
+                    }
+                    if piece == Piece::King {
+                        kings[colour as usize] += 1;
+                    }
+
+                    self.data.add_piece(piece, colour, square, false, zobrist);
+                    file += 1;
+                }
+            }
+        }
+
+        if rank != 0 {
+            return Err(FenError::BadRankCount);
+        }
+        if file != 8 {
+            return Err(FenError::BadRankLength(Rank::One));
+        }
+        for colour in [Colour::White, Colour::Black] {
+            if kings[colour as usize] != 1 {
+                return Err(FenError::KingCount(colour));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the castling rights field, checking the king and rook for each right are in place.
+    fn parse_castling(&mut self, start: usize, castling: &[u8], zobrist: &Zobrist) -> Result<(), FenError> {
+        if castling == b"-" {
+            return Ok(());
+        }
+
+        let has = |square: &str, piece: Piece, colour: Colour| {
+            let square = square.parse::<Square>().expect("castling square is valid");
+            self.data.piece_from_square(square) == Some(piece) && self.data.colour_from_square(square) == Some(colour)
+        };
+
+        let mut rights = [false; 4];
+        for (i, &c) in castling.iter().enumerate() {
+            let (kind, colour, king_square, rook_square) = match c {
+                b'K' => (0, Colour::White, "e1", "h1"),
+                b'Q' => (1, Colour::White, "e1", "a1"),
+                b'k' => (2, Colour::Black, "e8", "h8"),
+                b'q' => (3, Colour::Black, "e8", "a8"),
+                _ => return Err(FenError::InvalidChar { field: FenField::Castling, index: start + i, byte: c }),
+            };
+            if rights[kind] {
+                return Err(FenError::InvalidChar { field: FenField::Castling, index: start + i, byte: c });
+            }
+            if !has(king_square, Piece::King, colour) || !has(rook_square, Piece::Rook, colour) {
+                return Err(FenError::CastlingWithoutPieces(char::from(c)));
+            }
+            rights[kind] = true;
+        }
+
+        self.castle = rights.into();
+        for (kind, &right) in rights.iter().enumerate() {
+            if right {
+                self.data.add_castling(kind, zobrist);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the en-passant field, checking a pawn could have just double pushed past it.
+    fn parse_ep(&mut self, start: usize, ep: &[u8], zobrist: &Zobrist) -> Result<(), FenError> {
+        if ep == b"-" {
+            return Ok(());
+        }
+
+        let invalid = |i: usize| FenError::InvalidChar { field: FenField::EnPassant, index: start + i, byte: ep[i] };
+        if !(b'a'..=b'h').contains(&ep[0]) {
+            return Err(invalid(0));
+        }
+        let Some(&rank) = ep.get(1) else {
+            return Err(FenError::MissingField(FenField::EnPassant));
+        };
+        if !(b'1'..=b'8').contains(&rank) {
+            return Err(invalid(1));
+        }
+        if ep.len() > 2 {
+            return Err(invalid(2));
+        }
+
+        let file = File::try_from(ep[0] - b'a').expect("file out of bounds");
+        let rank = Rank::try_from(rank - b'1').expect("rank out of bounds");
+        let square = Square::from_rank_file(rank, file);
+
+        // The pawn that just moved is in front of the en-passant square, and the squares it passed over are empty.
+        let plausible = (|| {
+            let behind = square.relative_south(self.side)?;
+            let origin = square.relative_north(self.side)?;
+            let pusher = self.data.piece_index(behind)?;
+            Some(
+                Rank::from(behind).is_relative_fourth(!self.side)
+                    && self.data.piece_from_bit(pusher) == Piece::Pawn
+                    && pusher.colour() != self.side
+                    && !self.data.has_piece(square)
+                    && !self.data.has_piece(origin),
+            )
+        })();
+        if plausible != Some(true) {
+            return Err(FenError::InvalidEnPassant(square));
+        }
+
+        self.set_ep(zobrist, Some(square));
+        Ok(())
+    }
+}
+
+/// Parse a move counter field.
+fn parse_counter(start: usize, counter: &[u8], field: FenField) -> Result<u16, FenError> {
+    let mut value = 0_u16;
+    for (i, &c) in counter.iter().enumerate() {
+        if !c.is_ascii_digit() {
+            return Err(FenError::InvalidChar { field, index: start + i, byte: c });
+        }
+        value = value
+            .checked_mul(10)
+            .and_then(|value| value.checked_add(u16::from(c - b'0')))
+            .ok_or(FenError::CounterOutOfRange(field))?;
+    }
+    Ok(value)
+}
@@ -0,0 +1,148 @@
+use super::{bitlist::Bitlist, pins::PinInfo, Board};
+use crate::{
+    chessmove::{Move, MoveType},
+    colour::Colour,
+    piece::Piece,
+    square::{Rank, Square, Square16x8},
+};
+
+impl Board {
+    /// Returns true if the side to move has a piece on the origin square of `m` that moves that way, ignoring whether
+    /// the move leaves the king in check.
+    ///
+    /// This is cheap enough to check moves that come from elsewhere, such as the transposition table, before making
+    /// them.
+    #[must_use]
+    pub fn is_pseudo_legal(&self, m: Move) -> bool {
+        let Some(bit) = self.data.piece_index(m.from) else {
+            return false;
+        };
+        if bit.colour() != self.side || m.from == m.dest {
+            return false;
+        }
+        let piece = self.data.piece_from_bit(bit);
+        let attacks = self.data.attacks_to(m.dest, self.side).contains(bit.into());
+        let dest_colour = self.data.colour_from_square(m.dest);
+        let last_rank = Rank::from(m.dest).is_relative_eighth(self.side);
+        let push = m.from.relative_north(self.side) == Some(m.dest);
+
+        match (m.kind, m.prom) {
+            (MoveType::Normal, None) if piece == Piece::Pawn => push && dest_colour.is_none() && !last_rank,
+            (MoveType::Normal, None) => attacks && dest_colour.is_none(),
+            (MoveType::Capture, None) => attacks && dest_colour == Some(!self.side) && (piece != Piece::Pawn || !last_rank),
+            (MoveType::DoublePush, None) => {
+                let Some(middle) = m.from.relative_north(self.side) else {
+                    return false;
+                };
+                piece == Piece::Pawn
+                    && middle.relative_north(self.side) == Some(m.dest)
+                    && Rank::from(m.dest).is_relative_fourth(self.side)
+                    && !self.data.has_piece(middle)
+                    && !self.data.has_piece(m.dest)
+            }
+            (MoveType::Promotion, Some(Piece::Knight | Piece::Bishop | Piece::Rook | Piece::Queen)) => {
+                piece == Piece::Pawn && push && dest_colour.is_none() && last_rank
+            }
+            (MoveType::CapturePromotion, Some(Piece::Knight | Piece::Bishop | Piece::Rook | Piece::Queen)) => {
+                piece == Piece::Pawn && attacks && dest_colour == Some(!self.side) && last_rank
+            }
+            (MoveType::EnPassant, None) => piece == Piece::Pawn && attacks && self.ep == Some(m.dest),
+            (MoveType::Castle, None) => {
+                let (kingside, queenside) = match self.side {
+                    Colour::White => (self.castle.0, self.castle.1),
+                    Colour::Black => (self.castle.2, self.castle.3),
+                };
+                if piece != Piece::King {
+                    return false;
+                }
+                let empty = |square: Option<Square>| square.is_some_and(|square| !self.data.has_piece(square));
+                let (east1, west1) = (m.from.east(), m.from.west());
+                if kingside && east1.and_then(Square::east) == Some(m.dest) {
+                    return empty(east1) && empty(Some(m.dest));
+                }
+                if queenside && west1.and_then(Square::west) == Some(m.dest) {
+                    return empty(west1) && empty(Some(m.dest)) && empty(m.dest.west());
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if `m` is a legal move in this position: one that [`Board::generate`] would produce.
+    #[must_use]
+    pub fn is_legal(&self, m: Move) -> bool {
+        if !self.is_pseudo_legal(m) {
+            return false;
+        }
+
+        let king_square = self.data.king_square(self.side);
+        let checkers = self.data.attacks_to(king_square, !self.side);
+        let attacked = |square: Square| !self.data.attacks_to(square, !self.side).empty();
+
+        if m.kind == MoveType::Castle {
+            // The king may not castle out of, through or into check.
+            let kingside = m.from.east().and_then(Square::east) == Some(m.dest);
+            let through = if kingside { m.from.east() } else { m.from.west() };
+            return checkers.empty() && through.is_some_and(|square| !attacked(square)) && !attacked(m.dest);
+        }
+
+        if m.from == king_square {
+            if attacked(m.dest) {
+                return false;
+            }
+            // Slider attacks stop at the king, so the square behind it does not show up as attacked.
+            return !checkers.into_iter().any(|checker| {
+                let checker_piece = self.data.piece_from_bit(checker);
+                let checker_square = self.data.square_of_piece(checker);
+                matches!(checker_piece, Piece::Bishop | Piece::Rook | Piece::Queen)
+                    && checker_square.direction(king_square).and_then(|dir| king_square.travel(dir)) == Some(m.dest)
+            });
+        }
+
+        // Only the king can escape a double check.
+        if checkers.count_ones() > 1 {
+            return false;
+        }
+
+        let pininfo = PinInfo::discover(self);
+        let Some(bit) = self.data.piece_index(m.from) else {
+            return false;
+        };
+        if let Some(dir) = pininfo.pins[bit.into_inner() as usize] {
+            // Pinned pieces may only move along the pin ray.
+            let Some(move_dir) = m.from.direction(m.dest) else {
+                return false;
+            };
+            if dir != move_dir && dir != move_dir.opposite() {
+                return false;
+            }
+        }
+        if m.kind == MoveType::EnPassant && pininfo.enpassant_pinned.contains(Bitlist::from(bit)) {
+            return false;
+        }
+
+        let Some(checker) = checkers.peek() else {
+            return true;
+        };
+
+        // A single check must be answered by capturing the checker or blocking it.
+        let checker_square = self.data.square_of_piece(checker);
+        if m.kind == MoveType::EnPassant {
+            return self.data.piece_from_bit(checker) == Piece::Pawn && m.dest.relative_south(self.side) == Some(checker_square);
+        }
+        if m.dest == checker_square {
+            return true;
+        }
+        if !matches!(self.data.piece_from_bit(checker), Piece::Bishop | Piece::Rook | Piece::Queen) {
+            return false;
+        }
+        let Some(dir) = king_square.direction(checker_square) else {
+            return false;
+        };
+        Square16x8::from_square(king_square)
+            .ray_attacks(dir)
+            .take_while(|&square| square != checker_square)
+            .any(|square| square == m.dest)
+    }
+}
@@ -1,8 +1,4 @@
-use std::{
-    convert::{TryFrom, TryInto},
-    ffi::CString,
-    fmt::Display,
-};
+use std::{convert::TryInto, fmt::Display};
 
 use tinyvec::ArrayVec;
 pub use zobrist::Zobrist;
@@ -15,17 +11,29 @@ use crate::{
 };
 
 mod bitlist;
+mod checks;
 mod data;
 mod eval;
+mod eval_tables;
+mod fen;
 mod index;
+mod legality;
+mod nnue;
+mod notation;
 mod piecelist;
 mod piecemask;
 mod pins;
+mod polyglot;
+mod see;
 mod zobrist;
 
 use bitlist::Bitlist;
 use data::BoardData;
+pub use eval::{Eval, EvalParams, Evaluator};
+pub use fen::{Fen, FenError, FenField};
 pub use index::PieceIndex;
+pub use nnue::{Network, Nnue};
+pub use notation::ParseMoveError;
 
 /// A chess position.
 #[derive(Clone)]
@@ -38,6 +46,10 @@ pub struct Board {
     castle: (bool, bool, bool, bool),
     /// En-passant square, if any.
     ep: Option<Square>,
+    /// Plies since the last capture or pawn move.
+    halfmove: u16,
+    /// Move number, starting at 1 and incremented after Black moves.
+    fullmove: u16,
 }
 
 impl Default for Board {
@@ -50,7 +62,7 @@ impl Display for Board {
     #[allow(clippy::missing_inline_in_public_items)]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for i in 0_u8..64_u8 {
-            let j = i ^ 56_u8;
+            let j = i ^ 0o70_u8;
 
             if let (Some(piece), Some(colour)) = (
                 self.data.piece_from_square(j.try_into().expect("square somehow out of bounds")),
@@ -79,30 +91,7 @@ impl Display for Board {
                 writeln!(f)?;
             }
         }
-        if self.side == Colour::White {
-            writeln!(f, "White to move.")?;
-        } else {
-            writeln!(f, "Black to move.")?;
-        }
-        if self.castle.0 {
-            write!(f, "K")?;
-        }
-        if self.castle.1 {
-            write!(f, "Q")?;
-        }
-        if self.castle.2 {
-            write!(f, "k")?;
-        }
-        if self.castle.3 {
-            write!(f, "q")?;
-        }
-        writeln!(f)?;
-        if let Some(ep) = self.ep {
-            writeln!(f, "{ep}")?;
-        } else {
-            writeln!(f, "-")?;
-        }
-
+        writeln!(f, "{}", self.fen())?;
         writeln!(f, "{:016x}", self.hash())?;
 
         Ok(())
@@ -114,7 +103,14 @@ impl Board {
     #[must_use]
     #[inline]
     pub const fn new() -> Self {
-        Self { side: Colour::White, castle: (false, false, false, false), ep: None, data: BoardData::new() }
+        Self {
+            side: Colour::White,
+            castle: (false, false, false, false),
+            ep: None,
+            halfmove: 0,
+            fullmove: 1,
+            data: BoardData::new(),
+        }
     }
 
     #[allow(clippy::missing_panics_doc)]
@@ -142,126 +138,17 @@ impl Board {
         false
     }
 
-    /// Parse a position in Forsyth-Edwards Notation into a board.
-    ///
-    /// # Panics
-    /// Panics if `fen` is not ASCII.
-    #[must_use]
-    pub fn from_fen(fen: &str, zobrist: &Zobrist) -> Option<Self> {
-        let fen = CString::new(fen).expect("FEN is not ASCII");
-        let fen = fen.as_bytes();
-        Self::from_fen_bytes(fen, zobrist)
-    }
-
-    /// Parse a position in Forsyth-Edwards Notation into a board.
-    ///
-    /// # Panics
-    /// Panics when invalid FEN is input.
-    #[must_use]
-    pub fn from_fen_bytes(fen: &[u8], zobrist: &Zobrist) -> Option<Self> {
-        let mut b = Self::new();
-
-        let mut idx = 0_usize;
-        let mut c = fen[idx];
-
-        for rank in (0..=7).rev() {
-            let mut file = 0;
-            while file <= 7 {
-                if (b'1'..=b'8').contains(&c) {
-                    let length = c - b'0';
-                    let mut i = 0;
-                    while i < length {
-                        file += 1;
-                        i += 1;
-                    }
-                } else {
-                    let piece = match c.to_ascii_lowercase() {
-                        b'k' => Piece::King,
-                        b'q' => Piece::Queen,
-                        b'r' => Piece::Rook,
-                        b'b' => Piece::Bishop,
-                        b'n' => Piece::Knight,
-                        b'p' => Piece::Pawn,
-                        _ => return None,
-                    };
-
-                    let colour = if c.is_ascii_uppercase() { Colour::White } else { Colour::Black };
-
-                    let square = Square::from_rank_file(rank.try_into().unwrap(), file.try_into().unwrap());
-
-                    b.data.add_piece(piece, colour, square, false, zobrist);
-
-                    file += 1;
-                }
-                idx += 1;
-                c = fen[idx];
-            }
-            if rank > 0 {
-                idx += 1;
-                c = fen[idx];
-            }
-        }
-        idx += 1;
-        c = fen[idx];
-        b.side = match c {
-            b'w' => Colour::White,
-            b'b' => Colour::Black,
-            _ => return None,
-        };
-        idx += 2;
-        c = fen[idx];
-        b.castle = (false, false, false, false);
-        if c == b'-' {
-            idx += 1;
-        } else {
-            if c == b'K' {
-                b.castle.0 = true;
-                b.data.add_castling(0, zobrist);
-                idx += 1;
-                c = fen[idx];
-            }
-            if c == b'Q' {
-                b.castle.1 = true;
-                b.data.add_castling(1, zobrist);
-                idx += 1;
-                c = fen[idx];
-            }
-            if c == b'k' {
-                b.castle.2 = true;
-                b.data.add_castling(2, zobrist);
-                idx += 1;
-                c = fen[idx];
-            }
-            if c == b'q' {
-                b.castle.3 = true;
-                b.data.add_castling(3, zobrist);
-                idx += 1;
-            }
-        }
-        idx += 1;
-        c = fen[idx];
-        if c == b'-' {
-            b.ep = None;
-        } else {
-            let file = File::try_from(c - b'a').unwrap();
-            idx += 1;
-            c = fen[idx];
-            let rank = Rank::try_from(c - b'1').unwrap();
-            b.ep = Some(Square::from_rank_file(rank, file));
-        }
-
-        b.data.rebuild_attacks();
-
-        if b.illegal() {
-            return None;
-        }
-
-        Some(b)
-    }
-
+    /// Set the en-passant square, which may be captured on by the side to move.
     fn set_ep(&mut self, zobrist: &Zobrist, ep: Option<Square>) {
-        self.data.set_ep(self.ep, ep, zobrist);
+        // Some key sets ignore en-passant squares nobody can capture on.
+        let hashed = |board: &Self, ep: Option<Square>| {
+            ep.filter(|&square| {
+                !zobrist.ep_needs_capturer() || !(board.data.attacks_to(square, board.side) & board.data.pawns()).empty()
+            })
+        };
+        let old = hashed(self, self.ep);
         self.ep = ep;
+        self.data.set_ep(old, hashed(self, ep), zobrist);
     }
 
     /// Make a move on the board.
@@ -273,6 +160,21 @@ impl Board {
     #[allow(clippy::too_many_lines)]
     pub fn make(&self, m: Move, zobrist: &Zobrist) -> Self {
         let mut b = self.clone();
+
+        // Captures and pawn moves are irreversible, so they reset the fifty-move counter.
+        if m.is_capture() || b.data.piece_from_square(m.from) == Some(Piece::Pawn) {
+            b.halfmove = 0;
+        } else {
+            b.halfmove += 1;
+        }
+        if b.side == Colour::Black {
+            b.fullmove += 1;
+        }
+
+        // Whether the old en-passant square was hashed depends on the pieces around it, so clear it before they move.
+        let ep = b.ep;
+        b.set_ep(zobrist, None);
+
         match m.kind {
             MoveType::Promotion | MoveType::Normal | MoveType::DoublePush => {}
             MoveType::Capture | MoveType::CapturePromotion => {
@@ -288,7 +190,7 @@ impl Board {
                 b.data.move_piece(rook_from, rook_to, zobrist);
             }
             MoveType::EnPassant => {
-                let target_square = b.ep.unwrap().relative_south(b.side).unwrap();
+                let target_square = ep.unwrap().relative_south(b.side).unwrap();
                 let target_piece = b.data.piece_index(target_square).unwrap();
                 b.data.remove_piece(target_piece, true, zobrist);
             }
@@ -302,12 +204,6 @@ impl Board {
             b.data.add_piece(m.prom.unwrap(), b.side, m.dest, true, zobrist);
         }
 
-        if matches!(m.kind, MoveType::DoublePush) {
-            b.set_ep(zobrist, m.from.relative_north(b.side));
-        } else {
-            b.set_ep(zobrist, None);
-        }
-
         let a1 = Square::from_rank_file(Rank::One, File::A);
         let a8 = Square::from_rank_file(Rank::Eight, File::A);
         let e1 = Square::from_rank_file(Rank::One, File::E);
@@ -359,6 +255,10 @@ impl Board {
 
         b.side = !b.side;
         b.data.toggle_side(zobrist);
+
+        if matches!(m.kind, MoveType::DoublePush) {
+            b.set_ep(zobrist, m.from.relative_north(!b.side));
+        }
         b
     }
 
@@ -587,9 +487,48 @@ impl Board {
         }
     }
 
+    /// Generate the moves out of check if the side to move is in check, returning false if it is not.
+    fn generate_evasions(&self, v: &mut ArrayVec<[Move; 256]>) -> bool {
+        let king_square = self.data.king_square(self.side);
+        match self.data.attacks_to(king_square, !self.side).count_ones() {
+            0 => return false,
+            1 => self.generate_single_check(v),
+            _ => self.generate_double_check(v),
+        }
+        true
+    }
+
+    /// Generate the evasions of a check that `keep` accepts.
+    fn generate_evasions_filtered(&self, v: &mut ArrayVec<[Move; 256]>, keep: impl Fn(&Move) -> bool) -> bool {
+        let mut evasions = ArrayVec::new();
+        if !self.generate_evasions(&mut evasions) {
+            return false;
+        }
+        v.extend(evasions.into_iter().filter(keep));
+        true
+    }
+
+    /// Generate the legal captures, including en passant and capturing promotions.
+    ///
+    /// Captures of more valuable pieces come first. Together with [`Board::generate_quiets`] this produces the same moves
+    /// as [`Board::generate`].
     pub fn generate_captures(&self, v: &mut ArrayVec<[Move; 256]>) {
-        let pininfo = pins::PinInfo::discover(self);
+        if self.generate_evasions_filtered(v, Move::is_capture) {
+            return;
+        }
+        self.generate_captures_unchecked(v, &pins::PinInfo::discover(self));
+    }
+
+    /// Generate the legal moves that are not captures, including castling and non-capturing promotions.
+    pub fn generate_quiets(&self, v: &mut ArrayVec<[Move; 256]>) {
+        if self.generate_evasions_filtered(v, |m| !m.is_capture()) {
+            return;
+        }
+        self.generate_quiets_unchecked(v, &pins::PinInfo::discover(self));
+    }
 
+    /// Generate captures when not in check.
+    fn generate_captures_unchecked(&self, v: &mut ArrayVec<[Move; 256]>, pininfo: &pins::PinInfo) {
         let mut find_attackers = |dest: Square| {
             let promotion_pieces = [Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop];
             let attacks = self.data.attacks_to(dest, self.side);
@@ -597,10 +536,10 @@ impl Board {
                 let from = self.data.square_of_piece(capturer);
                 if Rank::from(dest).is_relative_eighth(self.side) {
                     for piece in &promotion_pieces {
-                        self.try_push_move(v, from, dest, MoveType::CapturePromotion, Some(*piece), &pininfo);
+                        self.try_push_move(v, from, dest, MoveType::CapturePromotion, Some(*piece), pininfo);
                     }
                 } else {
-                    self.try_push_move(v, from, dest, MoveType::Capture, None, &pininfo);
+                    self.try_push_move(v, from, dest, MoveType::Capture, None, pininfo);
                 }
             }
             let capturers = (attacks & self.data.knights())
@@ -611,7 +550,7 @@ impl Board {
 
             for capturer in capturers {
                 let from = self.data.square_of_piece(capturer);
-                self.try_push_move(v, from, dest, MoveType::Capture, None, &pininfo);
+                self.try_push_move(v, from, dest, MoveType::Capture, None, pininfo);
             }
             for capturer in attacks & self.data.kings() {
                 let from = self.data.square_of_piece(capturer);
@@ -619,7 +558,7 @@ impl Board {
                     // Moving into check is illegal.
                     continue;
                 }
-                self.try_push_move(v, from, dest, MoveType::Capture, None, &pininfo);
+                self.try_push_move(v, from, dest, MoveType::Capture, None, pininfo);
             }
         };
 
@@ -634,7 +573,7 @@ impl Board {
             find_attackers(self.square_of_piece(victim));
         }
 
-        self.generate_pawn_enpassant(v, &pininfo);
+        self.generate_pawn_enpassant(v, pininfo);
     }
 
     #[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
@@ -780,24 +719,24 @@ impl Board {
     /// Panics when Lofty writes shitty code.
     #[allow(clippy::missing_inline_in_public_items)]
     pub fn generate(&self, v: &mut ArrayVec<[Move; 256]>) {
-        // Unless something has gone very badly wrong we have to have a king.
-        let king_square = self.data.king_square(self.side);
-        let checks = self.data.attacks_to(king_square, !self.side);
-
-        if checks.count_ones() == 1 {
-            return self.generate_single_check(v);
-        }
-        if checks.count_ones() == 2 {
-            return self.generate_double_check(v);
+        if self.generate_evasions(v) {
+            return;
         }
 
         let pininfo = pins::PinInfo::discover(self);
-        self.generate_captures(v);
+        self.generate_captures_unchecked(v, &pininfo);
+        self.generate_quiets_unchecked(v, &pininfo);
+    }
+
+    /// Generate quiet moves when not in check.
+    fn generate_quiets_unchecked(&self, v: &mut ArrayVec<[Move; 256]>, pininfo: &pins::PinInfo) {
+        // Unless something has gone very badly wrong we have to have a king.
+        let king_square = self.data.king_square(self.side);
 
         // Pawns.
         for pawn in self.data.pawns().and(Bitlist::mask_from_colour(self.side)) {
             let from = self.data.square_of_piece(pawn);
-            self.generate_pawn_quiet(v, from, &pininfo);
+            self.generate_pawn_quiet(v, from, pininfo);
         }
 
         // General quiet move loop; pawns and kings handled separately.
@@ -818,7 +757,7 @@ impl Board {
                 }
 
                 let from = self.data.square_of_piece(attacker);
-                self.try_push_move(v, from, dest, MoveType::Normal, None, &pininfo);
+                self.try_push_move(v, from, dest, MoveType::Normal, None, pininfo);
             }
         }
 
@@ -832,7 +771,7 @@ impl Board {
                 && !self.data.has_piece(east2)
                 && self.data.attacks_to(east2, !self.side).empty()
             {
-                self.try_push_move(v, king_square, east2, MoveType::Castle, None, &pininfo);
+                self.try_push_move(v, king_square, east2, MoveType::Castle, None, pininfo);
             }
         }
 
@@ -848,7 +787,7 @@ impl Board {
                 && self.data.attacks_to(west2, !self.side).empty()
                 && !self.data.has_piece(west3)
             {
-                self.try_push_move(v, king_square, west2, MoveType::Castle, None, &pininfo);
+                self.try_push_move(v, king_square, west2, MoveType::Castle, None, pininfo);
             }
         }
     }
@@ -885,24 +824,44 @@ impl Board {
         self.ep
     }
 
+    /// Castling rights: white kingside, white queenside, black kingside and black queenside.
+    #[must_use]
+    pub const fn castling_rights(&self) -> (bool, bool, bool, bool) {
+        self.castle
+    }
+
     #[must_use]
     pub const fn side(&self) -> Colour {
         self.side
     }
 
+    /// Plies since the last capture or pawn move, for the fifty-move rule.
+    #[must_use]
+    pub const fn halfmove_clock(&self) -> u16 {
+        self.halfmove
+    }
+
+    /// The move number, which starts at 1 and increments after Black moves.
+    #[must_use]
+    pub const fn fullmove_number(&self) -> u16 {
+        self.fullmove
+    }
+
     #[must_use]
     pub const fn hash(&self) -> u64 {
         self.data.hash()
     }
 
+    /// Zobrist hash of the pawns alone, for pawn-structure caches.
     #[must_use]
-    pub fn hash_pawns(&self, zobrist: &Zobrist) -> u64 {
-        self.data.hash_pawns(zobrist)
+    pub const fn pawn_key(&self) -> u64 {
+        self.data.pawn_hash()
     }
 
+    /// Zobrist hash of how many pieces of each kind and colour are on the board, for material-keyed tables.
     #[must_use]
-    pub fn eval(&self, colour: Colour) -> i32 {
-        self.data.eval(colour)
+    pub const fn material_key(&self) -> u64 {
+        self.data.material_hash()
     }
 
     #[must_use]
@@ -913,8 +872,13 @@ impl Board {
     #[must_use]
     pub fn make_null(&self, zobrist: &Zobrist) -> Self {
         let mut board = self.clone();
-        board.side = !board.side;
+        board.halfmove += 1;
+        if board.side == Colour::Black {
+            board.fullmove += 1;
+        }
+        // Clear the en-passant square first: whether it was hashed depends on the side to move.
         board.set_ep(zobrist, None);
+        board.side = !board.side;
         board.data.toggle_side(zobrist);
         board
     }
@@ -0,0 +1,244 @@
+use std::{fs, io, path::Path};
+
+use super::{eval::Evaluator, Board};
+use crate::{Colour, Piece, Square};
+
+/// The size of the hidden layer of each perspective.
+pub const HIDDEN: usize = 256;
+
+/// One input feature per piece kind and colour on each square.
+const INPUTS: usize = 768;
+
+/// Quantisation of the feature transformer.
+const QA: i32 = 255;
+/// Quantisation of the output layer.
+const QB: i32 = 64;
+/// Converts the network output into centipawns.
+const SCALE: i32 = 400;
+
+/// The hidden layer of one perspective.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C, align(64))]
+pub struct Accumulator([i16; HIDDEN]);
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self([0; HIDDEN])
+    }
+}
+
+impl Accumulator {
+    fn add(&mut self, weights: &Self) {
+        for (value, weight) in self.0.iter_mut().zip(weights.0.iter()) {
+            *value += *weight;
+        }
+    }
+
+    fn sub(&mut self, weights: &Self) {
+        for (value, weight) in self.0.iter_mut().zip(weights.0.iter()) {
+            *value -= *weight;
+        }
+    }
+
+    fn add_sub(&mut self, add: &Self, sub: &Self) {
+        for ((value, add), sub) in self.0.iter_mut().zip(add.0.iter()).zip(sub.0.iter()) {
+            *value += *add - *sub;
+        }
+    }
+}
+
+/// A (768 -> `HIDDEN`)x2 -> 1 perspective network with a squared clipped `ReLU` activation.
+///
+/// Both perspectives share the feature transformer. Each sees the board from its own side, so black's features are
+/// flipped vertically and its pieces count as "ours".
+pub struct Network {
+    feature_weights: Box<[Accumulator]>,
+    feature_bias: Accumulator,
+    /// The weights of the side to move's hidden layer, then the other side's.
+    output_weights: [Accumulator; 2],
+    output_bias: i16,
+}
+
+impl Network {
+    /// The size of a network file: the weights and biases as little-endian `i16`s, in layer order.
+    pub const FILE_SIZE: usize = (INPUTS * HIDDEN + HIDDEN + 2 * HIDDEN + 1) * 2;
+
+    /// Read a network from the raw weights that [`Network::FILE_SIZE`] describes.
+    ///
+    /// Up to 63 trailing bytes are ignored, since trainers often pad the file to a multiple of 64 bytes. Any more means
+    /// the network has another architecture.
+    ///
+    /// # Errors
+    /// Returns an error if `bytes` is not the size of a network.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if !(Self::FILE_SIZE..Self::FILE_SIZE + 64).contains(&bytes.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("network is {} bytes, expected {} plus up to 63 bytes of padding", bytes.len(), Self::FILE_SIZE),
+            ));
+        }
+
+        let mut values = bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]]));
+        let mut read_accumulator = || {
+            let mut accumulator = Accumulator::default();
+            for (value, read) in accumulator.0.iter_mut().zip(&mut values) {
+                *value = read;
+            }
+            accumulator
+        };
+
+        let feature_weights = (0..INPUTS).map(|_| read_accumulator()).collect();
+        let feature_bias = read_accumulator();
+        let output_weights = [read_accumulator(), read_accumulator()];
+        let output_bias = values.next().unwrap_or_default();
+        Ok(Self { feature_weights, feature_bias, output_weights, output_bias })
+    }
+
+    /// Read a network from a file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not the size of a network.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// The network built in with the `embedded-nnue` feature, from the file named by `YUKARI_NNUE` at build time.
+    #[cfg(feature = "embedded-nnue")]
+    #[must_use]
+    pub fn embedded() -> &'static Self {
+        static EMBEDDED: std::sync::LazyLock<Network> = std::sync::LazyLock::new(|| {
+            Network::from_bytes(include_bytes!(env!("YUKARI_NNUE"))).expect("embedded network is the wrong size")
+        });
+        &EMBEDDED
+    }
+
+    /// The index of a piece's feature from the point of view of `perspective`.
+    fn feature(perspective: Colour, piece: Piece, colour: Colour, square: Square) -> usize {
+        let square = if perspective == Colour::White { square } else { square.flip() };
+        usize::from(colour != perspective) * 384 + piece as usize * 64 + square.into_inner() as usize
+    }
+
+    /// The hidden layers of an empty board.
+    const fn empty_accumulators(&self) -> [Accumulator; 2] {
+        [self.feature_bias; 2]
+    }
+
+    fn add_piece(&self, accumulators: &mut [Accumulator; 2], piece: Piece, colour: Colour, square: Square) {
+        for perspective in [Colour::White, Colour::Black] {
+            let weights = &self.feature_weights[Self::feature(perspective, piece, colour, square)];
+            accumulators[perspective as usize].add(weights);
+        }
+    }
+
+    fn remove_piece(&self, accumulators: &mut [Accumulator; 2], piece: Piece, colour: Colour, square: Square) {
+        for perspective in [Colour::White, Colour::Black] {
+            let weights = &self.feature_weights[Self::feature(perspective, piece, colour, square)];
+            accumulators[perspective as usize].sub(weights);
+        }
+    }
+
+    fn move_piece(&self, accumulators: &mut [Accumulator; 2], piece: Piece, colour: Colour, from: Square, dest: Square) {
+        for perspective in [Colour::White, Colour::Black] {
+            let add = &self.feature_weights[Self::feature(perspective, piece, colour, dest)];
+            let sub = &self.feature_weights[Self::feature(perspective, piece, colour, from)];
+            accumulators[perspective as usize].add_sub(add, sub);
+        }
+    }
+
+    /// The evaluation in centipawns from the point of view of `colour`.
+    fn evaluate(&self, accumulators: &[Accumulator; 2], colour: Colour) -> i32 {
+        let us = &accumulators[colour as usize];
+        let them = &accumulators[!colour as usize];
+        let sum = screlu_dot(us, &self.output_weights[0]) + screlu_dot(them, &self.output_weights[1]);
+        (sum / QA + i32::from(self.output_bias)) * SCALE / (QA * QB)
+    }
+}
+
+/// Evaluation with a [`Network`], keeping its hidden layers up to date as pieces move.
+#[derive(Clone)]
+pub struct Nnue {
+    network: &'static Network,
+    /// The hidden layers for white and black.
+    accumulators: [Accumulator; 2],
+}
+
+impl Nnue {
+    /// Evaluate with `network`. Call [`Evaluator::refresh`] before use.
+    #[must_use]
+    pub const fn new(network: &'static Network) -> Self {
+        Self { network, accumulators: network.empty_accumulators() }
+    }
+}
+
+impl Evaluator for Nnue {
+    fn evaluate(&self, board: &Board) -> i32 {
+        self.network.evaluate(&self.accumulators, board.side())
+    }
+
+    fn refresh(&mut self, board: &Board) {
+        self.accumulators = self.network.empty_accumulators();
+        for bit in board.pieces() {
+            self.network.add_piece(&mut self.accumulators, board.piece_from_bit(bit), bit.colour(), board.square_of_piece(bit));
+        }
+    }
+
+    fn add_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        self.network.add_piece(&mut self.accumulators, piece, colour, square);
+    }
+
+    fn remove_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        self.network.remove_piece(&mut self.accumulators, piece, colour, square);
+    }
+
+    fn move_piece(&mut self, piece: Piece, colour: Colour, from: Square, dest: Square) {
+        self.network.move_piece(&mut self.accumulators, piece, colour, from, dest);
+    }
+}
+
+/// The dot product of the squared clipped `ReLU` of `values` with `weights`.
+#[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
+fn screlu_dot(values: &Accumulator, weights: &Accumulator) -> i32 {
+    values
+        .0
+        .iter()
+        .zip(weights.0.iter())
+        .map(|(&value, &weight)| {
+            let value = i32::from(value).clamp(0, QA);
+            value * value * i32::from(weight)
+        })
+        .sum()
+}
+
+/// The dot product of the squared clipped `ReLU` of `values` with `weights`.
+///
+/// This multiplies each clipped value by its weight before squaring, which stays within 16 bits as long as the output
+/// weights are within ±128, as trainers clip them to be.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+#[allow(clippy::cast_ptr_alignment)]
+fn screlu_dot(values: &Accumulator, weights: &Accumulator) -> i32 {
+    use std::arch::x86_64::{
+        __m256i, _mm256_add_epi32, _mm256_castsi256_si128, _mm256_extracti128_si256, _mm256_load_si256, _mm256_madd_epi16,
+        _mm256_max_epi16, _mm256_min_epi16, _mm256_mullo_epi16, _mm256_set1_epi16, _mm256_setzero_si256, _mm_add_epi32,
+        _mm_cvtsi128_si32, _mm_shuffle_epi32,
+    };
+
+    const LANES: usize = 16;
+
+    // SAFETY: AVX2 is enabled at compile time, and accumulators are 64-byte aligned and a whole number of vectors long.
+    unsafe {
+        let zero = _mm256_setzero_si256();
+        #[allow(clippy::cast_possible_truncation)]
+        let qa = _mm256_set1_epi16(QA as i16);
+        let mut sum = _mm256_setzero_si256();
+        for i in (0..HIDDEN).step_by(LANES) {
+            let value = _mm256_load_si256(values.0.as_ptr().add(i).cast::<__m256i>());
+            let weight = _mm256_load_si256(weights.0.as_ptr().add(i).cast::<__m256i>());
+            let clipped = _mm256_min_epi16(_mm256_max_epi16(value, zero), qa);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, _mm256_mullo_epi16(clipped, weight)));
+        }
+        let sum = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256::<1>(sum));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32::<0b0100_1110>(sum));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32::<0b1011_0001>(sum));
+        _mm_cvtsi128_si32(sum)
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Write},
+};
+
+use tinyvec::ArrayVec;
+
+use super::{Board, Zobrist};
+use crate::{
+    chessmove::{Move, MoveType},
+    piece::Piece,
+    square::{File, Rank, Square},
+};
+
+/// An error from parsing a move in coordinate or standard algebraic notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseMoveError {
+    /// The text is not a move in the expected notation.
+    Malformed,
+    /// The text describes a move, but it is not legal in this position.
+    Illegal,
+    /// The text matches more than one legal move.
+    Ambiguous,
+}
+
+impl Display for ParseMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed move"),
+            Self::Illegal => write!(f, "illegal move"),
+            Self::Ambiguous => write!(f, "ambiguous move"),
+        }
+    }
+}
+
+impl Error for ParseMoveError {}
+
+/// Parse a square from two bytes of algebraic notation.
+fn parse_square(file: u8, rank: u8) -> Option<Square> {
+    if !(b'a'..=b'h').contains(&file) || !(b'1'..=b'8').contains(&rank) {
+        return None;
+    }
+    let file = File::try_from(file - b'a').ok()?;
+    let rank = Rank::try_from(rank - b'1').ok()?;
+    Some(Square::from_rank_file(rank, file))
+}
+
+/// Parse an uppercase SAN piece letter.
+const fn parse_piece(c: u8) -> Option<Piece> {
+    match c {
+        b'N' => Some(Piece::Knight),
+        b'B' => Some(Piece::Bishop),
+        b'R' => Some(Piece::Rook),
+        b'Q' => Some(Piece::Queen),
+        b'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+/// The uppercase SAN letter of a piece.
+const fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+impl Board {
+    /// Generate the legal moves of this position into a fresh list.
+    fn legal_moves(&self) -> ArrayVec<[Move; 256]> {
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        self.generate(&mut moves);
+        moves
+    }
+
+    /// Write a legal move in Standard Algebraic Notation, including check and mate suffixes.
+    ///
+    /// # Panics
+    /// Panics if `m` has no piece on its origin square.
+    #[must_use]
+    pub fn move_to_san(&self, m: Move, zobrist: &Zobrist) -> String {
+        let mut san = String::new();
+        let piece = self.piece_from_square(m.from).expect("move from empty square");
+        let from_file = char::from(b'a' + u8::from(File::from(m.from)));
+        let from_rank = char::from(b'1' + u8::from(Rank::from(m.from)));
+
+        if m.kind == MoveType::Castle {
+            san.push_str(if File::from(m.dest) == File::G { "O-O" } else { "O-O-O" });
+        } else if piece == Piece::Pawn {
+            if m.is_capture() {
+                san.push(from_file);
+                san.push('x');
+            }
+            write!(san, "{}", m.dest).unwrap();
+            if let Some(prom) = m.prom {
+                san.push('=');
+                san.push(piece_char(prom));
+            }
+        } else {
+            san.push(piece_char(piece));
+
+            // Only name as much of the origin square as needed to tell this move apart from
+            // other moves of the same piece type to the same square.
+            let rivals = self
+                .legal_moves()
+                .into_iter()
+                .filter(|other| other.dest == m.dest && other.from != m.from && self.piece_from_square(other.from) == Some(piece))
+                .collect::<ArrayVec<[Move; 256]>>();
+            if !rivals.is_empty() {
+                if rivals.iter().all(|other| File::from(other.from) != File::from(m.from)) {
+                    san.push(from_file);
+                } else if rivals.iter().all(|other| Rank::from(other.from) != Rank::from(m.from)) {
+                    san.push(from_rank);
+                } else {
+                    san.push(from_file);
+                    san.push(from_rank);
+                }
+            }
+
+            if m.is_capture() {
+                san.push('x');
+            }
+            write!(san, "{}", m.dest).unwrap();
+        }
+
+        let child = self.make(m, zobrist);
+        if child.in_check() {
+            san.push(if child.legal_moves().is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+
+    /// Parse a move in Standard Algebraic Notation and find it among the legal moves.
+    ///
+    /// Check and annotation suffixes are ignored, castling may be written with zeros, and the `=`
+    /// before a promotion piece may be left out.
+    ///
+    /// # Errors
+    /// Returns an error if `san` is malformed, or does not match exactly one legal move.
+    pub fn parse_san(&self, san: &str) -> Result<Move, ParseMoveError> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']).as_bytes();
+        let moves = self.legal_moves();
+
+        if matches!(san, b"O-O" | b"0-0" | b"O-O-O" | b"0-0-0") {
+            let file = if san.len() == 3 { File::G } else { File::C };
+            return moves
+                .into_iter()
+                .find(|m| m.kind == MoveType::Castle && File::from(m.dest) == file)
+                .ok_or(ParseMoveError::Illegal);
+        }
+
+        let (piece, mut rest) = match san.split_first() {
+            Some((&c, rest)) => parse_piece(c).map_or((Piece::Pawn, san), |piece| (piece, rest)),
+            None => return Err(ParseMoveError::Malformed),
+        };
+
+        let mut prom = None;
+        if let Some((&c, head)) = rest.split_last() {
+            if let Some(piece @ (Piece::Knight | Piece::Bishop | Piece::Rook | Piece::Queen)) = parse_piece(c) {
+                prom = Some(piece);
+                rest = head.strip_suffix(b"=").unwrap_or(head);
+            }
+        }
+        if prom.is_some() && piece != Piece::Pawn {
+            return Err(ParseMoveError::Malformed);
+        }
+
+        let [head @ .., dest_file, dest_rank] = rest else {
+            return Err(ParseMoveError::Malformed);
+        };
+        let dest = parse_square(*dest_file, *dest_rank).ok_or(ParseMoveError::Malformed)?;
+        let head = head.strip_suffix(b"x").unwrap_or(head);
+
+        let (from_file, from_rank) = match *head {
+            [] => (None, None),
+            [c @ b'a'..=b'h'] => (Some(c), None),
+            [c @ b'1'..=b'8'] => (None, Some(c)),
+            [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => (Some(file), Some(rank)),
+            _ => return Err(ParseMoveError::Malformed),
+        };
+
+        let mut candidates = moves.into_iter().filter(|m| {
+            m.dest == dest
+                && m.prom == prom
+                && m.kind != MoveType::Castle
+                && self.piece_from_square(m.from) == Some(piece)
+                && from_file.is_none_or(|c| b'a' + u8::from(File::from(m.from)) == c)
+                && from_rank.is_none_or(|c| b'1' + u8::from(Rank::from(m.from)) == c)
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(m), None) => Ok(m),
+            (Some(_), Some(_)) => Err(ParseMoveError::Ambiguous),
+            (None, _) => Err(ParseMoveError::Illegal),
+        }
+    }
+
+    /// Parse a move in coordinate notation (e.g. `e2e4` or `e7e8q`) and find it among the legal moves.
+    ///
+    /// # Errors
+    /// Returns an error if `text` is malformed or the move is not legal.
+    pub fn parse_uci_move(&self, text: &str) -> Result<Move, ParseMoveError> {
+        let (from, dest, prom) = match *text.as_bytes() {
+            [from_file, from_rank, dest_file, dest_rank, ref prom @ ..] if prom.len() <= 1 => {
+                let from = parse_square(from_file, from_rank).ok_or(ParseMoveError::Malformed)?;
+                let dest = parse_square(dest_file, dest_rank).ok_or(ParseMoveError::Malformed)?;
+                let prom = match prom {
+                    [] => None,
+                    [b'n'] => Some(Piece::Knight),
+                    [b'b'] => Some(Piece::Bishop),
+                    [b'r'] => Some(Piece::Rook),
+                    [b'q'] => Some(Piece::Queen),
+                    _ => return Err(ParseMoveError::Malformed),
+                };
+                (from, dest, prom)
+            }
+            _ => return Err(ParseMoveError::Malformed),
+        };
+        self.legal_moves().into_iter().find(|m| m.from == from && m.dest == dest && m.prom == prom).ok_or(ParseMoveError::Illegal)
+    }
+}
@@ -45,6 +45,17 @@ impl Piecemask {
         self.nbk.and(self.rqk)
     }
 
+    pub const fn pieces_of_type(&self, piece: Piece) -> Bitlist {
+        match piece {
+            Piece::Pawn => self.pawns(),
+            Piece::Knight => self.knights(),
+            Piece::Bishop => self.bishops(),
+            Piece::Rook => self.rooks(),
+            Piece::Queen => self.queens(),
+            Piece::King => self.kings(),
+        }
+    }
+
     pub const fn white(&self) -> Bitlist {
         self.occupied().and(Bitlist::white())
     }
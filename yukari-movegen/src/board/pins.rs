@@ -5,17 +5,12 @@ use crate::{
 };
 
 /// Pin information in a board.
+#[derive(Default)]
 pub struct PinInfo {
     pub pins: [Option<Direction>; 32],
     pub enpassant_pinned: Bitlist,
 }
 
-impl Default for PinInfo {
-    fn default() -> Self {
-        Self { pins: [None; 32], enpassant_pinned: Bitlist::new() }
-    }
-}
-
 impl PinInfo {
     /// Find pinned pieces and handle them specially.
     ///
@@ -77,7 +72,7 @@ impl PinInfo {
 
             match (friendly_blocker, enemy_blocker) {
                 // There are no friendly blockers: skip.
-                (None, _) => continue,
+                (None, _) => {}
                 // There is one friendly blocker: it is pinned.
                 (Some(blocker), None) => {
                     info.pins[blocker.into_inner() as usize] = Some(pinner_king_dir);
@@ -0,0 +1,188 @@
+use super::{bitlist::Bitlist, Board};
+use crate::{
+    chessmove::{Move, MoveType},
+    colour::Colour,
+    piece::Piece,
+    square::{Square, Square16x8},
+};
+
+/// Piece values for exchange evaluation, indexed by `Piece`.
+///
+/// The king is worth more than everything else put together, so that losing it always ends the exchange badly.
+const SEE_VALUE: [i32; 6] = [100, 300, 300, 500, 900, 20000];
+
+const fn see_value(piece: Piece) -> i32 {
+    SEE_VALUE[piece as usize]
+}
+
+impl Board {
+    /// The material the side to move gains from `m` before the capture, and the value of the piece left standing on
+    /// the destination square afterwards.
+    fn see_initial(&self, m: Move) -> (i32, i32) {
+        let mut gain = match m.kind {
+            MoveType::EnPassant => see_value(Piece::Pawn),
+            MoveType::Capture | MoveType::CapturePromotion => self.data.piece_from_square(m.dest).map_or(0, see_value),
+            _ => 0,
+        };
+        let piece = m.prom.map_or_else(
+            || self.data.piece_from_square(m.from).expect("move from empty square"),
+            |prom| {
+                gain += see_value(prom) - see_value(Piece::Pawn);
+                prom
+            },
+        );
+        (gain, see_value(piece))
+    }
+
+    /// All pieces attacking `dest`, of both colours.
+    fn see_attackers(&self, dest: Square) -> Bitlist {
+        self.data.attacks_to(dest, Colour::White) | self.data.attacks_to(dest, Colour::Black)
+    }
+
+    /// The slider, if any, that attacks `dest` through `from` once the pieces in `gone` have left the board.
+    fn see_xray(&self, dest: Square, from: Square, gone: Bitlist) -> Bitlist {
+        let Some(dir) = dest.direction(from) else {
+            return Bitlist::new();
+        };
+        if !dir.diagonal() && !dir.orthogonal() {
+            return Bitlist::new();
+        }
+
+        for square in Square16x8::from_square(from).ray_attacks(dir) {
+            let Some(bit) = self.data.piece_index(square) else {
+                continue;
+            };
+            if gone.contains(bit.into()) {
+                continue;
+            }
+            let piece = self.data.piece_from_bit(bit);
+            if matches!(piece, Piece::Bishop | Piece::Rook | Piece::Queen) && dir.valid_for_slider(piece) {
+                return bit.into();
+            }
+            break;
+        }
+        Bitlist::new()
+    }
+
+    /// The least valuable of `attackers` belonging to `colour`, with its piece type.
+    fn see_least_valuable(&self, attackers: Bitlist, colour: Colour) -> Option<(Bitlist, Piece)> {
+        let attackers = attackers & Bitlist::mask_from_colour(colour);
+        if attackers.empty() {
+            return None;
+        }
+        for (mask, piece) in [
+            (self.data.pawns(), Piece::Pawn),
+            (self.data.knights(), Piece::Knight),
+            (self.data.bishops(), Piece::Bishop),
+            (self.data.rooks(), Piece::Rook),
+            (self.data.queens(), Piece::Queen),
+            (self.data.kings(), Piece::King),
+        ] {
+            if let Some(bit) = (attackers & mask).peek() {
+                return Some((bit.into(), piece));
+            }
+        }
+        None
+    }
+
+    /// Static Exchange Evaluation: the material balance of `m` for the side to move, assuming both sides keep
+    /// recapturing on the destination square with their least valuable piece for as long as it pays.
+    ///
+    /// Sliders lined up behind a capturer join the exchange once it has moved. Pins are not considered, but a king
+    /// never recaptures onto a square that is still defended.
+    ///
+    /// # Panics
+    /// Panics if `m` has no piece on its origin square.
+    #[must_use]
+    pub fn see(&self, m: Move) -> i32 {
+        if m.kind == MoveType::Castle {
+            return 0;
+        }
+
+        let (first_gain, mut on_square) = self.see_initial(m);
+        let mut gains = [0_i32; 32];
+        gains[0] = first_gain;
+
+        let mut gone = Bitlist::from(self.data.piece_index(m.from).expect("move from empty square"));
+        let mut attackers = (self.see_attackers(m.dest) | self.see_xray(m.dest, m.from, gone)) & gone.invert();
+        let mut colour = !self.side;
+        let mut depth = 0;
+
+        while let Some((attacker, piece)) = self.see_least_valuable(attackers, colour) {
+            // A king may only capture if nothing can take it back.
+            if piece == Piece::King && !(attackers & Bitlist::mask_from_colour(!colour)).empty() {
+                break;
+            }
+
+            depth += 1;
+            gains[depth] = on_square - gains[depth - 1];
+            on_square = see_value(piece);
+
+            gone |= attacker;
+            let from = self.data.square_of_piece(unsafe { attacker.peek_nonzero() });
+            attackers = (attackers | self.see_xray(m.dest, from, gone)) & gone.invert();
+            colour = !colour;
+        }
+
+        // Either side may stop recapturing whenever continuing would lose material.
+        while depth > 0 {
+            gains[depth - 1] = -(-gains[depth - 1]).max(gains[depth]);
+            depth -= 1;
+        }
+        gains[0]
+    }
+
+    /// Returns true if the Static Exchange Evaluation of `m` is at least `threshold`.
+    ///
+    /// This gives the same answer as comparing [`Board::see`] against `threshold`, but stops as soon as the outcome
+    /// is known, which makes it the cheaper choice for pruning decisions.
+    ///
+    /// # Panics
+    /// Panics if `m` has no piece on its origin square.
+    #[must_use]
+    pub fn see_ge(&self, m: Move, threshold: i32) -> bool {
+        if m.kind == MoveType::Castle {
+            return threshold <= 0;
+        }
+
+        let (gain, on_square) = self.see_initial(m);
+
+        // Even if the opponent cannot recapture, we do not gain enough.
+        let mut swap = gain - threshold;
+        if swap < 0 {
+            return false;
+        }
+
+        // Even if the opponent recaptures for free, we still gain enough.
+        swap = on_square - swap;
+        if swap <= 0 {
+            return true;
+        }
+
+        let mut gone = Bitlist::from(self.data.piece_index(m.from).expect("move from empty square"));
+        let mut attackers = (self.see_attackers(m.dest) | self.see_xray(m.dest, m.from, gone)) & gone.invert();
+        let mut colour = !self.side;
+        // Whether the side to move reaches the threshold if the exchange stops here.
+        let mut result = true;
+
+        while let Some((attacker, piece)) = self.see_least_valuable(attackers, colour) {
+            // A king may only capture if nothing can take it back.
+            if piece == Piece::King && !(attackers & Bitlist::mask_from_colour(!colour)).empty() {
+                break;
+            }
+
+            result = !result;
+            swap = see_value(piece) - swap;
+            if swap < i32::from(result) {
+                break;
+            }
+
+            gone |= attacker;
+            let from = self.data.square_of_piece(unsafe { attacker.peek_nonzero() });
+            attackers = (attackers | self.see_xray(m.dest, from, gone)) & gone.invert();
+            colour = !colour;
+        }
+
+        result
+    }
+}
@@ -1,5 +1,6 @@
 use rand::{prelude::StdRng, Rng, SeedableRng};
 
+use super::polyglot::RANDOM64;
 use crate::{square::File, Colour, Piece, Square};
 
 #[derive(Clone)]
@@ -8,6 +9,11 @@ pub struct Zobrist {
     side: u64,
     ep: [u64; 8],
     castling: [u64; 4],
+    material: [[[u64; 16]; 6]; 2],
+    /// The side to move whose positions include the `side` key.
+    keyed_side: Colour,
+    /// Whether an en-passant square is only hashed when a pawn of the side to move could capture on it.
+    ep_needs_capturer: bool,
 }
 
 impl Zobrist {
@@ -18,6 +24,7 @@ impl Zobrist {
         let mut piece = [[[0_u64; 64]; 6]; 2];
         let mut ep = [0; 8];
         let mut castling = [0; 4];
+        let mut material = [[[0_u64; 16]; 6]; 2];
 
         for side in &mut piece {
             for piece_kind in side.iter_mut() {
@@ -37,22 +44,73 @@ impl Zobrist {
             *castle_flag = rng.gen();
         }
 
-        Self { piece, side, ep, castling }
+        for side in &mut material {
+            for piece_kind in side.iter_mut() {
+                for count in piece_kind.iter_mut() {
+                    *count = rng.gen();
+                }
+            }
+        }
+
+        Self { piece, side, ep, castling, material, keyed_side: Colour::Black, ep_needs_capturer: false }
+    }
+
+    /// Create the key set of the Polyglot opening book format, so that board hashes are Polyglot book keys.
+    ///
+    /// As Polyglot specifies, the side key is included when White is to move, and an en-passant square only counts
+    /// when a pawn of the side to move stands ready to capture on it. The material keys are not part of Polyglot and
+    /// are the same as those of [`Zobrist::new`].
+    #[must_use]
+    pub fn polyglot() -> Self {
+        let mut zobrist = Self::new();
+
+        for (colour, side) in zobrist.piece.iter_mut().enumerate() {
+            // Polyglot interleaves colours with black first, where `Colour` puts white first.
+            let colour_offset = usize::from(colour == usize::from(Colour::White));
+            for (piece, piece_kind) in side.iter_mut().enumerate() {
+                let kind = 2 * piece + colour_offset;
+                piece_kind.copy_from_slice(&RANDOM64[64 * kind..64 * (kind + 1)]);
+            }
+        }
+        zobrist.castling.copy_from_slice(&RANDOM64[768..772]);
+        zobrist.ep.copy_from_slice(&RANDOM64[772..780]);
+        zobrist.side = RANDOM64[780];
+        zobrist.keyed_side = Colour::White;
+        zobrist.ep_needs_capturer = true;
+        zobrist
     }
 
-    pub fn add_piece(&self, colour: Colour, piece: Piece, square: Square, hash: &mut u64) {
+    /// The side to move whose positions include the side-to-move key.
+    #[must_use]
+    pub const fn keyed_side(&self) -> Colour {
+        self.keyed_side
+    }
+
+    /// Returns true if an en-passant square should only be hashed when the side to move has a pawn that could
+    /// capture on it.
+    #[must_use]
+    pub const fn ep_needs_capturer(&self) -> bool {
+        self.ep_needs_capturer
+    }
+
+    pub const fn add_piece(&self, colour: Colour, piece: Piece, square: Square, hash: &mut u64) {
         *hash ^= self.piece[colour as usize][piece as usize][square.into_inner() as usize];
     }
 
-    pub fn remove_piece(&self, colour: Colour, piece: Piece, square: Square, hash: &mut u64) {
+    pub const fn remove_piece(&self, colour: Colour, piece: Piece, square: Square, hash: &mut u64) {
         *hash ^= self.piece[colour as usize][piece as usize][square.into_inner() as usize];
     }
 
-    pub fn move_piece(&self, colour: Colour, piece: Piece, from_square: Square, to_square: Square, hash: &mut u64) {
+    pub const fn move_piece(&self, colour: Colour, piece: Piece, from_square: Square, to_square: Square, hash: &mut u64) {
         *hash ^= self.piece[colour as usize][piece as usize][from_square.into_inner() as usize]
             ^ self.piece[colour as usize][piece as usize][to_square.into_inner() as usize];
     }
 
+    /// Toggle the material-signature key for the `count`th piece (counting from zero) of a kind.
+    pub const fn toggle_material(&self, colour: Colour, piece: Piece, count: u32, hash: &mut u64) {
+        *hash ^= self.material[colour as usize][piece as usize][count as usize];
+    }
+
     pub fn set_ep(&self, old: Option<Square>, new: Option<Square>, hash: &mut u64) {
         if let Some(ep) = old {
             *hash ^= self.ep[File::from(ep) as usize];
@@ -62,15 +120,15 @@ impl Zobrist {
         }
     }
 
-    pub fn add_castling(&self, kind: usize, hash: &mut u64) {
+    pub const fn add_castling(&self, kind: usize, hash: &mut u64) {
         *hash ^= self.castling[kind];
     }
 
-    pub fn remove_castling(&self, kind: usize, hash: &mut u64) {
+    pub const fn remove_castling(&self, kind: usize, hash: &mut u64) {
         *hash ^= self.castling[kind];
     }
 
-    pub fn toggle_side(&self, hash: &mut u64) {
+    pub const fn toggle_side(&self, hash: &mut u64) {
         *hash ^= self.side;
     }
 }
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use rand::Rng;
+use tinyvec::ArrayVec;
+
+use crate::{
+    board::{Board, Zobrist},
+    chessmove::{Move, MoveType},
+    colour::Colour,
+    pgn::{GameResult, PgnGame},
+    piece::Piece,
+    square::{File as BoardFile, Square},
+};
+
+/// An entry of a Polyglot opening book: a move playable in the position with the given key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookEntry {
+    /// The Polyglot key of the position.
+    pub key: u64,
+    /// The move, in Polyglot's packed encoding.
+    pub mv: u16,
+    /// How often the move should be played, relative to the other moves of the position.
+    pub weight: u16,
+    /// Learning data, which we keep but do not use.
+    pub learn: u32,
+}
+
+impl BookEntry {
+    /// The size of an entry on disk.
+    const SIZE: usize = 16;
+
+    const fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let [k0, k1, k2, k3, k4, k5, k6, k7, m0, m1, w0, w1, l0, l1, l2, l3] = *bytes;
+        Self {
+            key: u64::from_be_bytes([k0, k1, k2, k3, k4, k5, k6, k7]),
+            mv: u16::from_be_bytes([m0, m1]),
+            weight: u16::from_be_bytes([w0, w1]),
+            learn: u32::from_be_bytes([l0, l1, l2, l3]),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+/// Pack a move in Polyglot's encoding, where castling is written as the king capturing its own rook.
+fn encode_move(m: Move) -> u16 {
+    let dest = if m.kind == MoveType::Castle {
+        let rook_file = if BoardFile::from(m.dest) == BoardFile::G { BoardFile::H } else { BoardFile::A };
+        Square::from_rank_file(m.dest.into(), rook_file)
+    } else {
+        m.dest
+    };
+    let prom = match m.prom {
+        None | Some(Piece::Pawn | Piece::King) => 0,
+        Some(Piece::Knight) => 1,
+        Some(Piece::Bishop) => 2,
+        Some(Piece::Rook) => 3,
+        Some(Piece::Queen) => 4,
+    };
+    (prom << 12) | (u16::from(m.from.into_inner()) << 6) | u16::from(dest.into_inner())
+}
+
+/// How to choose among the book moves of a position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BookSelection {
+    /// Always play the move with the highest weight.
+    Best,
+    /// Play each move with probability proportional to its weight.
+    #[default]
+    Weighted,
+    /// Play any move with a nonzero weight, with equal probability.
+    Random,
+}
+
+impl FromStr for BookSelection {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "best" => Ok(Self::Best),
+            "weighted" => Ok(Self::Weighted),
+            "random" => Ok(Self::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for BookSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Best => write!(f, "best"),
+            Self::Weighted => write!(f, "weighted"),
+            Self::Random => write!(f, "random"),
+        }
+    }
+}
+
+/// A Polyglot opening book.
+///
+/// Books are looked up by `Board::hash`, so boards must be hashed with [`Zobrist::polyglot`].
+#[derive(Clone, Debug, Default)]
+pub struct Book {
+    /// Entries sorted by key, as in the file.
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    /// Create a book from entries in any order.
+    #[must_use]
+    pub fn new(mut entries: Vec<BookEntry>) -> Self {
+        entries.sort_by_key(|entry| (entry.key, u16::MAX - entry.weight));
+        Self { entries }
+    }
+
+    /// Read a book in Polyglot `.bin` format.
+    ///
+    /// # Errors
+    /// Returns an error if reading fails, or if the data is not a whole number of entries.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (chunks, rest) = bytes.as_chunks::<{ BookEntry::SIZE }>();
+        if !rest.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "book size is not a multiple of the entry size"));
+        }
+        Ok(Self::new(chunks.iter().map(BookEntry::from_bytes).collect()))
+    }
+
+    /// Open and read a book in Polyglot `.bin` format.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, or is not a whole number of entries.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Write the book in Polyglot `.bin` format.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for entry in &self.entries {
+            out.write_all(&entry.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The number of entries in the book.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the book has no entries.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries for the position with this key, in decreasing order of weight.
+    #[must_use]
+    pub fn entries(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let len = self.entries[start..].partition_point(|entry| entry.key == key);
+        &self.entries[start..start + len]
+    }
+
+    /// The legal book moves of a position with their weights, in decreasing order of weight.
+    ///
+    /// Entries that do not decode to a legal move, as happens with key collisions, are skipped.
+    #[must_use]
+    pub fn moves(&self, board: &Board) -> Vec<(Move, u16)> {
+        let entries = self.entries(board.hash());
+        if entries.is_empty() {
+            return Vec::new();
+        }
+
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        board.generate(&mut moves);
+        entries
+            .iter()
+            .filter_map(|entry| moves.iter().find(|&&m| encode_move(m) == entry.mv).map(|&m| (m, entry.weight)))
+            .collect()
+    }
+
+    /// Choose a book move for a position, if it has any with a nonzero weight.
+    pub fn choose<R: Rng>(&self, board: &Board, selection: BookSelection, rng: &mut R) -> Option<Move> {
+        let moves = self.moves(board).into_iter().filter(|&(_, weight)| weight > 0).collect::<Vec<_>>();
+        if moves.is_empty() {
+            return None;
+        }
+
+        match selection {
+            // The moves are already sorted by weight.
+            BookSelection::Best => Some(moves[0].0),
+            BookSelection::Random => Some(moves[rng.gen_range(0..moves.len())].0),
+            BookSelection::Weighted => {
+                let total = moves.iter().map(|&(_, weight)| u32::from(weight)).sum::<u32>();
+                let mut pick = rng.gen_range(0..total);
+                for &(m, weight) in &moves {
+                    if pick < u32::from(weight) {
+                        return Some(m);
+                    }
+                    pick -= u32::from(weight);
+                }
+                unreachable!("weighted pick out of range")
+            }
+        }
+    }
+}
+
+/// How the games that played a book move went, from the point of view of the side that played it.
+#[derive(Clone, Copy, Default)]
+struct MoveStats {
+    games: u32,
+    /// Two points for a win and one for a draw, as Polyglot weighs moves.
+    points: u32,
+}
+
+/// Builds a Polyglot book from the games of a PGN collection.
+pub struct BookBuilder<'a> {
+    zobrist: &'a Zobrist,
+    max_ply: usize,
+    min_games: u32,
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+impl<'a> BookBuilder<'a> {
+    /// Create a builder that keeps moves from the first `max_ply` plies of each game, if at least `min_games` games
+    /// played them. `zobrist` should be [`Zobrist::polyglot`] for the book to work with other programs.
+    #[must_use]
+    pub fn new(zobrist: &'a Zobrist, max_ply: usize, min_games: u32) -> Self {
+        Self { zobrist, max_ply, min_games: min_games.max(1), stats: HashMap::new() }
+    }
+
+    /// Add the opening moves of a game.
+    ///
+    /// # Panics
+    /// Panics if the game does not start from a valid position.
+    pub fn add_game(&mut self, game: &PgnGame) {
+        // The game may have been read with other keys.
+        let mut board = Board::from_fen(&game.start.to_fen(), self.zobrist).expect("game starts from an invalid position");
+        for &m in game.moves.iter().take(self.max_ply) {
+            let stats = self.stats.entry((board.hash(), encode_move(m))).or_default();
+            stats.games += 1;
+            stats.points += match (game.result, board.side()) {
+                (GameResult::WhiteWins, Colour::White) | (GameResult::BlackWins, Colour::Black) => 2,
+                (GameResult::Draw, _) => 1,
+                _ => 0,
+            };
+            board = board.make(m, self.zobrist);
+        }
+    }
+
+    /// Build the book from the moves played often enough.
+    ///
+    /// Weights are the points each move scored, scaled down where needed to fit Polyglot's 16-bit weights.
+    #[must_use]
+    pub fn build(self) -> Book {
+        let mut most_points = HashMap::<u64, u32>::new();
+        for (&(key, _), stats) in &self.stats {
+            let most = most_points.entry(key).or_default();
+            *most = (*most).max(stats.points);
+        }
+
+        let entries = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| stats.games >= self.min_games)
+            .map(|(&(key, mv), stats)| {
+                let most = u64::from(most_points[&key].max(u32::from(u16::MAX)));
+                let weight = u64::from(stats.points) * u64::from(u16::MAX) / most;
+                BookEntry { key, mv, weight: u16::try_from(weight).unwrap_or(u16::MAX), learn: 0 }
+            })
+            .collect();
+        Book::new(entries)
+    }
+}
@@ -1,14 +1,18 @@
 #![warn(clippy::pedantic, clippy::nursery, clippy::perf, clippy::style)]
 
 mod board;
+mod book;
 mod chessmove;
 mod colour;
+mod pgn;
 mod piece;
 mod square;
 
-pub use board::{Board, PieceIndex, Zobrist};
+pub use board::{Board, Eval, EvalParams, Evaluator, Fen, FenError, FenField, Network, Nnue, ParseMoveError, PieceIndex, Zobrist};
+pub use book::{Book, BookBuilder, BookEntry, BookSelection};
 pub use chessmove::{Move, MoveType};
 pub use colour::Colour;
+pub use pgn::{GameResult, PgnError, PgnGame, PgnReader};
 pub use piece::Piece;
 pub use square::Square;
 use tinyvec::ArrayVec;
@@ -1676,3 +1680,1101 @@ mod perft {
         assert_eq!(perft(&startpos, &zobrist, 6), 71_179_139);
     }
 }
+
+/// Every position from the perft tests, for tests that need a broad sample of positions.
+#[cfg(test)]
+const PERFT_FENS: [&str; 125] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+    "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1",
+    "4k2r/8/8/8/8/8/8/4K3 w k - 0 1",
+    "r3k3/8/8/8/8/8/8/4K3 w q - 0 1",
+    "4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1",
+    "r3k2r/8/8/8/8/8/8/4K3 w kq - 0 1",
+    "8/8/8/8/8/8/6k1/4K2R w K - 0 1",
+    "8/8/8/8/8/8/1k6/R3K3 w Q - 0 1",
+    "4k2r/6K1/8/8/8/8/8/8 w k - 0 1",
+    "r3k3/1K6/8/8/8/8/8/8 w q - 0 1",
+    "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/1R2K2R w Kkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/2R1K2R w Kkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/R3K1R1 w Qkq - 0 1",
+    "1r2k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1",
+    "2r1k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1",
+    "r3k1r1/8/8/8/8/8/8/R3K2R w KQq - 0 1",
+    "4k3/8/8/8/8/8/8/4K2R b K - 0 1",
+    "4k3/8/8/8/8/8/8/R3K3 b Q - 0 1",
+    "4k2r/8/8/8/8/8/8/4K3 b k - 0 1",
+    "r3k3/8/8/8/8/8/8/4K3 b q - 0 1",
+    "4k3/8/8/8/8/8/8/R3K2R b KQ - 0 1",
+    "r3k2r/8/8/8/8/8/8/4K3 b kq - 0 1",
+    "8/8/8/8/8/8/6k1/4K2R b K - 0 1",
+    "8/8/8/8/8/8/1k6/R3K3 b Q - 0 1",
+    "4k2r/6K1/8/8/8/8/8/8 b k - 0 1",
+    "r3k3/1K6/8/8/8/8/8/8 b q - 0 1",
+    "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/1R2K2R b Kkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/2R1K2R b Kkq - 0 1",
+    "r3k2r/8/8/8/8/8/8/R3K1R1 b Qkq - 0 1",
+    "1r2k2r/8/8/8/8/8/8/R3K2R b KQk - 0 1",
+    "2r1k2r/8/8/8/8/8/8/R3K2R b KQk - 0 1",
+    "r3k1r1/8/8/8/8/8/8/R3K2R b KQq - 0 1",
+    "8/1n4N1/2k5/8/8/5K2/1N4n1/8 w - - 0 1",
+    "8/1k6/8/5N2/8/4n3/8/2K5 w - - 0 1",
+    "8/8/4k3/3Nn3/3nN3/4K3/8/8 w - - 0 1",
+    "K7/8/2n5/1n6/8/8/8/k6N w - - 0 1",
+    "k7/8/2N5/1N6/8/8/8/K6n w - - 0 1",
+    "8/1n4N1/2k5/8/8/5K2/1N4n1/8 b - - 0 1",
+    "8/1k6/8/5N2/8/4n3/8/2K5 b - - 0 1",
+    "8/8/3K4/3Nn3/3nN3/4k3/8/8 b - - 0 1",
+    "K7/8/2n5/1n6/8/8/8/k6N b - - 0 1",
+    "k7/8/2N5/1N6/8/8/8/K6n b - - 0 1",
+    "B6b/8/8/8/2K5/4k3/8/b6B w - - 0 1",
+    "8/8/1B6/7b/7k/8/2B1b3/7K w - - 0 1",
+    "k7/B7/1B6/1B6/8/8/8/K6b w - - 0 1",
+    "K7/b7/1b6/1b6/8/8/8/k6B w - - 0 1",
+    "B6b/8/8/8/2K5/5k2/8/b6B b - - 0 1",
+    "8/8/1B6/7b/7k/8/2B1b3/7K b - - 0 1",
+    "k7/B7/1B6/1B6/8/8/8/K6b b - - 0 1",
+    "K7/b7/1b6/1b6/8/8/8/k6B b - - 0 1",
+    "7k/RR6/8/8/8/8/rr6/7K w - - 0 1",
+    "R6r/8/8/2K5/5k2/8/8/r6R w - - 0 1",
+    "7k/RR6/8/8/8/8/rr6/7K b - - 0 1",
+    "R6r/8/8/2K5/5k2/8/8/r6R b - - 0 1",
+    "6kq/8/8/8/8/8/8/7K w - - 0 1",
+    "6KQ/8/8/8/8/8/8/7k b - - 0 1",
+    "K7/8/8/3Q4/4q3/8/8/7k w - - 0 1",
+    "6qk/8/8/8/8/8/8/7K b - - 0 1",
+    "K7/8/8/3Q4/4q3/8/8/7k b - - 0 1",
+    "8/8/8/8/8/K7/P7/k7 w - - 0 1",
+    "8/8/8/8/8/7K/7P/7k w - - 0 1",
+    "K7/p7/k7/8/8/8/8/8 w - - 0 1",
+    "7K/7p/7k/8/8/8/8/8 w - - 0 1",
+    "8/2k1p3/3pP3/3P2K1/8/8/8/8 w - - 0 1",
+    "8/8/8/8/8/K7/P7/k7 b - - 0 1",
+    "8/8/8/8/8/7K/7P/7k b - - 0 1",
+    "K7/p7/k7/8/8/8/8/8 b - - 0 1",
+    "7K/7p/7k/8/8/8/8/8 b - - 0 1",
+    "8/2k1p3/3pP3/3P2K1/8/8/8/8 b - - 0 1",
+    "8/8/8/8/8/4k3/4P3/4K3 w - - 0 1",
+    "4k3/4p3/4K3/8/8/8/8/8 b - - 0 1",
+    "8/8/7k/7p/7P/7K/8/8 w - - 0 1",
+    "8/8/k7/p7/P7/K7/8/8 w - - 0 1",
+    "8/8/3k4/3p4/3P4/3K4/8/8 w - - 0 1",
+    "8/3k4/3p4/8/3P4/3K4/8/8 w - - 0 1",
+    "8/8/3k4/3p4/8/3P4/3K4/8 w - - 0 1",
+    "k7/8/3p4/8/3P4/8/8/7K w - - 0 1",
+    "8/8/7k/7p/7P/7K/8/8 b - - 0 1",
+    "8/8/k7/p7/P7/K7/8/8 b - - 0 1",
+    "8/8/3k4/3p4/3P4/3K4/8/8 b - - 0 1",
+    "8/3k4/3p4/8/3P4/3K4/8/8 b - - 0 1",
+    "8/8/3k4/3p4/8/3P4/3K4/8 b - - 0 1",
+    "k7/8/3p4/8/3P4/8/8/7K b - - 0 1",
+    "7k/3p4/8/8/3P4/8/8/K7 w - - 0 1",
+    "7k/8/8/3p4/8/8/3P4/K7 w - - 0 1",
+    "k7/8/8/7p/6P1/8/8/K7 w - - 0 1",
+    "k7/8/7p/8/8/6P1/8/K7 w - - 0 1",
+    "k7/8/8/6p1/7P/8/8/K7 w - - 0 1",
+    "k7/8/6p1/8/8/7P/8/K7 w - - 0 1",
+    "k7/8/8/3p4/4p3/8/8/7K w - - 0 1",
+    "k7/8/3p4/8/8/4P3/8/7K w - - 0 1",
+    "7k/3p4/8/8/3P4/8/8/K7 b - - 0 1",
+    "7k/8/8/3p4/8/8/3P4/K7 b - - 0 1",
+    "k7/8/8/7p/6P1/8/8/K7 b - - 0 1",
+    "k7/8/7p/8/8/6P1/8/K7 b - - 0 1",
+    "k7/8/8/6p1/7P/8/8/K7 b - - 0 1",
+    "k7/8/6p1/8/8/7P/8/K7 b - - 0 1",
+    "k7/8/8/3p4/4p3/8/8/7K b - - 0 1",
+    "k7/8/3p4/8/8/4P3/8/7K b - - 0 1",
+    "7k/8/8/p7/1P6/8/8/7K w - - 0 1",
+    "7k/8/p7/8/8/1P6/8/7K w - - 0 1",
+    "7k/8/8/1p6/P7/8/8/7K w - - 0 1",
+    "7k/8/1p6/8/8/P7/8/7K w - - 0 1",
+    "k7/7p/8/8/8/8/6P1/K7 w - - 0 1",
+    "k7/6p1/8/8/8/8/7P/K7 w - - 0 1",
+    "3k4/3pp3/8/8/8/8/3PP3/3K4 w - - 0 1",
+    "7k/8/8/p7/1P6/8/8/7K b - - 0 1",
+    "7k/8/p7/8/8/1P6/8/7K b - - 0 1",
+    "7k/8/8/1p6/P7/8/8/7K b - - 0 1",
+    "7k/8/1p6/8/8/P7/8/7K b - - 0 1",
+    "k7/7p/8/8/8/8/6P1/K7 b - - 0 1",
+    "k7/6p1/8/8/8/8/7P/K7 b - - 0 1",
+    "3k4/3pp3/8/8/8/8/3PP3/3K4 b - - 0 1",
+    "8/Pk6/8/8/8/8/6Kp/8 w - - 0 1",
+    "n1n5/1Pk5/8/8/8/8/5Kp1/5N1N w - - 0 1",
+    "8/PPPk4/8/8/8/8/4Kppp/8 w - - 0 1",
+    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1",
+    "8/Pk6/8/8/8/8/6Kp/8 b - - 0 1",
+    "n1n5/1Pk5/8/8/8/8/5Kp1/5N1N b - - 0 1",
+    "8/PPPk4/8/8/8/8/4Kppp/8 b - - 0 1",
+    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+];
+
+#[cfg(test)]
+mod fen {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tinyvec::ArrayVec;
+
+    use crate::{square::Rank, Board, Colour, FenError, FenField, Move, Zobrist, PERFT_FENS};
+
+    #[test]
+    fn perft_positions_round_trip() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            assert_eq!(board.to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn child_positions_round_trip() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            board.generate(&mut moves);
+            for m in moves {
+                let child = board.make(m, &zobrist);
+                let child_fen = child.to_fen();
+                let parsed = Board::from_fen(&child_fen, &zobrist).unwrap();
+                assert_eq!(parsed.to_fen(), child_fen, "{fen} {m}");
+                assert_eq!(parsed.hash(), child.hash(), "{fen} {m}");
+            }
+        }
+    }
+
+    #[test]
+    fn move_counters() {
+        let zobrist = Zobrist::new();
+        let board = Board::startpos(&zobrist);
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        board.generate(&mut moves);
+        let e4 = moves.into_iter().find(|m| m.to_string() == "e2e4").unwrap();
+        let nf3 = moves.into_iter().find(|m| m.to_string() == "g1f3").unwrap();
+
+        let board = board.make(e4, &zobrist);
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        let board = board.make_null(&zobrist);
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2");
+
+        let board = Board::startpos(&zobrist).make(nf3, &zobrist);
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+    }
+
+    #[test]
+    fn missing_counters_default() {
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen("8/8/8/8/8/6k1/6p1/6K1 w - -", &zobrist).unwrap();
+        assert_eq!(board.to_fen(), "8/8/8/8/8/6k1/6p1/6K1 w - - 0 1");
+    }
+
+    #[test]
+    fn malformed_fields() {
+        let zobrist = Zobrist::new();
+        let err = |fen: &str| Board::from_fen(fen, &zobrist).map(|_| fen).unwrap_err();
+        assert_eq!(err(""), FenError::MissingField(FenField::Placement));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3"), FenError::MissingField(FenField::Side));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w"), FenError::MissingField(FenField::Castling));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w -"), FenError::MissingField(FenField::EnPassant));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w - e"), FenError::MissingField(FenField::EnPassant));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w - - 0 1 x"), FenError::TrailingData(30));
+        assert_eq!(
+            err("4k3/8/8/8/8/8/8/4X3 w - - 0 1"),
+            FenError::InvalidChar { field: FenField::Placement, index: 17, byte: b'X' }
+        );
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 x - - 0 1"), FenError::InvalidChar { field: FenField::Side, index: 20, byte: b'x' });
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 wb - - 0 1"), FenError::InvalidChar { field: FenField::Side, index: 21, byte: b'b' });
+        assert_eq!(
+            err("4k3/8/8/8/8/8/8/4K2R w KK - 0 1"),
+            FenError::InvalidChar { field: FenField::Castling, index: 24, byte: b'K' }
+        );
+        assert_eq!(
+            err("4k3/8/8/8/8/8/8/4K3 w - i3 0 1"),
+            FenError::InvalidChar { field: FenField::EnPassant, index: 24, byte: b'i' }
+        );
+        assert_eq!(
+            err("4k3/8/8/8/8/8/8/4K3 w - - -1 1"),
+            FenError::InvalidChar { field: FenField::Halfmove, index: 26, byte: b'-' }
+        );
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w - - 0 70000"), FenError::CounterOutOfRange(FenField::Fullmove));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K4 w - - 0 1"), FenError::BadRankLength(Rank::One));
+        assert_eq!(
+            err("4k3/9/8/8/8/8/8/4K3 w - - 0 1"),
+            FenError::InvalidChar { field: FenField::Placement, index: 4, byte: b'9' }
+        );
+        assert_eq!(err("4k3/7/8/8/8/8/8/4K3 w - - 0 1"), FenError::BadRankLength(Rank::Seven));
+        assert_eq!(err("4k3/8/8/8/8/8/4K3 w - - 0 1"), FenError::BadRankCount);
+        assert_eq!(err("4k3/8/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::BadRankCount);
+    }
+
+    #[test]
+    fn illegal_positions() {
+        let zobrist = Zobrist::new();
+        let err = |fen: &str| Board::from_fen(fen, &zobrist).map(|_| fen).unwrap_err();
+        assert_eq!(err("8/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::KingCount(Colour::Black));
+        assert_eq!(err("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"), FenError::KingCount(Colour::White));
+        assert_eq!(err("4k3/8/8/8/8/8/PPPPPPPP/PK6 w - - 0 1"), FenError::TooManyPieces(Colour::White));
+        assert_eq!(err("4k3/8/8/8/8/8/8/QQQQQQQQ w - - 0 1").to_string(), "White does not have exactly one king");
+        assert_eq!(err("qqqqqqqq/qqqqqqqq/q7/8/8/8/8/4K3 w - - 0 1"), FenError::TooManyPieces(Colour::Black));
+        assert_eq!(err("4k2P/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::PawnOnBackRank("h8".parse().unwrap()));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1"), FenError::CastlingWithoutPieces('Q'));
+        assert_eq!(err("4k3/8/8/8/8/8/8/3K3R w K - 0 1"), FenError::CastlingWithoutPieces('K'));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K2r w K - 0 1"), FenError::CastlingWithoutPieces('K'));
+        assert_eq!(err("4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1"), FenError::InvalidEnPassant("e3".parse().unwrap()));
+        assert_eq!(err("4k3/8/8/8/4P3/8/8/4K3 b - e4 0 1"), FenError::InvalidEnPassant("e4".parse().unwrap()));
+        assert_eq!(err("4k3/8/8/8/8/8/8/4K3 b - e3 0 1"), FenError::InvalidEnPassant("e3".parse().unwrap()));
+        assert_eq!(err("4k3/8/8/8/B7/8/8/4K3 w - - 0 1").to_string(), "the side not to move is in check");
+        assert_eq!(err("4k3/8/8/8/8/8/4R3/4K3 w - - 0 1"), FenError::OpponentInCheck);
+        assert_eq!(err("4k3/8/3N1N2/8/8/8/4R3/4K3 b - - 0 1"), FenError::TooManyCheckers);
+
+        assert!(Board::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1", &zobrist).is_ok());
+        assert!(Board::from_fen("4k3/8/5N2/8/8/8/4R3/4K3 b - - 0 1", &zobrist).is_ok());
+    }
+
+    fn check_no_panic(fen: &[u8], zobrist: &Zobrist) {
+        if let Ok(board) = Board::from_fen_bytes(fen, zobrist) {
+            let reparsed = Board::from_fen(&board.to_fen(), zobrist).unwrap();
+            assert_eq!(reparsed.to_fen(), board.to_fen());
+            assert_eq!(reparsed.hash(), board.hash());
+        }
+    }
+
+    #[test]
+    fn truncated_fens_do_not_panic() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            for len in 0..fen.len() {
+                check_no_panic(&fen.as_bytes()[..len], &zobrist);
+            }
+        }
+    }
+
+    #[test]
+    fn mutated_fens_do_not_panic() {
+        const ALPHABET: &[u8] = b"KQRBNPkqrbnp12345678/ wb-abcdefgh09x\0";
+        let zobrist = Zobrist::new();
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+        for fen in PERFT_FENS {
+            for _ in 0..200 {
+                let mut fen = fen.as_bytes().to_vec();
+                for _ in 0..rng.gen_range(1..4) {
+                    let index = rng.gen_range(0..fen.len());
+                    match rng.gen_range(0..3) {
+                        0 => fen[index] = ALPHABET[rng.gen_range(0..ALPHABET.len())],
+                        1 => fen.insert(index, ALPHABET[rng.gen_range(0..ALPHABET.len())]),
+                        _ => {
+                            fen.remove(index);
+                        }
+                    }
+                }
+                check_no_panic(&fen, &zobrist);
+            }
+        }
+    }
+
+    #[test]
+    fn random_bytes_do_not_panic() {
+        let zobrist = Zobrist::new();
+        let mut rng = StdRng::seed_from_u64(0xF3E1);
+        for _ in 0..20000 {
+            let len = rng.gen_range(0..80);
+            let fen = (0..len).map(|_| rng.gen::<u8>()).collect::<Vec<_>>();
+            check_no_panic(&fen, &zobrist);
+        }
+    }
+}
+
+#[cfg(test)]
+mod san {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, ParseMoveError, Zobrist, PERFT_FENS};
+
+    fn san(fen: &str, uci: &str) -> String {
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen(fen, &zobrist).unwrap();
+        let m = board.parse_uci_move(uci).unwrap();
+        board.move_to_san(m, &zobrist)
+    }
+
+    #[test]
+    fn perft_moves_round_trip() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            board.generate(&mut moves);
+            for m in moves {
+                let san = board.move_to_san(m, &zobrist);
+                assert_eq!(board.parse_san(&san), Ok(m), "{fen} {san}");
+                assert_eq!(board.parse_uci_move(&m.to_string()), Ok(m), "{fen} {m}");
+            }
+        }
+    }
+
+    #[test]
+    fn move_to_san() {
+        let startpos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(startpos, "e2e4"), "e4");
+        assert_eq!(san(startpos, "g1f3"), "Nf3");
+        assert_eq!(san("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a5a3"), "R5a3");
+        assert_eq!(san("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "a1b2"), "Qa1b2");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"), "O-O-O");
+        assert_eq!(san("5k2/8/8/8/8/8/8/4K2R w K - 0 1", "e1g1"), "O-O+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8#");
+        assert_eq!(san("8/P6k/8/8/8/8/8/4K3 w - - 0 1", "a7a8n"), "a8=N");
+        assert_eq!(san("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), "axb8=Q+");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("r3k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Rxa8+");
+    }
+
+    #[test]
+    fn parse_san() {
+        let zobrist = Zobrist::new();
+        let board = Board::startpos(&zobrist);
+        assert_eq!(board.parse_san("Nf3").unwrap().to_string(), "g1f3");
+        assert_eq!(board.parse_san("e4!?").unwrap().to_string(), "e2e4");
+        assert_eq!(board.parse_san("e5"), Err(ParseMoveError::Illegal));
+        assert_eq!(board.parse_san("Nd2"), Err(ParseMoveError::Illegal));
+        assert_eq!(board.parse_san("O-O"), Err(ParseMoveError::Illegal));
+        assert_eq!(board.parse_san(""), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_san("Zz9"), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_san("Nf3=Q"), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_san("abcde4"), Err(ParseMoveError::Malformed));
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", &zobrist).unwrap();
+        assert_eq!(board.parse_san("Rd1"), Err(ParseMoveError::Ambiguous));
+        assert_eq!(board.parse_san("Rhd1").unwrap().to_string(), "h1d1");
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", &zobrist).unwrap();
+        assert_eq!(board.parse_san("0-0").unwrap().to_string(), "e1g1");
+        assert_eq!(board.parse_san("O-O-O").unwrap().to_string(), "e1c1");
+
+        let board = Board::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", &zobrist).unwrap();
+        assert_eq!(board.parse_san("axb8Q").unwrap().to_string(), "a7b8q");
+        assert_eq!(board.parse_san("a8=R").unwrap().to_string(), "a7a8r");
+        assert_eq!(board.parse_san("a8"), Err(ParseMoveError::Illegal));
+    }
+
+    #[test]
+    fn parse_uci_move() {
+        let zobrist = Zobrist::new();
+        let board = Board::startpos(&zobrist);
+        assert_eq!(board.parse_uci_move("e2e4").unwrap().to_string(), "e2e4");
+        assert_eq!(board.parse_uci_move("e2e5"), Err(ParseMoveError::Illegal));
+        assert_eq!(board.parse_uci_move("e2e4q"), Err(ParseMoveError::Illegal));
+        assert_eq!(board.parse_uci_move("e2"), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_uci_move("e2e4x"), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_uci_move("i2e4"), Err(ParseMoveError::Malformed));
+        assert_eq!(board.parse_uci_move("e2e4qq"), Err(ParseMoveError::Malformed));
+    }
+}
+
+#[cfg(test)]
+mod pgn_io {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, GameResult, Move, ParseMoveError, PgnError, PgnGame, PgnReader, Zobrist};
+
+    fn read(text: &str) -> Vec<Result<PgnGame, PgnError>> {
+        let zobrist = Zobrist::new();
+        PgnReader::new(text.as_bytes(), &zobrist).collect()
+    }
+
+    fn moves(game: &PgnGame) -> Vec<String> {
+        game.moves.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn reads_tags_comments_nags_and_variations() {
+        let text = r#"[Event "Test \"quoted\" \\ event"]
+[Site "?"]
+[Result "1-0"]
+
+% This line is escaped 1. e4
+1. e4 {An opening
+spanning lines} e5 $1 2. Nf3!? (2. f4 exf4 (2... d5) 3. Nf3) 2... Nc6 ; rest of line d4
+3. Bb5 1-0
+
+[Event "Second"]
+
+1. d4 d5 1/2-1/2
+"#;
+        let games = read(text);
+        assert_eq!(games.len(), 2);
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("Test \"quoted\" \\ event"));
+        assert_eq!(game.tag("Site"), Some("?"));
+        assert_eq!(moves(game), ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"]);
+        assert_eq!(game.result, GameResult::WhiteWins);
+
+        let game = games[1].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("Second"));
+        assert_eq!(moves(game), ["d2d4", "d7d5"]);
+        assert_eq!(game.result, GameResult::Draw);
+    }
+
+    #[test]
+    fn reads_games_from_fen() {
+        let text = r#"[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 10"]
+
+10... Kd7 11. e4 *
+"#;
+        let games = read(text);
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.start.to_fen(), "4k3/8/8/8/8/8/4P3/4K3 b - - 0 10");
+        assert_eq!(moves(game), ["e8d7", "e2e4"]);
+        assert_eq!(game.result, GameResult::Unknown);
+        assert_eq!(game.end(&Zobrist::new()).to_fen(), "8/3k4/8/8/4P3/8/8/4K3 b - e3 0 11");
+    }
+
+    #[test]
+    fn recovers_from_bad_games() {
+        let text = r#"[Event "Illegal"]
+
+1. e4 e4 2. Nf3 (2. d4) 1-0
+
+[Event "Bad FEN"]
+[FEN "8/8/8/8/8/8/8/8 w - - 0 1"]
+
+1. e4 *
+
+[Event "Unbalanced"]
+
+1. e4 ) e5 0-1
+
+[Event "Unterminated"]
+
+1. e4 e5
+
+[Event "Fine"]
+
+1. c4 0-1
+
+[Event "Unterminated comment"]
+
+1. e4 {never closed
+"#;
+        let games = read(text);
+        assert_eq!(games.len(), 6);
+        assert!(matches!(&games[0], Err(PgnError::Move { line: 3, san, error: ParseMoveError::Illegal }) if san == "e4"));
+        assert!(matches!(games[1], Err(PgnError::Fen { .. })));
+        assert!(matches!(games[2], Err(PgnError::Syntax { line: 12, .. })));
+        let game = games[3].as_ref().unwrap();
+        assert_eq!(moves(game), ["e2e4", "e7e5"]);
+        assert_eq!(game.result, GameResult::Unknown);
+        let game = games[4].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("Fine"));
+        assert_eq!(moves(game), ["c2c4"]);
+        assert_eq!(game.result, GameResult::BlackWins);
+        assert!(matches!(games[5], Err(PgnError::Syntax { line: 24, message: "unterminated comment" })));
+    }
+
+    #[test]
+    fn writes_export_format() {
+        let zobrist = Zobrist::new();
+        let mut game = PgnGame::new(Board::startpos(&zobrist), Vec::new(), GameResult::WhiteWins);
+        for san in ["f3", "e5", "g4", "Qh4"] {
+            let board = game.end(&zobrist);
+            game.moves.push(board.parse_san(san).unwrap());
+        }
+        game.result = GameResult::BlackWins;
+        game.set_tag("White", "Fool");
+        game.set_tag("Annotator", "Yukari");
+        game.set_tag("Result", "1-0");
+
+        let mut out = Vec::new();
+        game.write(&mut out, &zobrist).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"[Event "?"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "Fool"]
+[Black "?"]
+[Result "0-1"]
+[Annotator "Yukari"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+
+"#
+        );
+    }
+
+    #[test]
+    fn random_games_round_trip() {
+        let zobrist = Zobrist::new();
+        let mut rng = StdRng::seed_from_u64(0x6A3E);
+        let starts = [
+            Board::startpos(&zobrist),
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 3 17", &zobrist).unwrap(),
+        ];
+
+        let mut out = Vec::new();
+        let mut written = Vec::new();
+        for i in 0..50 {
+            let mut game = PgnGame::new(starts[i % 2].clone(), Vec::new(), GameResult::Draw);
+            let mut board = game.start.clone();
+            for _ in 0..200 {
+                let moves: [Move; 256] = [Move::default(); 256];
+                let mut moves = ArrayVec::from(moves);
+                moves.set_len(0);
+                board.generate(&mut moves);
+                let Some(&m) = moves.choose(&mut rng) else { break };
+                board = board.make(m, &zobrist);
+                game.moves.push(m);
+            }
+            game.set_tag("Round", &i.to_string());
+            game.write(&mut out, &zobrist).unwrap();
+            written.push(game);
+        }
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.lines().all(|line| line.len() < 80));
+        let read = PgnReader::new(text.as_bytes(), &zobrist).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(&written) {
+            assert_eq!(read.start.to_fen(), written.start.to_fen());
+            assert_eq!(read.moves, written.moves);
+            assert_eq!(read.result, written.result);
+            assert_eq!(read.tag("Round"), written.tag("Round"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod see {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, Zobrist, PERFT_FENS};
+
+    fn see(fen: &str, uci: &str) -> i32 {
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen(fen, &zobrist).unwrap();
+        let m = board.parse_uci_move(uci).unwrap();
+        assert!(board.see_ge(m, board.see(m)), "{fen} {uci}");
+        assert!(!board.see_ge(m, board.see(m) + 1), "{fen} {uci}");
+        board.see(m)
+    }
+
+    #[test]
+    fn exchanges() {
+        // Winning an undefended pawn.
+        assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 100);
+        // Knight for pawn, after a long sequence of recaptures including x-rays on both sides.
+        assert_eq!(see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"), -200);
+        // The rook behind the capturer makes the recapture lose.
+        assert_eq!(see("4k3/4r3/8/4p3/8/8/4R3/4R2K w - - 0 1", "e2e5"), 100);
+        assert_eq!(see("4k3/4r3/8/4p3/8/8/4R3/7K w - - 0 1", "e2e5"), -400);
+        // Moving a rook to a square a pawn attacks.
+        assert_eq!(see("4k3/8/8/3p4/8/8/8/2R1K3 w - - 0 1", "c1c4"), -500);
+        // Promotions count the promoted piece.
+        assert_eq!(see("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), 800);
+        assert_eq!(see("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), -100);
+        assert_eq!(see("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8q"), 1300);
+        // A king only recaptures on an undefended square.
+        assert_eq!(see("8/8/8/8/3k4/4p3/8/4RK2 w - - 0 1", "e1e3"), -400);
+        assert_eq!(see("8/8/8/8/3k4/4p3/5P2/4RK2 w - - 0 1", "e1e3"), 100);
+        // En passant captures a pawn that is not on the destination square.
+        assert_eq!(see("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1", "d5e6"), 100);
+        // Castling never exchanges anything.
+        assert_eq!(see("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"), 0);
+    }
+
+    #[test]
+    fn see_ge_agrees_with_see() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            board.generate(&mut moves);
+            for m in moves {
+                let see = board.see(m);
+                for threshold in (-1000..=1000).step_by(50).chain([see - 1, see, see + 1]) {
+                    assert_eq!(board.see_ge(m, threshold), see >= threshold, "{fen} {m} {threshold}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod keys {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, Zobrist, PERFT_FENS};
+
+    fn check_fresh(board: &Board, zobrist: &Zobrist) {
+        let fresh = Board::from_fen(&board.to_fen(), zobrist).unwrap();
+        assert_eq!(board.hash(), fresh.hash(), "{}", board.fen());
+        assert_eq!(board.pawn_key(), fresh.pawn_key(), "{}", board.fen());
+        assert_eq!(board.material_key(), fresh.material_key(), "{}", board.fen());
+    }
+
+    fn check_keys(board: &Board, zobrist: &Zobrist, depth: u32) {
+        check_fresh(board, zobrist);
+        if !board.in_check() {
+            check_fresh(&board.make_null(zobrist), zobrist);
+        }
+        if depth == 0 {
+            return;
+        }
+
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        board.generate(&mut moves);
+        for m in moves {
+            check_keys(&board.make(m, zobrist), zobrist, depth - 1);
+        }
+    }
+
+    #[test]
+    fn incremental_keys_match_fresh_boards() {
+        for zobrist in [Zobrist::new(), Zobrist::polyglot()] {
+            for fen in PERFT_FENS {
+                check_keys(&Board::from_fen(fen, &zobrist).unwrap(), &zobrist, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn null_move_clears_capturable_ep() {
+        for zobrist in [Zobrist::new(), Zobrist::polyglot()] {
+            let board = Board::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1", &zobrist).unwrap();
+            check_fresh(&board.make_null(&zobrist), &zobrist);
+        }
+    }
+
+    #[test]
+    fn key_contents() {
+        let zobrist = Zobrist::new();
+        let board = |fen| Board::from_fen(fen, &zobrist).unwrap();
+
+        // Piece moves leave the pawn and material keys alone.
+        let start = board("4k3/pp6/8/8/8/8/PP6/4K1N1 w - - 0 1");
+        let knight_moved = board("4k3/pp6/8/8/8/5N2/PP6/4K3 b - - 1 1");
+        assert_ne!(start.hash(), knight_moved.hash());
+        assert_eq!(start.pawn_key(), knight_moved.pawn_key());
+        assert_eq!(start.material_key(), knight_moved.material_key());
+
+        // Pawn moves change the pawn key, but not the material key.
+        let pawn_moved = board("4k3/pp6/8/8/8/P7/1P6/4K1N1 b - - 0 1");
+        assert_ne!(start.pawn_key(), pawn_moved.pawn_key());
+        assert_eq!(start.material_key(), pawn_moved.material_key());
+
+        // The material key tells apart which side owns a piece, and how many there are.
+        let black_knight = board("4k1n1/pp6/8/8/8/8/PP6/4K3 w - - 0 1");
+        let two_knights = board("4k3/pp6/8/8/8/8/PP6/4KNN1 w - - 0 1");
+        assert_eq!(start.pawn_key(), black_knight.pawn_key());
+        assert_ne!(start.material_key(), black_knight.material_key());
+        assert_ne!(start.material_key(), two_knights.material_key());
+
+        // Bare kings have no pawns.
+        assert_eq!(board("4k3/8/8/8/8/8/8/4K3 w - - 0 1").pawn_key(), 0);
+    }
+}
+
+#[cfg(test)]
+mod staged_generation {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, Zobrist, PERFT_FENS};
+
+    fn move_list() -> ArrayVec<[Move; 256]> {
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        moves
+    }
+
+    fn check_stages(board: &Board, zobrist: &Zobrist, depth: u32) {
+        let mut moves = move_list();
+        let mut captures = move_list();
+        let mut quiets = move_list();
+        board.generate(&mut moves);
+        board.generate_captures(&mut captures);
+        board.generate_quiets(&mut quiets);
+
+        let expected_captures = moves.iter().filter(|m| m.is_capture()).copied().collect::<ArrayVec<[Move; 256]>>();
+        let expected_quiets = moves.iter().filter(|m| !m.is_capture()).copied().collect::<ArrayVec<[Move; 256]>>();
+        assert!(captures == expected_captures, "captures of {}", board.to_fen());
+        assert!(quiets == expected_quiets, "quiets of {}", board.to_fen());
+
+        if depth > 0 {
+            for m in moves {
+                check_stages(&board.make(m, zobrist), zobrist, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn captures_and_quiets_partition_moves() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            check_stages(&Board::from_fen(fen, &zobrist).unwrap(), &zobrist, 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod legality {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, MoveType, Piece, Square, Zobrist, PERFT_FENS};
+
+    /// Every move shape between every pair of squares, including promotions to pieces that cannot be promoted to.
+    fn candidates() -> Vec<Move> {
+        let kinds = [MoveType::Normal, MoveType::Capture, MoveType::Castle, MoveType::DoublePush, MoveType::EnPassant];
+        let promotion_kinds = [MoveType::Promotion, MoveType::CapturePromotion];
+        let pieces = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+
+        let mut moves = Vec::new();
+        for from in 0..64 {
+            for dest in 0..64 {
+                let from = Square::try_from(from).unwrap();
+                let dest = Square::try_from(dest).unwrap();
+                for kind in kinds {
+                    moves.push(Move::new(from, dest, kind, None));
+                    moves.push(Move::new(from, dest, kind, Some(Piece::Queen)));
+                }
+                for kind in promotion_kinds {
+                    moves.push(Move::new(from, dest, kind, None));
+                    for piece in pieces {
+                        moves.push(Move::new(from, dest, kind, Some(piece)));
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    fn check_legality(board: &Board, zobrist: &Zobrist, candidates: &[Move], depth: u32) {
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        board.generate(&mut moves);
+
+        for &m in candidates {
+            let legal = moves.contains(&m);
+            assert_eq!(board.is_legal(m), legal, "{} {m:?}", board.to_fen());
+            if legal {
+                assert!(board.is_pseudo_legal(m), "{} {m:?}", board.to_fen());
+            } else if board.is_pseudo_legal(m) && m.kind != MoveType::Castle {
+                // Pseudo-legal moves can be made, but leave the king in check.
+                assert!(board.make(m, zobrist).illegal(), "{} {m:?}", board.to_fen());
+            }
+        }
+
+        if depth > 0 {
+            for m in moves {
+                check_legality(&board.make(m, zobrist), zobrist, candidates, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn agrees_with_generate() {
+        let zobrist = Zobrist::new();
+        let candidates = candidates();
+        // The perft positions never block castling with a piece next to the queenside rook.
+        let blocked_rooks = ["r3k2r/8/8/8/8/8/8/RN2K2R w KQkq - 0 1", "rn2k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1"];
+        for fen in PERFT_FENS.into_iter().chain(blocked_rooks) {
+            check_legality(&Board::from_fen(fen, &zobrist).unwrap(), &zobrist, &candidates, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Move, Zobrist, PERFT_FENS};
+
+    fn move_list() -> ArrayVec<[Move; 256]> {
+        let moves: [Move; 256] = [Move::default(); 256];
+        let mut moves = ArrayVec::from(moves);
+        moves.set_len(0);
+        moves
+    }
+
+    fn check_checks(board: &Board, zobrist: &Zobrist, depth: u32) {
+        let mut moves = move_list();
+        let mut checks = move_list();
+        board.generate(&mut moves);
+        board.generate_checks(&mut checks);
+
+        for &m in &moves {
+            let in_check = board.make(m, zobrist).in_check();
+            assert_eq!(board.gives_check(m), in_check, "{} {m}", board.to_fen());
+            assert_eq!(checks.contains(&m), in_check && !m.is_capture(), "{} {m}", board.to_fen());
+        }
+
+        if depth > 0 {
+            for m in moves {
+                check_checks(&board.make(m, zobrist), zobrist, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn agrees_with_make() {
+        let zobrist = Zobrist::new();
+        // Castling into a check from the rook, including through the square the king left, and discovering a check by
+        // capturing en passant, through either pawn.
+        let extra = [
+            "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+            "2k5/8/8/8/8/8/8/R3K3 w Q - 0 1",
+            "8/8/8/8/8/8/8/R3K1k1 w Q - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4R1K1 w - d6 0 1",
+            "6k1/8/8/3pP3/8/8/B7/4K3 w - d6 0 1",
+        ];
+        for fen in PERFT_FENS.into_iter().chain(extra) {
+            check_checks(&Board::from_fen(fen, &zobrist).unwrap(), &zobrist, 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod evaluators {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tinyvec::ArrayVec;
+
+    use crate::{Board, Eval, EvalParams, Evaluator, Move, Network, Nnue, Zobrist, PERFT_FENS};
+
+    /// A network with small random weights, so that every feature affects the evaluation.
+    fn random_network() -> &'static Network {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let bytes = (0..Network::FILE_SIZE / 2).flat_map(|_| rng.gen_range(-64_i16..64).to_le_bytes()).collect::<Vec<_>>();
+        Box::leak(Box::new(Network::from_bytes(&bytes).unwrap()))
+    }
+
+    #[test]
+    fn network_size() {
+        assert!(Network::from_bytes(&vec![0; Network::FILE_SIZE - 2]).is_err());
+        assert!(Network::from_bytes(&vec![0; Network::FILE_SIZE]).is_ok());
+        // Padding to a multiple of 64 bytes is allowed, a network with a wider hidden layer is not.
+        assert!(Network::from_bytes(&vec![0; Network::FILE_SIZE + 63]).is_ok());
+        assert!(Network::from_bytes(&vec![0; Network::FILE_SIZE + 64]).is_err());
+        assert!(Network::from_bytes(&vec![0; Network::FILE_SIZE * 2]).is_err());
+    }
+
+    /// Flip the board vertically and swap the colours of the pieces.
+    fn mirror(fen: &str) -> String {
+        let fields = fen.split(' ').collect::<Vec<_>>();
+        let swap_case = |s: &str| s.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect::<String>();
+        let placement = fields[0].split('/').rev().map(swap_case).collect::<Vec<_>>().join("/");
+        let side = if fields[1] == "w" { "b" } else { "w" };
+        format!("{placement} {side} {} - 0 1", swap_case(fields[2]))
+    }
+
+    fn check_incremental<E: Evaluator>(board: &Board, zobrist: &Zobrist, evaluator: &E, depth: u32) {
+        let mut refreshed = evaluator.clone();
+        refreshed.refresh(board);
+        assert_eq!(evaluator.evaluate(board), refreshed.evaluate(board), "{}", board.to_fen());
+
+        if depth > 0 {
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            board.generate(&mut moves);
+            for m in moves {
+                let mut child = evaluator.clone();
+                child.make_move(board, m);
+                check_incremental(&board.make(m, zobrist), zobrist, &child, depth - 1);
+            }
+        }
+    }
+
+    fn check_symmetric<E: Evaluator>(evaluator: &E) {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            let mirrored = Board::from_fen(&mirror(fen), &zobrist).unwrap();
+            let (mut evaluator, mut mirrored_evaluator) = (evaluator.clone(), evaluator.clone());
+            evaluator.refresh(&board);
+            mirrored_evaluator.refresh(&mirrored);
+            assert_eq!(evaluator.evaluate(&board), mirrored_evaluator.evaluate(&mirrored), "{fen}");
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_refresh() {
+        let zobrist = Zobrist::new();
+        for fen in PERFT_FENS {
+            let board = Board::from_fen(fen, &zobrist).unwrap();
+            let mut eval = Eval::new();
+            eval.refresh(&board);
+            check_incremental(&board, &zobrist, &eval, 2);
+            let mut nnue = Nnue::new(random_network());
+            nnue.refresh(&board);
+            check_incremental(&board, &zobrist, &nnue, 2);
+        }
+    }
+
+    #[test]
+    fn perspectives_are_symmetric() {
+        check_symmetric(&Eval::new());
+        check_symmetric(&Nnue::new(random_network()));
+    }
+
+    #[test]
+    fn eval_params_round_trip() {
+        // The built-in tables are exactly what the tuner would write for them.
+        let source = include_str!("board/eval_tables.rs");
+        assert_eq!(EvalParams::DEFAULT.to_rust(), source);
+        assert_eq!(EvalParams::parse(source).unwrap(), EvalParams::DEFAULT);
+
+        let mut params = EvalParams::DEFAULT;
+        params.pst_eg[3][17] = -42;
+        params.mat_mg[4] = 1200;
+        assert_eq!(EvalParams::parse(&params.to_rust()).unwrap(), params);
+
+        assert!(EvalParams::parse("pub const MAT_MG: [i32; 6] = [1, 2, 3];").is_err());
+        assert!(EvalParams::parse(&source.replace("PHASE", "PHASES")).is_err());
+    }
+}
+
+#[cfg(test)]
+mod polyglot_book {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{Board, Book, BookBuilder, BookSelection, PgnReader, Zobrist};
+
+    /// Positions and keys from the Polyglot specification, reached by playing the moves from the start.
+    const KEYS: [(&str, &str, u64); 9] = [
+        ("", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0x463b_9618_1691_fc9c),
+        ("e2e4", "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", 0x823c_9b50_fd11_4196),
+        ("e2e4 d7d5", "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2", 0x0756_b944_61c5_0fb0),
+        ("e2e4 d7d5 e4e5", "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2", 0x662f_afb9_65db_29d4),
+        ("e2e4 d7d5 e4e5 f7f5", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", 0x22a4_8b5a_8e47_ff78),
+        ("e2e4 d7d5 e4e5 f7f5 e1e2", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3", 0x652a_607c_a3f2_42c1),
+        (
+            "e2e4 d7d5 e4e5 f7f5 e1e2 e8f7",
+            "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+            0x00fd_d303_c946_bdd9,
+        ),
+        ("a2a4 b7b5 h2h4 b5b4 c2c4", "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3", 0x3c81_23ea_7b06_7637),
+        (
+            "a2a4 b7b5 h2h4 b5b4 c2c4 b4c3 a1a3",
+            "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+            0x5c3f_9b82_9b27_9560,
+        ),
+    ];
+
+    #[test]
+    fn polyglot_keys() {
+        let zobrist = Zobrist::polyglot();
+        for (moves, fen, key) in KEYS {
+            assert_eq!(Board::from_fen(fen, &zobrist).unwrap().hash(), key, "{fen}");
+
+            let mut board = Board::startpos(&zobrist);
+            for m in moves.split_whitespace() {
+                board = board.make(board.parse_uci_move(m).unwrap(), &zobrist);
+            }
+            assert_eq!(board.hash(), key, "{moves}");
+        }
+    }
+
+    const GAMES: &str = "\
+[Result \"1-0\"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. O-O 1-0
+
+[Result \"1/2-1/2\"]
+
+1. e4 e5 2. Nf3 Nf6 3. d4 1/2-1/2
+
+[Result \"0-1\"]
+
+1. e4 c5 2. Nf3 0-1
+
+[Result \"1-0\"]
+
+1. d4 d5 1-0
+";
+
+    fn build(max_ply: usize, min_games: u32) -> Book {
+        let zobrist = Zobrist::polyglot();
+        let mut builder = BookBuilder::new(&zobrist, max_ply, min_games);
+        for game in PgnReader::new(GAMES.as_bytes(), &zobrist) {
+            builder.add_game(&game.unwrap());
+        }
+        builder.build()
+    }
+
+    fn moves(book: &Book, board: &Board) -> Vec<(String, u16)> {
+        book.moves(board).into_iter().map(|(m, weight)| (m.to_string(), weight)).collect()
+    }
+
+    #[test]
+    fn build_and_read() {
+        let zobrist = Zobrist::polyglot();
+        let book = build(20, 1);
+
+        let mut bytes = Vec::new();
+        book.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 16 * book.len());
+        let book = Book::read(bytes.as_slice()).unwrap();
+        assert!(Book::read(&bytes[1..]).is_err());
+
+        // White scored a win, a draw and a loss with e4, and won with d4. Wins are worth two points and draws one.
+        let startpos = Board::startpos(&zobrist);
+        assert_eq!(moves(&book, &startpos), [("e2e4".to_string(), 3), ("d2d4".to_string(), 2)]);
+
+        // Black won its only game with c5, and scored a draw and a loss with e5.
+        let e4 = startpos.make(startpos.parse_uci_move("e2e4").unwrap(), &zobrist);
+        assert_eq!(moves(&book, &e4), [("c7c5".to_string(), 2), ("e7e5".to_string(), 1)]);
+
+        // Castling is stored as the king taking its own rook, and read back as castling.
+        let mut board = startpos;
+        for m in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6"] {
+            board = board.make(board.parse_uci_move(m).unwrap(), &zobrist);
+        }
+        assert_eq!(moves(&book, &board), [("e1g1".to_string(), 2)]);
+        assert_eq!(book.entries(board.hash())[0].mv & 0x3f, 7);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let e4e5 = e4.make(e4.parse_uci_move("e7e5").unwrap(), &zobrist);
+        for selection in [BookSelection::Best, BookSelection::Weighted, BookSelection::Random] {
+            assert_eq!(book.choose(&e4e5, selection, &mut rng).map(|m| m.to_string()), Some("g1f3".to_string()));
+        }
+        // A loss weighs nothing, and is never chosen.
+        let nf3 = e4e5.make(e4e5.parse_uci_move("g1f3").unwrap(), &zobrist);
+        assert_eq!(moves(&book, &nf3), [("g8f6".to_string(), 1), ("b8c6".to_string(), 0)]);
+        assert_eq!(book.choose(&nf3, BookSelection::Best, &mut rng).map(|m| m.to_string()), Some("g8f6".to_string()));
+        let c5 = e4.make(e4.parse_uci_move("c7c5").unwrap(), &zobrist);
+        assert_eq!(moves(&book, &c5), [("g1f3".to_string(), 0)]);
+        assert_eq!(book.choose(&c5, BookSelection::Weighted, &mut rng), None);
+    }
+
+    #[test]
+    fn filters() {
+        let zobrist = Zobrist::polyglot();
+        let startpos = Board::startpos(&zobrist);
+        let e4 = startpos.make(startpos.parse_uci_move("e2e4").unwrap(), &zobrist);
+
+        // Only the first ply of each game.
+        let book = build(1, 1);
+        assert_eq!(book.len(), 2);
+        assert!(book.moves(&e4).is_empty());
+
+        // Only moves played in at least two games.
+        let book = build(20, 2);
+        assert_eq!(moves(&book, &startpos), [("e2e4".to_string(), 3)]);
+        assert_eq!(moves(&book, &e4), [("e7e5".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    board::{Board, FenError, ParseMoveError, Zobrist},
+    chessmove::Move,
+    colour::Colour,
+};
+
+/// The FEN of the standard starting position, which games without a `FEN` tag begin from.
+const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// The tags every PGN game carries, in the order they are written.
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+/// The outcome of a game, as written at the end of its movetext.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game is unfinished or its result is not known.
+    #[default]
+    Unknown,
+}
+
+impl GameResult {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        match token {
+            b"1-0" => Some(Self::WhiteWins),
+            b"0-1" => Some(Self::BlackWins),
+            b"1/2-1/2" => Some(Self::Draw),
+            b"*" => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+}
+
+impl Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WhiteWins => write!(f, "1-0"),
+            Self::BlackWins => write!(f, "0-1"),
+            Self::Draw => write!(f, "1/2-1/2"),
+            Self::Unknown => write!(f, "*"),
+        }
+    }
+}
+
+/// An error from reading a PGN file.
+///
+/// Everything except `Io` only affects the game it occurred in, and the reader moves on to the next game.
+#[derive(Debug)]
+pub enum PgnError {
+    /// Reading from the underlying reader failed.
+    Io(io::Error),
+    /// The text on this line is not valid PGN.
+    Syntax { line: usize, message: &'static str },
+    /// The `FEN` tag of a game is invalid.
+    Fen { line: usize, error: FenError },
+    /// A move in the main line could not be played.
+    Move { line: usize, san: String, error: ParseMoveError },
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            Self::Fen { line, error } => write!(f, "line {line}: bad FEN tag: {error}"),
+            Self::Move { line, san, error } => write!(f, "line {line}: {error} {san}"),
+        }
+    }
+}
+
+impl Error for PgnError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Syntax { .. } => None,
+            Self::Fen { error, .. } => Some(error),
+            Self::Move { error, .. } => Some(error),
+        }
+    }
+}
+
+/// A game record: its tags, the position it started from, and the moves of its main line.
+#[derive(Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    /// Create a game with no tags.
+    #[must_use]
+    pub const fn new(start: Board, moves: Vec<Move>, result: GameResult) -> Self {
+        Self { tags: Vec::new(), start, moves, result }
+    }
+
+    /// Return the value of a tag, if the game has it.
+    #[must_use]
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Set the value of a tag, replacing any previous value.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Return the position at the end of the main line.
+    #[must_use]
+    pub fn end(&self, zobrist: &Zobrist) -> Board {
+        self.moves.iter().fold(self.start.clone(), |board, &m| board.make(m, zobrist))
+    }
+
+    /// Write this game in PGN export format.
+    ///
+    /// The seven tag roster comes first, with `?` for missing tags, and `SetUp` and `FEN` tags are added
+    /// when the game does not start from the standard starting position.
+    ///
+    /// # Errors
+    /// Returns an error if writing to `out` fails.
+    pub fn write<W: Write>(&self, out: &mut W, zobrist: &Zobrist) -> io::Result<()> {
+        for name in SEVEN_TAG_ROSTER {
+            let result = self.result.to_string();
+            let value = match name {
+                "Result" => &result,
+                "Date" => self.tag(name).unwrap_or("????.??.??"),
+                _ => self.tag(name).unwrap_or("?"),
+            };
+            write_tag(out, name, value)?;
+        }
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN" {
+                write_tag(out, name, value)?;
+            }
+        }
+        let fen = self.start.to_fen();
+        if fen != STARTPOS {
+            write_tag(out, "SetUp", "1")?;
+            write_tag(out, "FEN", &fen)?;
+        }
+        writeln!(out)?;
+
+        // Export format keeps lines under 80 characters.
+        let mut line = String::new();
+        let mut push = |out: &mut W, token: &str| {
+            if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+                writeln!(out, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(token);
+            io::Result::Ok(())
+        };
+
+        let mut board = self.start.clone();
+        for (i, &m) in self.moves.iter().enumerate() {
+            if board.side() == Colour::White {
+                push(out, &format!("{}.", board.fullmove_number()))?;
+            } else if i == 0 {
+                push(out, &format!("{}...", board.fullmove_number()))?;
+            }
+            push(out, &board.move_to_san(m, zobrist))?;
+            board = board.make(m, zobrist);
+        }
+        push(out, &self.result.to_string())?;
+        writeln!(out, "{line}")?;
+        writeln!(out)
+    }
+}
+
+/// Write a tag pair, escaping the value.
+fn write_tag<W: Write>(out: &mut W, name: &str, value: &str) -> io::Result<()> {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(out, "[{name} \"{value}\"]")
+}
+
+/// A lexical unit of PGN.
+enum Token {
+    Tag(String, String),
+    Comment,
+    Nag,
+    MoveNumber,
+    VariationStart,
+    VariationEnd,
+    Result(GameResult),
+    San(String),
+}
+
+/// Whether a byte can be part of a SAN move, move number or game result.
+const fn is_symbol(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'+' | b'#' | b'=' | b':' | b'-' | b'/' | b'!' | b'?')
+}
+
+/// A streaming reader of games from a PGN file.
+///
+/// Each game is yielded once its terminator is read. Games with syntax errors, bad `FEN` tags or illegal moves
+/// produce an error and the reader carries on with the next game; an I/O error ends the stream.
+pub struct PgnReader<'a, R> {
+    reader: R,
+    zobrist: &'a Zobrist,
+    line: Vec<u8>,
+    pos: usize,
+    line_number: usize,
+    /// A tag read while looking for the end of an unterminated game, which starts the next one.
+    next_tag: Option<(String, String)>,
+    failed: bool,
+}
+
+impl<'a, R: BufRead> PgnReader<'a, R> {
+    /// Create a reader of games from `reader`.
+    pub const fn new(reader: R, zobrist: &'a Zobrist) -> Self {
+        Self { reader, zobrist, line: Vec::new(), pos: 0, line_number: 0, next_tag: None, failed: false }
+    }
+
+    /// Read the next line into the buffer, returning false at the end of the input.
+    fn read_line(&mut self) -> Result<bool, PgnError> {
+        self.line.clear();
+        self.pos = 0;
+        if self.reader.read_until(b'\n', &mut self.line).map_err(PgnError::Io)? == 0 {
+            return Ok(false);
+        }
+        self.line_number += 1;
+        // Lines starting with a percent sign are escaped from PGN processing.
+        if self.line.first() == Some(&b'%') {
+            self.pos = self.line.len();
+        }
+        Ok(true)
+    }
+
+    const fn syntax(&self, message: &'static str) -> PgnError {
+        PgnError::Syntax { line: self.line_number, message }
+    }
+
+    /// Read the next token, or `None` at the end of the input.
+    fn next_token(&mut self) -> Result<Option<Token>, PgnError> {
+        loop {
+            let Some(&c) = self.line.get(self.pos) else {
+                if !self.read_line()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            self.pos += 1;
+            return Ok(Some(match c {
+                c if c.is_ascii_whitespace() || c == b'.' => continue,
+                b';' => {
+                    self.pos = self.line.len();
+                    Token::Comment
+                }
+                b'{' => {
+                    let line = self.line_number;
+                    while self.line.get(self.pos) != Some(&b'}') {
+                        if self.pos >= self.line.len() {
+                            if !self.read_line()? {
+                                return Err(PgnError::Syntax { line, message: "unterminated comment" });
+                            }
+                        } else {
+                            self.pos += 1;
+                        }
+                    }
+                    self.pos += 1;
+                    Token::Comment
+                }
+                b'[' => self.read_tag()?,
+                b'(' => Token::VariationStart,
+                b')' => Token::VariationEnd,
+                b'$' => {
+                    let digits = self.line[self.pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+                    if digits == 0 {
+                        return Err(self.syntax("expected a number after '$'"));
+                    }
+                    self.pos += digits;
+                    Token::Nag
+                }
+                b'*' => Token::Result(GameResult::Unknown),
+                c if is_symbol(c) => {
+                    let start = self.pos - 1;
+                    self.pos += self.line[self.pos..].iter().take_while(|&&c| is_symbol(c)).count();
+                    let token = &self.line[start..self.pos];
+                    if let Some(result) = GameResult::from_token(token) {
+                        return Ok(Some(Token::Result(result)));
+                    }
+                    if token.iter().all(u8::is_ascii_digit) {
+                        Token::MoveNumber
+                    } else if token.iter().all(|c| matches!(c, b'!' | b'?')) {
+                        Token::Nag
+                    } else {
+                        Token::San(String::from_utf8_lossy(token).into_owned())
+                    }
+                }
+                _ => return Err(self.syntax("unexpected character")),
+            }));
+        }
+    }
+
+    /// Read a tag pair after its opening bracket.
+    fn read_tag(&mut self) -> Result<Token, PgnError> {
+        let skip_whitespace = |this: &mut Self| {
+            this.pos += this.line[this.pos..].iter().take_while(|c| c.is_ascii_whitespace()).count();
+        };
+        let malformed = |this: &mut Self| {
+            this.pos = this.line.len();
+            this.syntax("malformed tag")
+        };
+
+        skip_whitespace(self);
+        let name_len = self.line[self.pos..].iter().take_while(|&&c| c.is_ascii_alphanumeric() || c == b'_').count();
+        if name_len == 0 {
+            return Err(malformed(self));
+        }
+        let name = String::from_utf8_lossy(&self.line[self.pos..self.pos + name_len]).into_owned();
+        self.pos += name_len;
+
+        skip_whitespace(self);
+        if self.line.get(self.pos) != Some(&b'"') {
+            return Err(malformed(self));
+        }
+        self.pos += 1;
+        let mut value = Vec::new();
+        loop {
+            match self.line.get(self.pos) {
+                Some(b'"') => break,
+                Some(b'\\') if matches!(self.line.get(self.pos + 1), Some(b'"' | b'\\')) => {
+                    value.push(self.line[self.pos + 1]);
+                    self.pos += 2;
+                }
+                Some(b'\n' | b'\r') | None => return Err(malformed(self)),
+                Some(&c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        self.pos += 1;
+
+        skip_whitespace(self);
+        if self.line.get(self.pos) != Some(&b']') {
+            return Err(malformed(self));
+        }
+        self.pos += 1;
+        Ok(Token::Tag(name, String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    /// Set up the starting position from the tags of a game.
+    fn start_position(&self, tags: &[(String, String)]) -> Result<Board, PgnError> {
+        match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Board::from_fen(fen, self.zobrist).map_err(|error| PgnError::Fen { line: self.line_number, error }),
+            None => Ok(Board::startpos(self.zobrist)),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<'_, R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let mut tags = Vec::new();
+        tags.extend(self.next_tag.take());
+        // The position and moves of the main line, once the movetext starts.
+        let mut game: Option<(Board, Board, Vec<Move>)> = None;
+        let mut result = None;
+        let mut depth = 0_u32;
+        let mut error = None;
+
+        loop {
+            let token = match self.next_token() {
+                Ok(Some(token)) => token,
+                Ok(None) => break,
+                Err(PgnError::Io(io)) => {
+                    self.failed = true;
+                    return Some(Err(PgnError::Io(io)));
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                    continue;
+                }
+            };
+
+            let movetext = match token {
+                Token::Tag(name, value) => {
+                    if game.is_some() {
+                        // This game had no terminator; the tag belongs to the next one.
+                        self.next_tag = Some((name, value));
+                        break;
+                    }
+                    tags.push((name, value));
+                    continue;
+                }
+                Token::Comment => continue,
+                token => token,
+            };
+
+            let (_, board, moves) = game.get_or_insert_with(|| {
+                let start = self.start_position(&tags).unwrap_or_else(|err| {
+                    error.get_or_insert(err);
+                    Board::startpos(self.zobrist)
+                });
+                (start.clone(), start, Vec::new())
+            });
+
+            match movetext {
+                Token::Tag(..) | Token::Comment | Token::Nag | Token::MoveNumber => {}
+                Token::VariationStart => depth += 1,
+                Token::VariationEnd => {
+                    if depth == 0 {
+                        error.get_or_insert_with(|| self.syntax("unmatched ')'"));
+                    } else {
+                        depth -= 1;
+                    }
+                }
+                Token::Result(token) => {
+                    if depth == 0 {
+                        result = Some(token);
+                        break;
+                    }
+                }
+                Token::San(san) => {
+                    // Variations are skipped, and so is the rest of a game that already failed.
+                    if depth > 0 || error.is_some() {
+                        continue;
+                    }
+                    match board.parse_san(&san) {
+                        Ok(m) => {
+                            *board = board.make(m, self.zobrist);
+                            moves.push(m);
+                        }
+                        Err(err) => error = Some(PgnError::Move { line: self.line_number, san, error: err }),
+                    }
+                }
+            }
+        }
+
+        if let Some(err) = error {
+            return Some(Err(err));
+        }
+        if depth > 0 {
+            return Some(Err(self.syntax("unterminated variation")));
+        }
+        let (start, moves) = match game {
+            Some((start, _, moves)) => (start, moves),
+            None if tags.is_empty() => return None,
+            // Tags with no movetext at the end of the input.
+            None => match self.start_position(&tags) {
+                Ok(start) => (start, Vec::new()),
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        // An unterminated game falls back on its Result tag.
+        let result = result
+            .or_else(|| {
+                tags.iter().find(|(name, _)| name == "Result").and_then(|(_, value)| GameResult::from_token(value.as_bytes()))
+            })
+            .unwrap_or_default();
+        Some(Ok(PgnGame { tags, start, moves, result }))
+    }
+}
@@ -354,7 +354,7 @@ impl Rank {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum File {
     A,
     B,
@@ -719,7 +719,7 @@ impl Square {
 
     #[must_use]
     pub const fn flip(self) -> Self {
-        unsafe { Self::from_u8_unchecked(self.into_inner() ^ 56) }
+        unsafe { Self::from_u8_unchecked(self.into_inner() ^ 0o70) }
     }
 }
 
@@ -1,10 +1,13 @@
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 use tinyvec::ArrayVec;
-use yukari::Search;
+use yukari::{allocate_tt, Search, SearchParams};
 use yukari_movegen::{Board, Zobrist};
 
 pub fn search_bench(c: &mut Criterion) {
     let zobrist = Zobrist::new();
+    let tt = allocate_tt(16);
+    let mut corrhist = [[0; 16384]; 2];
+    let params = SearchParams::default();
     let kiwipete = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &zobrist).unwrap();
 
     let mut group = c.benchmark_group("kiwipete");
@@ -14,7 +17,7 @@ pub fn search_bench(c: &mut Criterion) {
     group.noise_threshold(0.025);
 
     let nodes = {
-        let mut s = Search::new(None, &zobrist);
+        let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
         let mut pv = ArrayVec::new();
         let mut keystack = Vec::new();
         s.search_root(&kiwipete, 3, &mut pv, &mut keystack);
@@ -23,7 +26,7 @@ pub fn search_bench(c: &mut Criterion) {
 
     group.throughput(Throughput::Elements(nodes));
     group.bench_with_input("kiwipete-3", &kiwipete, |b, board| {
-        let mut s = Search::new(None, &zobrist);
+        let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
         let mut pv = ArrayVec::new();
         let mut keystack = Vec::new();
         b.iter(|| {
@@ -32,7 +35,7 @@ pub fn search_bench(c: &mut Criterion) {
     });
 
     let nodes = {
-        let mut s = Search::new(None, &zobrist);
+        let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
         let mut pv = ArrayVec::new();
         let mut keystack = Vec::new();
         s.search_root(&kiwipete, 4, &mut pv, &mut keystack);
@@ -41,7 +44,7 @@ pub fn search_bench(c: &mut Criterion) {
 
     group.throughput(Throughput::Elements(nodes));
     group.bench_with_input("kiwipete-4", &kiwipete, |b, board| {
-        let mut s = Search::new(None, &zobrist);
+        let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
         let mut pv = ArrayVec::new();
         let mut keystack = Vec::new();
         b.iter(|| {
@@ -1,7 +1,6 @@
 use std::time::Instant;
 
-use tinyvec::ArrayVec;
-use yukari::Search;
+use yukari::{allocate_tt, Iteration, IterativeDeepening, Search, SearchParams};
 use yukari_movegen::{Board, Zobrist};
 
 fn main() {
@@ -58,19 +57,20 @@ fn main() {
         "7k/7P/6K1/8/3B4/8/8/8 b - -",
     ];
 
+    let tt = allocate_tt(16);
+    let mut corrhist = [[0; 16384]; 2];
+    let params = SearchParams::default();
     let mut nodes = 0;
     let start = Instant::now();
     for fen in fens {
         let zobrist = Zobrist::new();
         let board = Board::from_fen(fen, &zobrist).unwrap();
-        let mut s = Search::new(None, &zobrist);
+        let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
         let start = Instant::now();
         let mut keystack = Vec::new();
-        let mut pv = ArrayVec::new();
-        pv.set_len(0);
-        let score = s.search_root(&board, 8, &mut pv, &mut keystack);
+        let Iteration { score, pv, .. } = IterativeDeepening::new(8).run(&mut s, &board, &mut keystack, |_| {}).unwrap_or_default();
         let now = Instant::now().duration_since(start);
-        print!("10 {score:.2} {} {} ", now.as_millis() / 10, s.nodes() + s.qnodes());
+        print!("10 {} {} {} ", score.to_xboard(), now.as_millis() / 10, s.nodes() + s.qnodes());
         for m in pv {
             print!("{m} ");
         }
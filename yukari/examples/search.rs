@@ -1,7 +1,6 @@
 use std::time::Instant;
 
-use tinyvec::ArrayVec;
-use yukari::Search;
+use yukari::{allocate_tt, IterativeDeepening, Search, SearchParams};
 use yukari_movegen::{Board, Zobrist};
 
 fn main() {
@@ -13,20 +12,20 @@ fn main() {
     )
     .unwrap();
 
-    let mut s = Search::new(None, &zobrist);
+    let tt = allocate_tt(16);
+    let mut corrhist = [[0; 16384]; 2];
+    let params = SearchParams::default();
+    let mut s = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
     let start = Instant::now();
-    for depth in 1..=10 {
-        let mut keystack = Vec::new();
-        let mut pv = ArrayVec::new();
-        pv.set_len(0);
-        let score = s.search_root(&board, depth, &mut pv, &mut keystack);
+    let mut keystack = Vec::new();
+    IterativeDeepening::new(10).run(&mut s, &board, &mut keystack, |iteration| {
         let now = Instant::now().duration_since(start);
-        print!("{} {:.2} {} {} ", depth, score, now.as_millis() / 10, s.nodes() + s.qnodes());
-        for m in pv {
+        print!("{} {} {} {} ", iteration.depth, iteration.score.to_xboard(), now.as_millis() / 10, iteration.nodes);
+        for m in &iteration.pv {
             print!("{} ", m);
         }
         println!();
-    }
+    });
     println!("# QS: {:.3}%", (100 * s.qnodes()) as f64 / (s.nodes() as f64 + s.qnodes() as f64));
     println!(
         "# Branching factor: {:.3} (AB); {:.3} (QS); {:.3} overall",
@@ -0,0 +1,130 @@
+use std::io::{self, Write};
+
+use yukari_movegen::{Board, Colour, GameResult, Piece, Square};
+
+/// A position from a self-play game, with its search score from white's point of view.
+#[derive(Clone)]
+pub struct Sample {
+    pub board: Board,
+    pub score: i16,
+}
+
+/// The game result as the fraction of a point white scored.
+const fn white_points(result: GameResult) -> &'static str {
+    match result {
+        GameResult::WhiteWins => "1.0",
+        GameResult::BlackWins => "0.0",
+        GameResult::Draw | GameResult::Unknown => "0.5",
+    }
+}
+
+/// Write a position in the text format: `<fen> | <score> | <result>`.
+///
+/// The score is in centipawns and the result is the fraction of a point white scored, both from white's point of view.
+///
+/// # Errors
+/// Returns any error from writing to `out`.
+pub fn write_text<W: Write>(out: &mut W, sample: &Sample, result: GameResult) -> io::Result<()> {
+    writeln!(out, "{} | {} | {}", sample.board.to_fen(), sample.score, white_points(result))
+}
+
+/// Encode a position in marlinformat, the 32-byte packed board that bullet and marlinflow read.
+///
+/// The layout, all little-endian:
+/// - the occupied squares as a 64-bit set, with a1 as the lowest bit;
+/// - 16 bytes of 4-bit pieces, in the order of the occupied squares and low nibble first. The low 3 bits are the piece
+///   (pawn to king, or 6 for a rook that can still castle) and the top bit is set for black;
+/// - the side to move in the top bit of a byte whose low 7 bits are the en-passant square, or 64 if there is none;
+/// - the halfmove clock as a byte and the fullmove number as 16 bits;
+/// - the score as a signed 16-bit number, then the result as 0 for a black win, 1 for a draw or 2 for a white win;
+/// - one unused byte.
+#[must_use]
+pub fn pack_marlinformat(sample: &Sample, result: GameResult) -> [u8; 32] {
+    let board = &sample.board;
+    let (white_short, white_long, black_short, black_long) = board.castling_rights();
+    // The castling rooks start on h1, a1, h8 and a8.
+    let can_castle_with = |square: Square| match square.into_inner() {
+        7 => white_short,
+        0 => white_long,
+        63 => black_short,
+        56 => black_long,
+        _ => false,
+    };
+
+    let mut pieces = board
+        .pieces()
+        .into_iter()
+        .map(|bit| (board.square_of_piece(bit), board.piece_from_bit(bit), bit.colour()))
+        .collect::<Vec<_>>();
+    pieces.sort_unstable_by_key(|(square, _, _)| square.into_inner());
+
+    let mut packed = [0_u8; 32];
+    let mut occupancy = 0_u64;
+    for (i, &(square, piece, colour)) in pieces.iter().enumerate() {
+        occupancy |= 1 << square.into_inner();
+        let kind = if piece == Piece::Rook && can_castle_with(square) { 6 } else { piece as u8 };
+        let nibble = kind | if colour == Colour::Black { 8 } else { 0 };
+        packed[8 + i / 2] |= nibble << (4 * (i % 2));
+    }
+    packed[..8].copy_from_slice(&occupancy.to_le_bytes());
+
+    let ep = board.ep().map_or(64, Square::into_inner);
+    packed[24] = ep | if board.side() == Colour::Black { 0x80 } else { 0 };
+    packed[25] = u8::try_from(board.halfmove_clock()).unwrap_or(u8::MAX);
+    packed[26..28].copy_from_slice(&board.fullmove_number().to_le_bytes());
+    packed[28..30].copy_from_slice(&sample.score.to_le_bytes());
+    packed[30] = match result {
+        GameResult::BlackWins => 0,
+        GameResult::Draw | GameResult::Unknown => 1,
+        GameResult::WhiteWins => 2,
+    };
+    packed
+}
+
+/// Where the positions of finished games go, in text, marlinformat or both.
+pub struct DataWriter<W> {
+    text: Option<W>,
+    binary: Option<W>,
+}
+
+impl<W: Write> DataWriter<W> {
+    #[must_use]
+    pub const fn new(text: Option<W>, binary: Option<W>) -> Self {
+        Self { text, binary }
+    }
+
+    /// Write every position of a game that ended in `result`.
+    ///
+    /// # Errors
+    /// Returns any error from writing to the outputs.
+    pub fn write_game(&mut self, samples: &[Sample], result: GameResult) -> io::Result<()> {
+        for sample in samples {
+            if let Some(text) = &mut self.text {
+                write_text(text, sample, result)?;
+            }
+            if let Some(binary) = &mut self.binary {
+                binary.write_all(&pack_marlinformat(sample, result))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush both outputs.
+    ///
+    /// # Errors
+    /// Returns any error from flushing the outputs.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(text) = &mut self.text {
+            text.flush()?;
+        }
+        if let Some(binary) = &mut self.binary {
+            binary.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Give back the text and binary outputs.
+    pub fn into_inner(self) -> (Option<W>, Option<W>) {
+        (self.text, self.binary)
+    }
+}
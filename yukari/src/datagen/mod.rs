@@ -0,0 +1,287 @@
+//! Self-play games played to generate evaluation training data.
+
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use tinyvec::ArrayVec;
+use yukari_movegen::{Board, Book, BookSelection, Colour, Evaluator, GameResult, Move, Piece, Zobrist};
+
+use crate::{allocate_tt, is_repetition_draw, IterativeDeepening, Score, Search, SearchParams, TtEntry};
+
+mod format;
+
+pub use format::{pack_marlinformat, write_text, DataWriter, Sample};
+
+/// Stack size for threads that play games, which recurse as deeply as any search
+pub(crate) const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// How the self-play games are played, adjudicated and filtered.
+#[derive(Clone)]
+pub struct DatagenConfig {
+    /// Number of games to play.
+    pub games: u64,
+    /// Number of games played at once, each on its own thread with its own transposition table.
+    pub threads: usize,
+    /// Transposition table size of each thread, in megabytes.
+    pub hash_mb: usize,
+    /// Stop searching each move after this many nodes.
+    pub nodes: Option<u64>,
+    /// Stop searching each move at this depth.
+    pub depth: i32,
+    /// Random moves played from the start position, or from the end of the book line, before the game is scored.
+    pub random_plies: usize,
+    /// Seed of the random openings; each thread adds its index.
+    pub seed: u64,
+    /// Adjudicate a win once both sides have scored the position beyond this for `resign_plies` plies in a row.
+    pub resign_score: i32,
+    pub resign_plies: u32,
+    /// Adjudicate a draw once both sides have scored the position within this for `draw_plies` plies in a row, from
+    /// ply `draw_min_ply` of the scored game on.
+    pub draw_score: i32,
+    pub draw_plies: u32,
+    pub draw_min_ply: u32,
+    /// Adjudicate a win as soon as a search finds a forced mate.
+    pub win_on_mate: bool,
+    /// Leave out positions where the side to move is in check.
+    pub skip_in_check: bool,
+    /// Leave out positions where the best move is a capture or a promotion.
+    pub skip_noisy: bool,
+    /// Leave out positions with a mate score.
+    pub skip_mates: bool,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        Self {
+            games: 1000,
+            threads: 1,
+            hash_mb: 16,
+            nodes: Some(5000),
+            depth: 99,
+            random_plies: 8,
+            seed: 0,
+            resign_score: 1000,
+            resign_plies: 6,
+            draw_score: 10,
+            draw_plies: 12,
+            draw_min_ply: 80,
+            win_on_mate: true,
+            skip_in_check: true,
+            skip_noisy: true,
+            skip_mates: true,
+        }
+    }
+}
+
+/// Counts of what the generator produced.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DatagenStats {
+    pub games: u64,
+    pub positions: u64,
+}
+
+/// Plays self-play games one after another on a single thread.
+pub struct Generator<'a, E: Evaluator> {
+    zobrist: &'a Zobrist,
+    params: &'a SearchParams,
+    config: &'a DatagenConfig,
+    evaluator: E,
+    book: Option<&'a Book>,
+    tt: Vec<TtEntry>,
+    corrhist: Box<[[i32; 16384]; 2]>,
+    rng: StdRng,
+}
+
+impl<'a, E: Evaluator> Generator<'a, E> {
+    /// Create a generator. `zobrist` must be [`Zobrist::polyglot`] to play from `book`.
+    #[must_use]
+    pub fn new(
+        zobrist: &'a Zobrist, params: &'a SearchParams, config: &'a DatagenConfig, evaluator: E, book: Option<&'a Book>, seed: u64,
+    ) -> Self {
+        Self {
+            zobrist,
+            params,
+            config,
+            evaluator,
+            book,
+            tt: allocate_tt(config.hash_mb),
+            corrhist: Box::new([[0; 16384]; 2]),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Play a game, returning its filtered positions and result, or `None` if it could not be played out.
+    pub fn play_game(&mut self) -> Option<(Vec<Sample>, GameResult)> {
+        let mut board = opening(self.zobrist, self.book, self.config.random_plies, &mut self.rng)?;
+        let mut keystack = vec![board.hash()];
+        self.tt.iter_mut().for_each(|entry| *entry = TtEntry::default());
+        *self.corrhist = [[0; 16384]; 2];
+
+        let mut samples = Vec::new();
+        let mut resign = Resign::default();
+        // Plies in a row that both sides scored as drawn.
+        let mut drawn_plies = 0;
+        let mut ply = 0;
+        loop {
+            if let Some(result) = game_over(&board, &keystack) {
+                return Some((samples, result));
+            }
+
+            let mut search =
+                Search::with_evaluator(None, self.zobrist, &self.tt, &mut self.corrhist, self.params, self.evaluator.clone());
+            search.set_node_limit(self.config.nodes);
+            let iteration = IterativeDeepening::new(self.config.depth).run(&mut search, &board, &mut keystack, |_| {})?;
+            let m = *iteration.pv.first()?;
+            let score = if board.side() == Colour::White { iteration.score } else { -iteration.score };
+
+            if self.config.win_on_mate && score.is_mate() {
+                return Some((samples, winner(score)));
+            }
+            if let Some(result) = resign.update(score, self.config.resign_score, self.config.resign_plies) {
+                return Some((samples, result));
+            }
+
+            drawn_plies = if score.get().abs() <= self.config.draw_score { drawn_plies + 1 } else { 0 };
+            if ply >= self.config.draw_min_ply && drawn_plies >= self.config.draw_plies {
+                return Some((samples, GameResult::Draw));
+            }
+
+            let skip = (self.config.skip_in_check && board.in_check())
+                || (self.config.skip_noisy && (m.is_capture() || m.prom.is_some()))
+                || (self.config.skip_mates && score.is_mate());
+            if !skip {
+                let score = i16::try_from(score.get()).unwrap_or(if score.get() > 0 { i16::MAX } else { i16::MIN });
+                samples.push(Sample { board: board.clone(), score });
+            }
+
+            board = board.make(m, self.zobrist);
+            keystack.push(board.hash());
+            ply += 1;
+        }
+    }
+}
+
+/// The legal moves of `board`.
+pub(crate) fn legal_moves(board: &Board) -> ArrayVec<[Move; 256]> {
+    let mut moves = ArrayVec::from([Move::default(); 256]);
+    moves.set_len(0);
+    board.generate(&mut moves);
+    moves
+}
+
+/// Play an opening: book moves while there are any, then `random_plies` random moves.
+///
+/// Returns `None` if a random move ended the game.
+pub(crate) fn opening(zobrist: &Zobrist, book: Option<&Book>, random_plies: usize, rng: &mut impl Rng) -> Option<Board> {
+    let mut board = Board::startpos(zobrist);
+    if let Some(book) = book {
+        while let Some(m) = book.choose(&board, BookSelection::Weighted, rng) {
+            board = board.make(m, zobrist);
+        }
+    }
+    for _ in 0..random_plies {
+        let m = *legal_moves(&board).choose(rng)?;
+        board = board.make(m, zobrist);
+    }
+    (!legal_moves(&board).is_empty()).then_some(board)
+}
+
+/// The side that `score`, from White's point of view, has winning.
+pub(crate) fn winner(score: Score) -> GameResult {
+    if score.get() > 0 {
+        GameResult::WhiteWins
+    } else {
+        GameResult::BlackWins
+    }
+}
+
+/// Counts the plies in a row that both sides scored as won for the same side, to adjudicate resignations.
+#[derive(Default)]
+pub(crate) struct Resign {
+    plies: u32,
+    last_sign: i32,
+}
+
+impl Resign {
+    /// Count the score of the next ply, from White's point of view, returning the result once it has been beyond
+    /// `resign_score` for `resign_plies` plies in a row.
+    pub(crate) fn update(&mut self, score: Score, resign_score: i32, resign_plies: u32) -> Option<GameResult> {
+        let sign = score.get().signum();
+        self.plies = if score.get().abs() < resign_score {
+            0
+        } else if sign == self.last_sign {
+            self.plies + 1
+        } else {
+            1
+        };
+        self.last_sign = sign;
+        (self.plies >= resign_plies).then(|| winner(score))
+    }
+}
+
+/// The result of the game if it has ended by the rules: by checkmate, stalemate, the fifty-move rule, repetition or
+/// insufficient material.
+pub(crate) fn game_over(board: &Board, keystack: &[u64]) -> Option<GameResult> {
+    if legal_moves(board).is_empty() {
+        return Some(match (board.in_check(), board.side()) {
+            (false, _) => GameResult::Draw,
+            (true, Colour::White) => GameResult::BlackWins,
+            (true, Colour::Black) => GameResult::WhiteWins,
+        });
+    }
+
+    let mut material = board.pieces().into_iter().map(|bit| board.piece_from_bit(bit)).filter(|&piece| piece != Piece::King);
+    let insufficient = matches!((material.next(), material.next()), (None, _) | (Some(Piece::Knight | Piece::Bishop), None));
+
+    (board.halfmove_clock() >= 100 || is_repetition_draw(keystack, board.hash(), board.halfmove_clock()) || insufficient)
+        .then_some(GameResult::Draw)
+}
+
+/// Play `config.games` games on `config.threads` threads, writing each finished game to `writer`.
+///
+/// # Errors
+/// Returns the first error from writing to `writer`, or from starting the threads.
+pub fn generate<E: Evaluator + Send + Sync, W: Write + Send>(
+    config: &DatagenConfig, params: &SearchParams, evaluator: &E, book: Option<&Book>, writer: DataWriter<W>,
+) -> io::Result<(DatagenStats, DataWriter<W>)> {
+    let zobrist = Zobrist::polyglot();
+    let writer = Mutex::new(writer);
+    let (started, games, positions) = (AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0));
+    let start = Instant::now();
+
+    let pool =
+        rayon::ThreadPoolBuilder::new().num_threads(config.threads).stack_size(STACK_SIZE).build().map_err(io::Error::other)?;
+    pool.broadcast(|context| -> io::Result<()> {
+        let seed = config.seed.wrapping_add(context.index() as u64);
+        let mut generator = Generator::new(&zobrist, params, config, evaluator.clone(), book, seed);
+        while started.fetch_add(1, Ordering::Relaxed) < config.games {
+            let Some((samples, result)) = generator.play_game() else {
+                // Try another opening instead.
+                started.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            };
+            writer.lock().unwrap().write_game(&samples, result)?;
+
+            let games = games.fetch_add(1, Ordering::Relaxed) + 1;
+            let positions = positions.fetch_add(samples.len() as u64, Ordering::Relaxed) + samples.len() as u64;
+            if games % 100 == 0 {
+                let rate = positions as f64 / start.elapsed().as_secs_f64();
+                println!("# {games} games, {positions} positions, {rate:.0} positions/s");
+            }
+        }
+        Ok(())
+    })
+    .into_iter()
+    .collect::<io::Result<()>>()?;
+
+    let mut writer = writer.into_inner().unwrap();
+    writer.flush()?;
+    Ok((DatagenStats { games: games.into_inner(), positions: positions.into_inner() }, writer))
+}
@@ -0,0 +1,99 @@
+use tinyvec::ArrayVec;
+use yukari_movegen::{Board, Evaluator, Move};
+
+use crate::{score::Score, search::Search};
+
+/// Iterations shallower than this are searched with a full window, since their scores swing too much to guess.
+const ASPIRATION_MIN_DEPTH: i32 = 4;
+
+/// The initial distance of the aspiration window bounds from the previous iteration's score.
+const ASPIRATION_DELTA: i32 = 25;
+
+/// How many depths in a row each helper thread searches and then skips, by helper, repeating after the last.
+const SKIP_SIZE: [i32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+
+/// Where in its cycle of searched and skipped depths each helper thread starts.
+const SKIP_PHASE: [i32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+/// The result of one completed iteration.
+#[derive(Clone, Default)]
+pub struct Iteration {
+    pub depth: i32,
+    pub score: Score,
+    pub pv: ArrayVec<[Move; 32]>,
+    /// Nodes searched so far, including quiescence nodes.
+    pub nodes: u64,
+}
+
+/// Searches a position one ply deeper at a time until a depth limit or the search is stopped.
+///
+/// From the fourth iteration on, each search uses a narrow aspiration window around the previous score. When the score
+/// falls outside the window, the failing bound is moved out by a delta that doubles with every re-search.
+pub struct IterativeDeepening {
+    max_depth: i32,
+    /// The helper thread this search is for, if any, which decides the depths it skips.
+    helper: Option<usize>,
+}
+
+impl IterativeDeepening {
+    #[must_use]
+    pub const fn new(max_depth: i32) -> Self {
+        Self { max_depth, helper: None }
+    }
+
+    /// Search as helper thread `index` (from 1) of a multi-threaded search.
+    ///
+    /// Helpers skip some depths, on a schedule that differs between them, so that at any time the threads are spread
+    /// over several depths instead of all searching the one the main thread is on.
+    pub fn set_helper_index(&mut self, index: usize) {
+        self.helper = index.checked_sub(1).map(|index| index % SKIP_SIZE.len());
+    }
+
+    /// Whether the helper skips this depth.
+    fn skips(&self, depth: i32) -> bool {
+        self.helper.is_some_and(|helper| (depth + SKIP_PHASE[helper]) / SKIP_SIZE[helper] % 2 != 0)
+    }
+
+    /// Search `board` with `search`, calling `on_iteration` after every completed iteration.
+    ///
+    /// Returns the last completed iteration, or `None` if the search was stopped during the first.
+    pub fn run<E: Evaluator>(
+        &self, search: &mut Search<E>, board: &Board, keystack: &mut Vec<u64>, mut on_iteration: impl FnMut(&Iteration),
+    ) -> Option<Iteration> {
+        let mut last: Option<Iteration> = None;
+
+        for depth in (1..=self.max_depth).filter(|&depth| !self.skips(depth)) {
+            let mut pv = ArrayVec::new();
+            let mut delta = ASPIRATION_DELTA;
+            let (mut lower_bound, mut upper_bound) = match &last {
+                Some(last) if depth >= ASPIRATION_MIN_DEPTH => {
+                    ((last.score - delta).max(-Score::INFINITY), (last.score + delta).min(Score::INFINITY))
+                }
+                _ => (-Score::INFINITY, Score::INFINITY),
+            };
+
+            let score = loop {
+                pv.set_len(0);
+                let score = search.search_window(board, depth, lower_bound, upper_bound, &mut pv, keystack);
+                if search.stopped() {
+                    return last;
+                }
+
+                if score <= lower_bound && lower_bound > -Score::INFINITY {
+                    lower_bound = (score - delta).max(-Score::INFINITY);
+                } else if score >= upper_bound && upper_bound < Score::INFINITY {
+                    upper_bound = (score + delta).min(Score::INFINITY);
+                } else {
+                    break score;
+                }
+                delta *= 2;
+            };
+
+            let iteration = Iteration { depth, score, pv, nodes: search.nodes() + search.qnodes() };
+            on_iteration(&iteration);
+            last = Some(iteration);
+        }
+
+        last
+    }
+}
@@ -18,6 +18,7 @@ impl TimeControl {
         Self {
             remaining: match mode {
                 TimeMode::MoveTime(time) => time as f32 / 1000.0,
+                TimeMode::Infinite => 0.0,
                 TimeMode::Incremental { base, .. } | TimeMode::Classical { base, .. } => base,
             },
             mode,
@@ -25,6 +26,18 @@ impl TimeControl {
         }
     }
 
+    /// Construct time controls from the clock of a UCI `go` command, with times in milliseconds.
+    #[must_use]
+    pub fn from_uci(remaining: u32, increment: u32, movestogo: Option<u32>) -> Self {
+        let base = remaining as f32 / 1000.0;
+        let mode = match movestogo {
+            // UCI tells us the moves left directly, so the session starts at our current move.
+            Some(mps) => TimeMode::Classical { base, mps: mps.max(1) },
+            None => TimeMode::Incremental { base, increment: increment as f32 / 1000.0 },
+        };
+        Self::new(mode)
+    }
+
     /// Set the time using a centisecond value
     pub fn set_remaining(&mut self, centiseconds: f32) {
         self.remaining = centiseconds / 100.0;
@@ -35,11 +48,12 @@ impl TimeControl {
         self.move_number += 1;
     }
 
-    /// Compute the time to search.
+    /// Compute the time to search, or `None` if the search has no time limit.
     #[must_use]
-    pub fn search_time(&self) -> f32 {
-        match self.mode {
+    pub fn search_time(&self) -> Option<f32> {
+        Some(match self.mode {
             TimeMode::MoveTime(millisecs) => (millisecs as f32 / 1000.0) - 0.02,
+            TimeMode::Infinite => return None,
             TimeMode::Incremental { base: _, increment } => {
                 let remaining = self.remaining - 0.02;
                 remaining.min((remaining + increment) / 30.0)
@@ -57,7 +71,7 @@ impl TimeControl {
 
                 remaining / (movesleft as f32)
             }
-        }
+        })
     }
 }
 
@@ -66,6 +80,8 @@ impl TimeControl {
 pub enum TimeMode {
     /// MoveTime mode has a fixed number of milliseconds per move
     MoveTime(u32),
+    /// Infinite mode searches until told to stop
+    Infinite,
     /// Incremental mode gives us the whole game's clock, plus time to be added after each move
     Incremental {
         /// Base time for the game in seconds
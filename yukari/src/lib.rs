@@ -1,6 +1,308 @@
 #![warn(clippy::imprecise_flops, clippy::suboptimal_flops)]
 
+pub mod datagen;
+mod deepening;
 pub mod engine;
+mod movepick;
+mod params;
+mod score;
 mod search;
+pub mod spsa;
+pub mod tune;
 
-pub use search::{is_repetition_draw, Search, SearchParams, TtEntry, allocate_tt};
+pub use deepening::{Iteration, IterativeDeepening};
+pub use params::{SearchParams, Tunable};
+pub use score::Score;
+pub use search::{allocate_tt, is_repetition_draw, Search, StackEntry, TtEntry};
+
+#[cfg(test)]
+mod mate_scores {
+    use yukari_movegen::{Board, Zobrist};
+
+    use crate::{allocate_tt, IterativeDeepening, Score, Search, SearchParams};
+
+    fn search_mate(fen: &str, depth: i32) -> Score {
+        search_mate_with(fen, depth, &SearchParams::default())
+    }
+
+    fn search_mate_with(fen: &str, depth: i32, params: &SearchParams) -> Score {
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen(fen, &zobrist).unwrap();
+        let tt = allocate_tt(1);
+        let mut corrhist = [[0; 16384]; 2];
+        let mut search = Search::new(None, &zobrist, &tt, &mut corrhist, params);
+        IterativeDeepening::new(depth).run(&mut search, &board, &mut Vec::new(), |_| {}).unwrap().score
+    }
+
+    #[test]
+    fn tt_round_trip() {
+        for ply in 0..20 {
+            for score in [Score::mate_in(ply + 3), Score::mated_in(ply + 4), Score::centipawns(150), Score::DRAW] {
+                assert_eq!(Score::from_tt(score.to_tt(ply), ply), score);
+            }
+        }
+        // A mate found 3 plies from this node is still 3 plies away when the node is reached at another ply.
+        assert_eq!(Score::from_tt(Score::mate_in(5).to_tt(2), 6), Score::mate_in(9));
+    }
+
+    #[test]
+    fn reporting() {
+        assert_eq!(Score::mate_in(1).to_uci(), "mate 1");
+        assert_eq!(Score::mate_in(3).to_uci(), "mate 2");
+        assert_eq!(Score::mated_in(2).to_uci(), "mate -1");
+        assert_eq!(Score::mated_in(0).to_uci(), "mate 0");
+        assert_eq!(Score::centipawns(-42).to_uci(), "cp -42");
+        assert_eq!(Score::mate_in(5).to_xboard(), 100_003);
+        assert_eq!(Score::mated_in(4).to_xboard(), -100_002);
+        assert_eq!(Score::centipawns(42).to_xboard(), 42);
+        assert!(!Score::centipawns(1_000_000).is_mate());
+    }
+
+    #[test]
+    fn finds_forced_mates() {
+        // KQK: Qa8#.
+        assert_eq!(search_mate("7k/8/6K1/8/8/8/Q7/8 w - - 0 1", 4).mate_moves(), Some(1));
+        // KRK: Rh7 forces the king to a back-rank corner for Rh8#.
+        assert_eq!(search_mate("3k4/8/4K3/8/8/8/8/7R w - - 0 1", 6).mate_moves(), Some(2));
+        // The defending side sees its own mate coming: Kg8 Rb8#.
+        assert_eq!(search_mate("7k/8/6K1/8/8/8/8/1R6 b - - 0 1", 4).mate_moves(), Some(-1));
+        // Deeper searches, which also see longer mates, still report the shortest.
+        assert_eq!(search_mate("7k/8/6K1/8/8/8/Q7/8 w - - 0 1", 8).mate_moves(), Some(1));
+    }
+
+    #[test]
+    fn quiescence_checks() {
+        let params = SearchParams { qsearch_checks: true, ..SearchParams::default() };
+        assert_eq!(search_mate_with("7k/8/6K1/8/8/8/Q7/8 w - - 0 1", 1, &params).mate_moves(), Some(1));
+        assert_eq!(search_mate_with("3k4/8/4K3/8/8/8/8/7R w - - 0 1", 6, &params).mate_moves(), Some(2));
+        // After the forced Kg8, a one-ply search leaves Rb8# to quiescence, which only sees it when trying checks.
+        assert_eq!(search_mate_with("7k/8/6K1/8/8/8/8/1R6 b - - 0 1", 1, &params).mate_moves(), Some(-1));
+        assert_eq!(search_mate("7k/8/6K1/8/8/8/8/1R6 b - - 0 1", 1).mate_moves(), None);
+    }
+
+    #[test]
+    fn probcut_at_shallow_depths() {
+        // ProbCut must not run, or store bounds, at nodes no deeper than its reduction.
+        let zobrist = Zobrist::new();
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &zobrist).unwrap();
+        let params = SearchParams { probcut_min_depth: 1, probcut_margin: 0, ..SearchParams::default() };
+        let tt = allocate_tt(1);
+        let mut corrhist = [[0; 16384]; 2];
+        let mut search = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
+        IterativeDeepening::new(5).run(&mut search, &board, &mut Vec::new(), |_| {}).unwrap();
+        assert!(tt.iter().all(|entry| entry.depth() <= 16));
+    }
+}
+
+#[cfg(test)]
+mod evaluators {
+    use yukari_movegen::{Board, Eval, Evaluator, Zobrist};
+
+    use crate::{allocate_tt, Iteration, IterativeDeepening, Search, SearchParams};
+
+    /// Counts material from scratch every time, ignoring the incremental hooks.
+    #[derive(Clone)]
+    struct Material;
+
+    impl Evaluator for Material {
+        fn evaluate(&self, board: &Board) -> i32 {
+            const VALUES: [i32; 6] = [100, 300, 300, 500, 900, 0];
+            board
+                .pieces()
+                .into_iter()
+                .map(|bit| {
+                    let value = VALUES[board.piece_from_bit(bit) as usize];
+                    if bit.colour() == board.side() { value } else { -value }
+                })
+                .sum()
+        }
+    }
+
+    fn search_with<E: Evaluator>(fen: &str, depth: i32, evaluator: E) -> (Iteration, u64) {
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen(fen, &zobrist).unwrap();
+        let tt = allocate_tt(1);
+        let mut corrhist = [[0; 16384]; 2];
+        let params = SearchParams::default();
+        let mut search = Search::with_evaluator(None, &zobrist, &tt, &mut corrhist, &params, evaluator);
+        let iteration = IterativeDeepening::new(depth).run(&mut search, &board, &mut Vec::new(), |_| {}).unwrap();
+        (iteration, search.nodes() + search.qnodes())
+    }
+
+    #[test]
+    fn full_recompute_evaluator() {
+        let (iteration, _) = search_with("4k3/8/8/3q4/8/4N3/8/4K3 w - - 0 1", 4, Material);
+        assert_eq!(iteration.pv[0].to_string(), "e3d5");
+        assert!(iteration.score.get() >= 300);
+    }
+
+    #[test]
+    fn pesto_is_the_default() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let (expected, expected_nodes) = search_with(fen, 5, Eval::new());
+
+        let zobrist = Zobrist::new();
+        let board = Board::from_fen(fen, &zobrist).unwrap();
+        let tt = allocate_tt(1);
+        let mut corrhist = [[0; 16384]; 2];
+        let params = SearchParams::default();
+        let mut search = Search::new(None, &zobrist, &tt, &mut corrhist, &params);
+        let iteration = IterativeDeepening::new(5).run(&mut search, &board, &mut Vec::new(), |_| {}).unwrap();
+        assert_eq!(iteration.score, expected.score);
+        assert_eq!(search.nodes() + search.qnodes(), expected_nodes);
+    }
+}
+
+#[cfg(test)]
+mod training_data {
+    use yukari_movegen::{Board, Eval, GameResult, Zobrist};
+
+    use crate::{
+        datagen::{generate, pack_marlinformat, DataWriter, DatagenConfig, Sample},
+        SearchParams,
+    };
+
+    #[test]
+    fn marlinformat_layout() {
+        let zobrist = Zobrist::new();
+        let sample = Sample { board: Board::startpos(&zobrist), score: -20 };
+        let packed = pack_marlinformat(&sample, GameResult::WhiteWins);
+        assert_eq!(packed[..8], 0xffff_0000_0000_ffff_u64.to_le_bytes());
+        // Castling rooks are 6, black pieces have the top bit set.
+        assert_eq!(packed[8..24], [0x16, 0x42, 0x25, 0x61, 0, 0, 0, 0, 0x88, 0x88, 0x88, 0x88, 0x9e, 0xca, 0xad, 0xe9]);
+        assert_eq!(packed[24..], [64, 0, 1, 0, 0xec, 0xff, 2, 0]);
+
+        let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 40", &zobrist).unwrap();
+        let packed = pack_marlinformat(&Sample { board, score: 0 }, GameResult::Draw);
+        assert_eq!(packed[24..28], [43, 0, 40, 0]);
+        assert_eq!(packed[30], 1);
+    }
+
+    #[test]
+    fn writes_both_formats() {
+        let config = DatagenConfig { games: 2, nodes: Some(500), hash_mb: 1, ..DatagenConfig::default() };
+        let writer = DataWriter::new(Some(Vec::new()), Some(Vec::new()));
+        let (stats, writer) = generate(&config, &SearchParams::default(), &Eval::new(), None, writer).unwrap();
+        let (Some(text), Some(binary)) = writer.into_inner() else { unreachable!() };
+
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(stats.games, 2);
+        assert_eq!(text.lines().count() as u64, stats.positions);
+        assert_eq!(binary.len() as u64, 32 * stats.positions);
+        for line in text.lines() {
+            let [fen, score, result] = line.split(" | ").collect::<Vec<_>>()[..] else { panic!("bad line {line}") };
+            assert!(Board::from_fen(fen, &Zobrist::new()).is_ok(), "{fen}");
+            assert!(score.parse::<i16>().is_ok(), "{score}");
+            assert!(["1.0", "0.5", "0.0"].contains(&result), "{result}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tuning {
+    use yukari_movegen::{Board, Colour, Eval, EvalParams, Evaluator, Zobrist};
+
+    use crate::tune::{load_positions, Position, Tuner};
+
+    const FENS: [&str; 4] = [
+        "r1bqkbnr/pp1ppp2/n1p4p/6p1/6P1/NP5N/P1PPPP1P/R1BQKB1R w KQkq - 0 5",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    ];
+
+    /// Positions labeled with the default evaluation's own scores.
+    fn self_labeled() -> Vec<Position> {
+        let zobrist = Zobrist::new();
+        FENS.iter()
+            .map(|fen| {
+                let board = Board::from_fen(fen, &zobrist).unwrap();
+                let mut eval = Eval::new();
+                eval.refresh(&board);
+                let score = if board.side() == Colour::White { eval.evaluate(&board) } else { -eval.evaluate(&board) };
+                Position::new(&board, &EvalParams::DEFAULT.phase, f64::from(score), 0.5)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_the_evaluation() {
+        let tuner = Tuner::new(self_labeled(), &EvalParams::DEFAULT, 0.0);
+        assert!(tuner.error() < 1e-6, "{}", tuner.error());
+        assert!(tuner.gradient().iter().all(|gradient| gradient.abs() < 1e-6));
+        assert_eq!(tuner.params(), EvalParams::DEFAULT);
+    }
+
+    #[test]
+    fn optimisers_reduce_the_error() {
+        let text = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 | 0 | 1.0\n\n\
+                    rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8 | 0 | 0.0\n";
+        let positions = load_positions(text.as_bytes(), &EvalParams::DEFAULT.phase).unwrap();
+        assert_eq!(positions.len(), 2);
+
+        let mut tuner = Tuner::new(positions, &EvalParams::DEFAULT, 1.0);
+        let error = tuner.error();
+        tuner.adam(10, 1.0, |_, _| {});
+        let adam_error = tuner.error();
+        assert!(adam_error < error, "{adam_error} >= {error}");
+        tuner.gauss_newton(2, |_, _| {});
+        assert!(tuner.error() < adam_error, "{} >= {adam_error}", tuner.error());
+    }
+}
+
+#[cfg(test)]
+mod search_params {
+    use yukari_movegen::Eval;
+
+    use crate::{
+        spsa::{Spsa, SpsaConfig},
+        SearchParams,
+    };
+
+    #[test]
+    fn registry_matches_defaults() {
+        let params = SearchParams::default();
+        assert_eq!(params.lmr_base, 1.0);
+        assert_eq!(params.lmr_mul, 0.5);
+        for (i, tunable) in SearchParams::TUNABLES.iter().enumerate() {
+            assert_eq!(tunable.get(&params), tunable.default, "{}", tunable.name);
+            assert!((tunable.min..=tunable.max).contains(&tunable.default), "{}", tunable.name);
+            assert!(SearchParams::TUNABLES[..i].iter().all(|other| other.name != tunable.name), "{}", tunable.name);
+        }
+        assert_eq!(SearchParams::openbench().lines().count(), SearchParams::TUNABLES.len());
+        assert!(SearchParams::openbench().starts_with("RfpMarginBase, int, 0, 0, 100, 5, 0.002\n"));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut params = SearchParams::default();
+        assert!(params.set("LmrMul", 420));
+        assert!(params.set("ProbcutMargin", 5000));
+        assert!(!params.set("NoSuchParam", 1));
+        assert_eq!(params.lmr_mul, 0.42);
+        assert_eq!(params.probcut_margin, 1000);
+        assert_eq!(SearchParams::parse(&params.to_string()).unwrap(), params);
+
+        let params = SearchParams::parse("# OpenBench results\nRfpMarginMul, 41.7\n\nLmpBase = 4\n").unwrap();
+        assert_eq!((params.rfp_margin_mul, params.lmp_base), (42, 4));
+        assert!(SearchParams::parse("NoSuchParam = 1").is_err());
+        assert!(SearchParams::parse("LmpBase").is_err());
+    }
+
+    #[test]
+    fn spsa_stays_in_range() {
+        let config = SpsaConfig { iterations: 2, pairs: 1, hash_mb: 1, nodes: Some(300), max_plies: 40, ..SpsaConfig::default() };
+        let mut spsa = Spsa::new(config, &SearchParams::default(), Eval::new(), None).unwrap();
+        let mut iterations = 0;
+        spsa.run(|iteration, result, _| {
+            iterations = iteration;
+            assert!((-2.0..=2.0).contains(&result));
+        });
+        assert_eq!(iterations, 2);
+        let params = spsa.params();
+        for tunable in SearchParams::TUNABLES {
+            assert!((tunable.min..=tunable.max).contains(&tunable.get(&params)), "{}", tunable.name);
+        }
+    }
+}
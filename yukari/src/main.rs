@@ -1,13 +1,15 @@
 use std::{
-    fs::File, io::{self, BufRead, Write}, str::FromStr, sync::Mutex, time::{Duration, Instant} 
+    collections::VecDeque, fs::File, io::{self, BufRead, Write}, str::FromStr, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender}, Arc, Mutex}, thread, time::{Duration, Instant} 
 };
 
 use tinyvec::ArrayVec;
 use rayon::prelude::*;
 use yukari::{
-    self, allocate_tt, engine::{TimeControl, TimeMode}, is_repetition_draw, Search, SearchParams, TtEntry
+    self, allocate_tt, datagen::{DataWriter, DatagenConfig}, spsa::{Spsa, SpsaConfig}, tune::{self, Tuner}, engine::{TimeControl, TimeMode}, is_repetition_draw, Iteration, IterativeDeepening, Search, SearchParams, TtEntry
+};
+use yukari_movegen::{
+    Board, Book, BookBuilder, BookSelection, Colour, Eval, EvalParams, Evaluator, FenError, Move, Network, Nnue, ParseMoveError, PgnReader, Piece, Square, Zobrist
 };
-use yukari_movegen::{Board, Move, Piece, Square, Zobrist};
 
 #[derive(Clone, Copy, Debug)]
 enum Mode {
@@ -20,6 +22,60 @@ enum Mode {
            // TODO: Analyze mode also exists
 }
 
+/// The protocol the GUI talks to us in, detected from the first command it sends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Protocol {
+    Xboard,
+    Uci,
+}
+
+/// The evaluation the engine searches with, picked at runtime by the `UseNNUE` option
+///
+/// The network's hidden layers are kept inline rather than boxed, since the search copies one evaluation per ply.
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)]
+enum Evaluation {
+    Pesto(Eval),
+    Nnue(Nnue),
+}
+
+impl Evaluator for Evaluation {
+    fn evaluate(&self, board: &Board) -> i32 {
+        match self {
+            Self::Pesto(eval) => eval.evaluate(board),
+            Self::Nnue(nnue) => nnue.evaluate(board),
+        }
+    }
+
+    fn refresh(&mut self, board: &Board) {
+        match self {
+            Self::Pesto(eval) => eval.refresh(board),
+            Self::Nnue(nnue) => nnue.refresh(board),
+        }
+    }
+
+    fn add_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        match self {
+            Self::Pesto(eval) => eval.add_piece(piece, colour, square),
+            Self::Nnue(nnue) => nnue.add_piece(piece, colour, square),
+        }
+    }
+
+    fn remove_piece(&mut self, piece: Piece, colour: Colour, square: Square) {
+        match self {
+            Self::Pesto(eval) => eval.remove_piece(piece, colour, square),
+            Self::Nnue(nnue) => nnue.remove_piece(piece, colour, square),
+        }
+    }
+
+    fn move_piece(&mut self, piece: Piece, colour: Colour, from: Square, dest: Square) {
+        match self {
+            Self::Pesto(eval) => eval.move_piece(piece, colour, from, dest),
+            Self::Nnue(nnue) => nnue.move_piece(piece, colour, from, dest),
+        }
+    }
+}
+
 /// The main engine state
 #[derive(Clone)]
 pub struct Yukari {
@@ -29,14 +85,40 @@ pub struct Yukari {
     zobrist: Zobrist,
     keystack: Vec<u64>,
     corrhist: [[i32; 16384]; 2],
-    params: SearchParams
+    params: SearchParams,
+    protocol: Protocol,
+    /// Maximum depth to search to
+    depth_limit: i32,
+    /// Maximum number of nodes to search, if any
+    node_limit: Option<u64>,
+    /// Number of threads to search with
+    threads: usize,
+    /// Correction history for each helper thread, kept between searches like the main thread's
+    helper_corrhist: Vec<Box<[[i32; 16384]; 2]>>,
+    /// Opening book to play from before searching, if any
+    book: Option<Arc<Book>>,
+    /// How to pick among the book moves of a position
+    book_selection: BookSelection,
+    /// Network to evaluate with, if one is built in or has been loaded
+    network: Option<&'static Network>,
+    /// Whether to evaluate with `network` rather than the hand-crafted evaluation
+    use_nnue: bool,
+    /// Weights of the hand-crafted evaluation
+    eval_params: &'static EvalParams,
 }
 
+/// Most threads we will search with
+const MAX_THREADS: usize = 256;
+
+/// Stack size for helper search threads, which recurse as deeply as the main thread
+const SEARCH_STACK_SIZE: usize = 16 * 1024 * 1024;
+
 impl Yukari {
     /// Create a new copy of the engine, starting with the typical position and unused time controls
     #[must_use]
     pub fn new() -> Self {
-        let zobrist = Zobrist::new();
+        // Polyglot keys make our hashes usable as opening book keys
+        let zobrist = Zobrist::polyglot();
         Self {
             // Using startpos fixes knights
             board: Board::startpos(&zobrist),
@@ -48,15 +130,114 @@ impl Yukari {
             keystack: Vec::new(),
             corrhist: [[0; 16384]; 2],
             params: SearchParams::default(),
+            protocol: Protocol::Xboard,
+            depth_limit: 99,
+            node_limit: None,
+            threads: 1,
+            helper_corrhist: Vec::new(),
+            book: None,
+            book_selection: BookSelection::default(),
+            network: built_in_network(),
+            use_nnue: built_in_network().is_some(),
+            eval_params: &EvalParams::DEFAULT,
         }
     }
 
-    /// Sets the game board from FEN notation
-    /// # Panics
-    /// Panics when invalid FEN is input.
-    pub fn set_board(&mut self, s: &str) {
-        self.board = Board::from_fen(s, &self.zobrist).unwrap();
+    /// Reset the game state for a new game, keeping the protocol and search parameters the GUI set up
+    pub fn new_game(&mut self) {
+        let params = self.params.clone();
+        let protocol = self.protocol;
+        let threads = self.threads;
+        let book = self.book.take();
+        let book_selection = self.book_selection;
+        let network = self.network;
+        let use_nnue = self.use_nnue;
+        let eval_params = self.eval_params;
+        *self = Self::new();
+        self.params = params;
+        self.protocol = protocol;
+        self.threads = threads;
+        self.book = book;
+        self.book_selection = book_selection;
+        self.network = network;
+        self.use_nnue = use_nnue;
+        self.eval_params = eval_params;
+    }
+
+    /// Set a search parameter from an integer option value, returning false if there is no such option
+    pub fn set_param(&mut self, name: &str, value: i32) -> bool {
+        if name == "QsearchChecks" {
+            self.params.qsearch_checks = value != 0;
+            return true;
+        }
+        self.params.set(name, value)
+    }
+
+    /// Load tunable search parameters from a file written by `yukari spsa`, or go back to the defaults if the path is
+    /// empty. Parameters that are not tunable are kept.
+    pub fn set_search_params_file(&mut self, path: &str) -> io::Result<()> {
+        let params = if path.is_empty() { SearchParams::default() } else { SearchParams::load(path)? };
+        self.params = SearchParams { qsearch_checks: self.params.qsearch_checks, ..params };
+        Ok(())
+    }
+
+    /// Set the number of threads to search with
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    /// Load a Polyglot opening book, or stop using one if the path is empty
+    pub fn set_book(&mut self, path: &str) -> io::Result<()> {
+        self.book = if path.is_empty() { None } else { Some(Arc::new(Book::open(path)?)) };
+        Ok(())
+    }
+
+    /// Load a network to evaluate with, or go back to the built-in one (if any) if the path is empty
+    pub fn set_eval_file(&mut self, path: &str) -> io::Result<()> {
+        // Networks are loaded rarely and searched with everywhere, so they live for the rest of the process
+        self.network = if path.is_empty() { built_in_network() } else { Some(Box::leak(Box::new(Network::load(path)?))) };
+        Ok(())
+    }
+
+    /// Load weights for the hand-crafted evaluation written by `yukari tune`, or go back to the built-in ones if the path
+    /// is empty
+    pub fn set_eval_params_file(&mut self, path: &str) -> io::Result<()> {
+        self.eval_params = if path.is_empty() { &EvalParams::DEFAULT } else { Box::leak(Box::new(EvalParams::load(path)?)) };
+        Ok(())
+    }
+
+    /// Switch between the network and the hand-crafted evaluation
+    pub fn set_use_nnue(&mut self, use_nnue: bool) {
+        self.use_nnue = use_nnue;
+    }
+
+    /// Load a network and evaluate with it, as the `eval-file` argument of the subcommands asks for. An empty path
+    /// evaluates with the built-in network if there is one.
+    pub fn use_eval_file(&mut self, path: &str) -> io::Result<()> {
+        self.set_eval_file(path)?;
+        self.use_nnue = self.network.is_some();
+        Ok(())
+    }
+
+    /// The evaluation to search with: the network if it is in use, otherwise the hand-crafted evaluation
+    fn evaluation(&self) -> Evaluation {
+        match self.network.filter(|_| self.use_nnue) {
+            Some(network) => Evaluation::Nnue(Nnue::new(network)),
+            None => Evaluation::Pesto(Eval::with_params(self.eval_params)),
+        }
+    }
+
+    /// Pick a move from the opening book for the game position, if it has any
+    #[must_use]
+    pub fn book_move(&self) -> Option<Move> {
+        self.book.as_ref()?.choose(&self.board, self.book_selection, &mut rand::thread_rng())
+    }
+
+    /// Sets the game board from FEN notation, leaving it untouched if the FEN is invalid
+    pub fn set_board(&mut self, s: &str) -> Result<(), FenError> {
+        self.board = Board::from_fen(s, &self.zobrist)?;
         self.keystack.clear();
+        Ok(())
     }
 
     /// Parses the two xboard time control setup commands and sets that as our controls
@@ -73,50 +254,122 @@ impl Yukari {
         self.tc.set_remaining(csec);
     }
 
-    /// Generates valid moves for current posiition then finds the attempted
-    /// move in the list
+    /// Parses a move in coordinate notation (e.g. `e2e4` or `e7e8q`) and finds it in the legal moves
     #[must_use]
-    pub fn find_move(&self, from: Square, dest: Square, prom: Option<Piece>) -> Option<Move> {
-        let moves: [Move; 256] = [Move::default(); 256];
-        let mut moves = ArrayVec::from(moves);
-        moves.set_len(0);
-        self.board.generate(&mut moves);
-        moves.into_iter().find(|&m| m.from == from && m.dest == dest && m.prom == prom)
+    pub fn parse_move(&self, s: &str) -> Option<Move> {
+        self.board.parse_uci_move(s).ok()
+    }
+
+    /// Tell the GUI if the game position is drawn by repetition or the fifty-move rule
+    pub fn claim_draw(&self) {
+        if is_repetition_draw(&self.keystack, self.board.hash(), self.board.halfmove_clock()) {
+            println!("1/2-1/2 {{Draw by repetition}}");
+        } else if self.board.halfmove_clock() >= 100 {
+            // Checkmate on the hundredth ply still wins.
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            self.board.generate(&mut moves);
+            if !moves.is_empty() || !self.board.in_check() {
+                println!("1/2-1/2 {{Draw by fifty move rule}}");
+            }
+        }
+    }
+
+    /// Make a move on the game board and record it for repetition detection
+    pub fn make_move(&mut self, m: Move) {
+        self.board = self.board.make(m, &self.zobrist);
+        self.keystack.push(self.board.hash());
     }
 
     /// Real search, falls back to dumb search in extreme time constraints
-    pub fn search(&mut self, best_pv: &mut ArrayVec<[Move; 32]>, tt: &mut [TtEntry]) {
+    ///
+    ///
+    /// With more than one thread, helper threads search the same position alongside the main
+    /// thread, sharing the TT, until the main thread finishes.
+    /// The search also stops early once `stop` is set.
+    pub fn search(&mut self, best_pv: &mut ArrayVec<[Move; 32]>, tt: &[TtEntry], stop: &AtomicBool) {
+        let comment = if self.protocol == Protocol::Uci { "info string" } else { "#" };
+        // Book moves are played without searching
+        if let Some(m) = self.book_move() {
+            println!("{comment} book move {m}");
+            best_pv.set_len(0);
+            best_pv.push(m);
+            return;
+        }
+
         let start = Instant::now();
-        let stop_after = start + Duration::from_secs_f32(self.tc.search_time());
-        let mut s = Search::new(Some(stop_after), &self.zobrist, tt, &mut self.corrhist, &self.params);
-        // clone another to use inside the loop
-        // Use a seperate backing data to record the current move set
-        let mut depth = 1;
-        let mut pv: ArrayVec<[Move; 32]> = ArrayVec::new();
-        while depth <= 99 {
-            pv.set_len(0);
-            // FIXME: We want to search one depth without time controls
-            let score = s.search_root(&self.board, depth, &mut pv, &mut self.keystack);
-            // If we have bailed out stop the loop
-            if Instant::now() >= stop_after {
-                break;
+        let stop_after = self.tc.search_time().map(|secs| start + Duration::from_secs_f32(secs.max(0.0)));
+        let stop_helpers = AtomicBool::new(false);
+        let helper_nodes = (1..self.threads).map(|_| AtomicU64::new(0)).collect::<Vec<_>>();
+        self.helper_corrhist.resize_with(self.threads - 1, || Box::new([[0; 16384]; 2]));
+        let evaluation = self.evaluation();
+
+        thread::scope(|scope| {
+            for (index, (corrhist, nodes)) in self.helper_corrhist.iter_mut().zip(&helper_nodes).enumerate() {
+                let index = index + 1;
+                let board = self.board.clone();
+                let mut keystack = self.keystack.clone();
+                let evaluation = evaluation.clone();
+                let (zobrist, params, stop_helpers, depth_limit) = (&self.zobrist, &self.params, &stop_helpers, self.depth_limit);
+                thread::Builder::new()
+                    .stack_size(SEARCH_STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        let mut s = Search::with_evaluator(None, zobrist, tt, corrhist, params, evaluation);
+                        s.set_stop_signal(stop_helpers);
+                        s.set_node_counter(nodes);
+                        s.set_thread_index(index);
+                        // Helpers skip depths on their own schedules, so the threads spread over several depths.
+                        let mut deepening = IterativeDeepening::new(depth_limit);
+                        deepening.set_helper_index(index);
+                        deepening.run(&mut s, &board, &mut keystack, |_| {});
+                    })
+                    .expect("failed to spawn search thread");
             }
-            // If we have a pv that's not just empty from bailing out use that as our best moves
-            best_pv.clone_from(&pv);
-            let now = Instant::now().duration_since(start);
-            print!("{} {:.2} {} {} ", depth, score, now.as_millis() / 10, s.nodes() + s.qnodes());
-            for m in &pv {
-                print!("{m} ");
+
+            let mut s = Search::with_evaluator(stop_after, &self.zobrist, tt, &mut self.corrhist, &self.params, evaluation);
+            s.set_node_limit(self.node_limit);
+            s.set_stop_signal(stop);
+            let protocol = self.protocol;
+            // FIXME: We want to search one depth without time controls
+            let last = IterativeDeepening::new(self.depth_limit).run(&mut s, &self.board, &mut self.keystack, |iteration| {
+                let Iteration { depth, score, ref pv, .. } = *iteration;
+                let now = Instant::now().duration_since(start);
+                let nodes = iteration.nodes + helper_nodes.iter().map(|nodes| nodes.load(Ordering::Relaxed)).sum::<u64>();
+                match protocol {
+                    Protocol::Xboard => print!("{} {} {} {} ", depth, score.to_xboard(), now.as_millis() / 10, nodes),
+                    Protocol::Uci => {
+                        let nps = (nodes as f64 / now.as_secs_f64().max(0.001)) as u64;
+                        print!("info depth {depth} score {} time {} nodes {nodes} nps {nps} pv ", score.to_uci(), now.as_millis());
+                    }
+                }
+                for m in pv {
+                    print!("{m} ");
+                }
+                println!();
+            });
+            stop_helpers.store(true, Ordering::Relaxed);
+            // Only completed iterations have a trustworthy PV.
+            let depth = last.as_ref().map_or(1, |last| last.depth + 1);
+            if let Some(last) = last {
+                best_pv.clone_from(&last.pv);
             }
-            println!();
-            depth += 1;
+
+            println!("{comment} QS: {:.3}%", (100 * s.qnodes()) as f64 / (s.nodes() as f64 + s.qnodes() as f64));
+            println!("{comment} Branching factor: {:.3}", ((s.nodes() + s.qnodes()) as f64).powf(1.0 / f64::from(depth)));
+        });
+
+        // If we were stopped before finishing the first iteration, any legal move beats none.
+        if best_pv.is_empty() {
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            self.board.generate(&mut moves);
+            best_pv.extend(moves.first().copied());
         }
-        println!("# QS: {:.3}%", (100 * s.qnodes()) as f64 / (s.nodes() as f64 + s.qnodes() as f64));
-        println!("# Branching factor: {:.3}", ((s.nodes() + s.qnodes()) as f64).powf(1.0 / f64::from(depth)));
-        self.tc.increment_moves();
     }
 
-    fn bench(&mut self, tt: &mut [TtEntry]) {
+    fn bench(&mut self, tt: &[TtEntry]) {
         let fens = [
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
             "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
@@ -175,13 +428,13 @@ impl Yukari {
         for fen in fens {
             let zobrist = Zobrist::new();
             let board = Board::from_fen(fen, &zobrist).unwrap();
-            let mut s = Search::new(None, &zobrist, tt, &mut self.corrhist, &self.params);
+            let evaluation = self.evaluation();
+            let mut s = Search::with_evaluator(None, &zobrist, tt, &mut self.corrhist, &self.params, evaluation);
             let start = Instant::now();
             let mut keystack = Vec::new();
-            let mut pv = ArrayVec::new();
-            let score = s.search_root(&board, 8, &mut pv, &mut keystack);
+            let Iteration { score, pv, .. } = IterativeDeepening::new(8).run(&mut s, &board, &mut keystack, |_| {}).unwrap_or_default();
             let now = Instant::now().duration_since(start);
-            print!("10 {score:.2} {} {} ", now.as_millis() / 10, s.nodes() + s.qnodes());
+            print!("10 {} {} {} ", score.to_xboard(), now.as_millis() / 10, s.nodes() + s.qnodes());
             for m in pv {
                 print!("{m} ");
             }
@@ -193,7 +446,7 @@ impl Yukari {
         println!("{nodes} nodes {nps} nps");
     }
 
-    fn nnue_label(&mut self, tt: &mut [TtEntry]) {
+    fn nnue_label(&mut self) {
         let input = File::open("quiescent_positions_with_results").unwrap();
         let output = File::create("labeled.txt").unwrap();
         let input = io::BufReader::new(input).lines().map_while(Result::ok).collect::<Vec<_>>();
@@ -211,14 +464,17 @@ impl Yukari {
                 let result = line.next().unwrap();
                 let fen = [board, stm, castling, ep].join(" ");
     
-                let board = Board::from_fen(&fen, &self.zobrist).unwrap();
-                let mut s = Search::new(None, &self.zobrist, &tt, &mut corrhist, &self.params);
+                let Ok(board) = Board::from_fen(&fen, &self.zobrist) else {
+                    println!("# skipping invalid position {fen}");
+                    continue;
+                };
+                let mut s = Search::with_evaluator(None, &self.zobrist, &tt, &mut corrhist, &self.params, self.evaluation());
                 let start = Instant::now();
                 let mut keystack = Vec::new();
                 let mut pv = ArrayVec::new();
                 let score = s.search_root(&board, 6, &mut pv, &mut keystack);
                 let now = Instant::now().duration_since(start);
-                print!("6 {score:.2} {} {} ", now.as_millis() / 10, s.nodes() + s.qnodes());
+                print!("6 {} {} {} ", score.to_xboard(), now.as_millis() / 10, s.nodes() + s.qnodes());
                 for m in pv {
                     print!("{m} ");
                 }
@@ -226,7 +482,7 @@ impl Yukari {
                 let score = if stm == "b" { -score } else { score };
                 let result = if result == "1-0" { "1.0" } else if result == "1/2-1/2" { "0.5" } else if result == "0-1" { "0.0" } else { panic!("unknown result {result}"); };
                 let mut output = output.lock().unwrap();
-                writeln!(output, "{fen} | {score} | {result}").unwrap()
+                writeln!(output, "{} | {} | {result}", board.to_fen(), score.get()).unwrap()
             }
         })
         .for_each(|_| ());
@@ -240,32 +496,743 @@ impl Default for Yukari {
 }
 
 fn main() -> io::Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("makebook") {
+        return make_book(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("datagen") {
+        return datagen(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("tune") {
+        return tune(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("spsa") {
+        return spsa(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("openbench") {
+        // The SPSA input for an OpenBench tune, to paste into its form
+        print!("{}", SearchParams::openbench());
+        return Ok(());
+    }
+
     let mut engine = Yukari::new();
-    let mut tt = allocate_tt(16);
+    let tt = allocate_tt(16);
 
-    for arg in std::env::args() {
+    for arg in args {
         if arg == "bench" {
-            engine.bench(&mut tt);
+            engine.bench(&tt);
             return Ok(());
         }
 
         if arg == "label" {
-            engine.nnue_label(&mut tt);
+            engine.nnue_label();
             return Ok(());
         }
     }
 
+    // The first command tells us which protocol the GUI speaks
     let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    if line.trim() == "uci" {
+        uci(engine, tt)
+    } else {
+        xboard(engine, tt, line)
+    }
+}
+
+/// Build a Polyglot opening book from a PGN file.
+///
+/// Usage: `yukari makebook <games.pgn> <book.bin> [max-ply] [min-games]`
+fn make_book(args: &[String]) -> io::Result<()> {
+    let [pgn, out, rest @ ..] = args else {
+        eprintln!("usage: yukari makebook <games.pgn> <book.bin> [max-ply] [min-games]");
+        return Ok(());
+    };
+    let max_ply = rest.first().map_or(Ok(16), |s| s.parse::<usize>()).expect("max-ply is not a number");
+    let min_games = rest.get(1).map_or(Ok(1), |s| s.parse::<u32>()).expect("min-games is not a number");
+
+    let zobrist = Zobrist::polyglot();
+    let mut builder = BookBuilder::new(&zobrist, max_ply, min_games);
+    let (mut games, mut skipped) = (0, 0);
+    for game in PgnReader::new(io::BufReader::new(File::open(pgn)?), &zobrist) {
+        match game {
+            Ok(game) => {
+                builder.add_game(&game);
+                games += 1;
+            }
+            Err(err) => {
+                eprintln!("skipping game: {err}");
+                skipped += 1;
+            }
+        }
+    }
+
+    let book = builder.build();
+    book.write(&mut io::BufWriter::new(File::create(out)?))?;
+    println!("{games} games read, {skipped} skipped, {} book entries written to {out}", book.len());
+    Ok(())
+}
+
+/// Generate training data from self-play games.
+///
+/// Usage: `yukari datagen [text=<file>] [binary=<file>] [option=value...]`, where the options are the fields of
+/// `DatagenConfig` in kebab case, plus `book` and `eval-file`. A node limit of 0 means none.
+fn datagen(args: &[String]) -> io::Result<()> {
+    let mut config = DatagenConfig::default();
+    let mut engine = Yukari::new();
+    let (mut text, mut binary) = (None, None);
+    for arg in args {
+        let Some((name, value)) = arg.split_once('=') else {
+            eprintln!("usage: yukari datagen [text=<file>] [binary=<file>] [option=value...]");
+            return Ok(());
+        };
+        let number = || value.parse::<u64>().unwrap_or_else(|_| panic!("{name} is not a number"));
+        let flag = || value == "true" || value == "1";
+        match name {
+            "text" => text = Some(io::BufWriter::new(File::create(value)?)),
+            "binary" => binary = Some(io::BufWriter::new(File::create(value)?)),
+            "book" => engine.set_book(value)?,
+            "eval-file" => engine.use_eval_file(value)?,
+            "games" => config.games = number(),
+            "threads" => config.threads = number() as usize,
+            "hash" => config.hash_mb = number() as usize,
+            "nodes" => config.nodes = Some(number()).filter(|&nodes| nodes > 0),
+            "depth" => config.depth = number() as i32,
+            "random-plies" => config.random_plies = number() as usize,
+            "seed" => config.seed = number(),
+            "resign-score" => config.resign_score = number() as i32,
+            "resign-plies" => config.resign_plies = number() as u32,
+            "draw-score" => config.draw_score = number() as i32,
+            "draw-plies" => config.draw_plies = number() as u32,
+            "draw-min-ply" => config.draw_min_ply = number() as u32,
+            "win-on-mate" => config.win_on_mate = flag(),
+            "skip-in-check" => config.skip_in_check = flag(),
+            "skip-noisy" => config.skip_noisy = flag(),
+            "skip-mates" => config.skip_mates = flag(),
+            _ => {
+                eprintln!("unknown datagen option {name}");
+                return Ok(());
+            }
+        }
+    }
+    if text.is_none() && binary.is_none() {
+        eprintln!("datagen needs text=<file>, binary=<file> or both");
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let (stats, _) =
+        yukari::datagen::generate(&config, &engine.params, &engine.evaluation(), engine.book.as_deref(), DataWriter::new(text, binary))?;
+    let secs = start.elapsed().as_secs_f64();
+    println!("{} games, {} positions in {secs:.1}s", stats.games, stats.positions);
+    Ok(())
+}
+
+/// Tune the hand-crafted evaluation's weights on labeled positions, writing them as a replacement `eval_tables.rs`.
+///
+/// Usage: `yukari tune <positions> <tables.rs> [option=value...]`, where the options are `optimizer` (`adam` or
+/// `gauss-newton`), `epochs`, `rate` (Adam's learning rate), `lambda` (how much the results count against the search
+/// scores), `k` (to skip fitting it), `threads` and `start` (weights to start from instead of the built-in ones).
+fn tune(args: &[String]) -> io::Result<()> {
+    let usage = "usage: yukari tune <positions> <tables.rs> [option=value...]";
+    let [positions, out, rest @ ..] = args else {
+        eprintln!("{usage}");
+        return Ok(());
+    };
+    let (mut optimizer, mut epochs, mut rate, mut lambda, mut k, mut threads) = ("adam", None, 1.0, 1.0, None, 0);
+    let mut start = EvalParams::DEFAULT;
+    for arg in rest {
+        let Some((name, value)) = arg.split_once('=') else {
+            eprintln!("{usage}");
+            return Ok(());
+        };
+        let number = || value.parse::<f64>().unwrap_or_else(|_| panic!("{name} is not a number"));
+        match name {
+            "optimizer" if value == "adam" || value == "gauss-newton" => optimizer = value,
+            "epochs" => epochs = Some(number() as usize),
+            "rate" => rate = number(),
+            "lambda" => lambda = number(),
+            "k" => k = Some(number()),
+            "threads" => threads = number() as usize,
+            "start" => start = EvalParams::load(value)?,
+            _ => {
+                eprintln!("unknown or invalid tune option {arg}");
+                return Ok(());
+            }
+        }
+    }
+
+    // Zero threads lets rayon use every core.
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().map_err(io::Error::other)?;
+    pool.install(|| {
+        let start_time = Instant::now();
+        let positions = tune::load_positions(io::BufReader::new(File::open(positions)?), &start.phase)?;
+        println!("# loaded {} positions in {:.1}s", positions.len(), start_time.elapsed().as_secs_f64());
+
+        let mut tuner = Tuner::new(positions, &start, lambda);
+        match k {
+            Some(k) => tuner.set_k(k),
+            None => println!("# fitted K = {:.4}", tuner.fit_k(0.0..10.0)),
+        }
+        println!("# starting error {:.6}", tuner.error());
+
+        let report = |step: usize, tuner: &Tuner| {
+            println!("{step} {:.6} {:.1}s", tuner.error(), start_time.elapsed().as_secs_f64());
+        };
+        if optimizer == "adam" {
+            tuner.adam(epochs.unwrap_or(1000), rate, |epoch, tuner| {
+                if epoch % 50 == 0 {
+                    report(epoch, tuner);
+                }
+            });
+        } else {
+            tuner.gauss_newton(epochs.unwrap_or(10), report);
+        }
+
+        std::fs::write(out, tuner.params().to_rust())?;
+        println!("# final error {:.6}, weights written to {out}", tuner.error());
+        Ok(())
+    })
+}
+
+/// Tune the search parameters by SPSA over self-play matches, writing them to a parameter file after each iteration.
+///
+/// Usage: `yukari spsa <params.txt> [option=value...]`, where the options are the fields of `SpsaConfig` in kebab case,
+/// plus `book`, `eval-file` and `start` (a parameter file to start from instead of the defaults). A node limit of 0
+/// means none.
+fn spsa(args: &[String]) -> io::Result<()> {
+    let usage = "usage: yukari spsa <params.txt> [option=value...]";
+    let [out, rest @ ..] = args else {
+        eprintln!("{usage}");
+        return Ok(());
+    };
+    let mut config = SpsaConfig::default();
+    let mut engine = Yukari::new();
+    for arg in rest {
+        let Some((name, value)) = arg.split_once('=') else {
+            eprintln!("{usage}");
+            return Ok(());
+        };
+        let number = || value.parse::<f64>().unwrap_or_else(|_| panic!("{name} is not a number"));
+        match name {
+            "book" => engine.set_book(value)?,
+            "eval-file" => engine.use_eval_file(value)?,
+            "start" => engine.set_search_params_file(value)?,
+            "iterations" => config.iterations = number() as u64,
+            "pairs" => config.pairs = number() as u64,
+            "threads" => config.threads = number() as usize,
+            "hash" => config.hash_mb = number() as usize,
+            "nodes" => config.nodes = Some(number() as u64).filter(|&nodes| nodes > 0),
+            "random-plies" => config.random_plies = number() as usize,
+            "seed" => config.seed = number() as u64,
+            "r-end" => config.r_end = number(),
+            "a-ratio" => config.a_ratio = number(),
+            "alpha" => config.alpha = number(),
+            "gamma" => config.gamma = number(),
+            "resign-score" => config.resign_score = number() as i32,
+            "resign-plies" => config.resign_plies = number() as u32,
+            "max-plies" => config.max_plies = number() as u32,
+            _ => {
+                eprintln!("unknown spsa option {name}");
+                return Ok(());
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let mut spsa = Spsa::new(config, &engine.params, engine.evaluation(), engine.book.as_deref())?;
+    let mut total = 0.0;
+    let mut saved = Ok(());
+    spsa.run(|iteration, result, spsa| {
+        total += result;
+        println!("{iteration} {result:+} {total:+} {:.1}s", start.elapsed().as_secs_f64());
+        if saved.is_ok() {
+            saved = spsa.params().save(out);
+        }
+    });
+    saved?;
+    println!("# parameters written to {out}");
+    print!("{}", spsa.params());
+    Ok(())
+}
+
+/// The network built into the binary, if any
+fn built_in_network() -> Option<&'static Network> {
+    #[cfg(feature = "embedded-nnue")]
+    return Some(Network::embedded());
+    #[cfg(not(feature = "embedded-nnue"))]
+    None
+}
+
+/// Loads the file an option names into the engine
+type LoadFile = fn(&mut Yukari, &str) -> io::Result<()>;
+
+/// The options that load a file, or go back to the built-in data when set to an empty path: the option name, what the
+/// file holds and how to load it
+const FILE_OPTIONS: [(&str, &str, LoadFile); 4] = [
+    ("BookFile", "book", Yukari::set_book),
+    ("EvalFile", "network", Yukari::set_eval_file),
+    ("EvalParamsFile", "evaluation weights", Yukari::set_eval_params_file),
+    ("SearchParamsFile", "search parameters", Yukari::set_search_params_file),
+];
+
+/// Print the UCI identification and option list
+fn uci_identify() {
+    println!("id name Yukari 20072021");
+    println!("id author Yukari Chess");
+    println!("option name Hash type spin default 16 min 1 max 65536");
+    println!("option name Threads type spin default 1 min 1 max {MAX_THREADS}");
+    println!("option name BookFile type string default <empty>");
+    println!("option name BookSelection type combo default weighted var best var weighted var random");
+    println!("option name EvalFile type string default <empty>");
+    println!("option name EvalParamsFile type string default <empty>");
+    println!("option name UseNNUE type check default {}", built_in_network().is_some());
+    println!("option name SearchParamsFile type string default <empty>");
+    // Tunables!
+    for tunable in SearchParams::TUNABLES {
+        println!("option name {} type spin default {} min {} max {}", tunable.name, tunable.default, tunable.min, tunable.max);
+    }
+    println!("option name QsearchChecks type check default false");
+    println!("uciok");
+}
+
+
+/// What a search running on the worker thread is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SearchKind {
+    /// Searching for our move, which is reported as soon as the search finishes
+    Think,
+    /// Searching on the opponent's time, in the position after the move we expect them to play
+    /// (UCI GUIs make that guess themselves, so there is no move to check against)
+    Ponder(Option<Move>),
+    /// Searching until told to stop, as in UCI `go infinite`
+    Infinite,
+}
+
+/// A search running on the worker thread
+struct RunningSearch {
+    kind: SearchKind,
+    stop: Arc<AtomicBool>,
+    /// When to raise the stop flag, for searches that began as ponder searches
+    deadline: Option<Instant>,
+    /// The principal variation, once the worker has finished
+    pv: Option<ArrayVec<[Move; 32]>>,
+}
+
+/// Messages to the protocol loop from the stdin reader and the search worker
+enum Message {
+    Input(String),
+    InputClosed,
+    SearchDone(Box<Yukari>, ArrayVec<[Move; 32]>),
+}
+
+/// Something the protocol loop has to react to
+enum Event {
+    /// A line from the GUI
+    Input(String),
+    /// The GUI closed our input
+    InputClosed,
+    /// The running search has finished, and its result can be taken
+    SearchDone,
+}
+
+/// Runs searches on a worker thread while the protocol loop keeps reading commands
+struct Controller {
+    engine: Yukari,
+    tt: Arc<Vec<TtEntry>>,
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    /// Lines that arrived while we were waiting for a search to stop, in order
+    backlog: VecDeque<String>,
+    input_closed: bool,
+    search: Option<RunningSearch>,
+}
+
+impl Controller {
+    fn new(engine: Yukari, tt: Vec<TtEntry>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let input = sender.clone();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if input.send(Message::Input(line)).is_err() {
+                    return;
+                }
+            }
+            let _ = input.send(Message::InputClosed);
+        });
+        Self { engine, tt: Arc::new(tt), sender, receiver, backlog: VecDeque::new(), input_closed: false, search: None }
+    }
+
+    /// Replace the transposition table, stopping any search using the old one
+    fn resize_tt(&mut self, megabytes: usize) {
+        self.stop_search();
+        self.tt = Arc::new(allocate_tt(megabytes));
+    }
+
+    /// Search the position of `worker` on the worker thread
+    fn start_search(&mut self, kind: SearchKind, mut worker: Yukari) {
+        assert!(self.search.is_none(), "search already running");
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tt, sender, worker_stop) = (Arc::clone(&self.tt), self.sender.clone(), Arc::clone(&stop));
+        thread::Builder::new()
+            .stack_size(SEARCH_STACK_SIZE)
+            .spawn(move || {
+                let mut pv = ArrayVec::new();
+                worker.search(&mut pv, &tt, &worker_stop);
+                let _ = sender.send(Message::SearchDone(Box::new(worker), pv));
+            })
+            .expect("failed to spawn search thread");
+        self.search = Some(RunningSearch { kind, stop, deadline: None, pv: None });
+    }
+
+    /// Search for our move in the current position
+    fn think(&mut self) {
+        self.start_search(SearchKind::Think, self.engine.clone());
+    }
+
+    /// The kind of search running or waiting to be collected, if any
+    fn search_kind(&self) -> Option<SearchKind> {
+        self.search.as_ref().map(|search| search.kind)
+    }
+
+    /// True while searching for our move
+    fn thinking(&self) -> bool {
+        self.search.as_ref().is_some_and(|search| search.kind == SearchKind::Think && search.pv.is_none())
+    }
+
+    /// Ask the running search to stop, without waiting for it
+    fn move_now(&self) {
+        if let Some(search) = &self.search {
+            search.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Turn a ponder search into a search for our move, with this many seconds left to think
+    fn ponder_hit(&mut self, time: Option<f32>) {
+        if let Some(search) = &mut self.search {
+            search.kind = SearchKind::Think;
+            search.deadline = time.map(|secs| Instant::now() + Duration::from_secs_f32(secs.max(0.0)));
+        }
+    }
+
+    /// Keep the tables the worker learned, and record its result
+    fn collect(&mut self, worker: Box<Yukari>, pv: ArrayVec<[Move; 32]>) {
+        self.engine.corrhist = worker.corrhist;
+        self.engine.helper_corrhist = worker.helper_corrhist;
+        if let Some(search) = &mut self.search {
+            search.pv = Some(pv);
+        }
+    }
+
+    /// Take the result of a finished search
+    fn take_result(&mut self) -> Option<(SearchKind, ArrayVec<[Move; 32]>)> {
+        let search = self.search.take_if(|search| search.pv.is_some())?;
+        Some((search.kind, search.pv?))
+    }
+
+    /// Stop any running search and wait for it, returning its result
+    fn stop_search(&mut self) -> Option<(SearchKind, ArrayVec<[Move; 32]>)> {
+        self.move_now();
+        while self.search.as_ref().is_some_and(|search| search.pv.is_none()) {
+            match self.receiver.recv().expect("input thread went away") {
+                Message::Input(line) => self.backlog.push_back(line),
+                Message::InputClosed => self.input_closed = true,
+                Message::SearchDone(worker, pv) => self.collect(worker, pv),
+            }
+        }
+        self.take_result()
+    }
+
+    /// Wait for the next line of input or the end of the running search
+    fn next_event(&mut self) -> Event {
+        if let Some(line) = self.backlog.pop_front() {
+            return Event::Input(line);
+        }
+        // A search for our move still gets to finish, so piped input sees its result
+        if self.input_closed && !self.thinking() {
+            return Event::InputClosed;
+        }
+        loop {
+            let deadline = self.search.as_ref().and_then(|search| search.deadline);
+            let message = match deadline {
+                Some(deadline) => match self.receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.move_now();
+                        if let Some(search) = &mut self.search {
+                            search.deadline = None;
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => panic!("input thread went away"),
+                },
+                None => self.receiver.recv().expect("input thread went away"),
+            };
+            return match message {
+                Message::Input(line) => Event::Input(line),
+                Message::InputClosed => {
+                    self.input_closed = true;
+                    if self.thinking() {
+                        continue;
+                    }
+                    Event::InputClosed
+                }
+                Message::SearchDone(worker, pv) => {
+                    self.collect(worker, pv);
+                    Event::SearchDone
+                }
+            };
+        }
+    }
+}
+
+/// Print the UCI report of the best move, with the move we expect in reply to ponder on
+fn uci_bestmove(pv: &ArrayVec<[Move; 32]>) {
+    match (pv.first(), pv.get(1)) {
+        (Some(m), Some(reply)) => println!("bestmove {m} ponder {reply}"),
+        (Some(m), None) => println!("bestmove {m}"),
+        (None, _) => println!("bestmove 0000"),
+    }
+}
+
+/// The UCI main loop, entered once the GUI has sent `uci`
+fn uci(mut engine: Yukari, tt: Vec<TtEntry>) -> io::Result<()> {
+    engine.protocol = Protocol::Uci;
+    let mut hash_megabytes = 16;
+    uci_identify();
+
+    let mut ctl = Controller::new(engine, tt);
     loop {
-        line.clear();
-        let count = io::stdin().read_line(&mut line)?;
-        if count == 0 {
-            println!("# got zero read");
-            continue;
+        let line = match ctl.next_event() {
+            Event::Input(line) => line,
+            // The GUI went away without sending quit
+            Event::InputClosed => break,
+            Event::SearchDone => {
+                // Infinite and ponder searches hold their result until the GUI asks for it
+                if ctl.search_kind() == Some(SearchKind::Think) {
+                    if let Some((_, pv)) = ctl.take_result() {
+                        uci_bestmove(&pv);
+                    }
+                }
+                continue;
+            }
+        };
+        let trimmed = line.trim();
+        let (cmd, args) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+
+        // Every go gets a bestmove, even if the GUI changes the position without stopping the search first
+        if !matches!(cmd, "isready" | "stop" | "ponderhit" | "d" | "") {
+            if let Some((_, pv)) = ctl.stop_search() {
+                uci_bestmove(&pv);
+            }
         }
+
+        match cmd {
+            "uci" => uci_identify(),
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                ctl.engine.new_game();
+                ctl.resize_tt(hash_megabytes);
+            }
+            "setoption" => {
+                let Some(args) = args.strip_prefix("name ") else {
+                    println!("info string malformed setoption: {trimmed}");
+                    continue;
+                };
+                let (name, value) = args.split_once(" value ").unwrap_or((args, ""));
+                let value = value.trim();
+                if let Some((_, what, load)) = FILE_OPTIONS.iter().find(|(option, ..)| *option == name) {
+                    let path = if value == "<empty>" { "" } else { value };
+                    if let Err(err) = load(&mut ctl.engine, path) {
+                        println!("info string cannot read {what} {path}: {err}");
+                    }
+                    continue;
+                }
+                if name == "BookSelection" {
+                    match value.parse() {
+                        Ok(selection) => ctl.engine.book_selection = selection,
+                        Err(()) => println!("info string invalid value for option {name}"),
+                    }
+                    continue;
+                }
+                // Check options are switched on and off by name.
+                let value = match value {
+                    "true" => "1",
+                    "false" => "0",
+                    value => value,
+                };
+                let Ok(value) = value.parse::<i32>() else {
+                    println!("info string invalid value for option {name}");
+                    continue;
+                };
+                if name == "Hash" {
+                    hash_megabytes = value.max(1) as usize;
+                    ctl.resize_tt(hash_megabytes);
+                } else if name == "Threads" {
+                    ctl.engine.set_threads(value.max(1) as usize);
+                } else if name == "UseNNUE" {
+                    ctl.engine.set_use_nnue(value != 0);
+                } else if !ctl.engine.set_param(name, value) {
+                    println!("info string unknown option {name}");
+                }
+            }
+            "position" => {
+                let engine = &mut ctl.engine;
+                let (position, moves) = args.split_once(" moves").unwrap_or((args, ""));
+                if position == "startpos" {
+                    engine.board = Board::startpos(&engine.zobrist);
+                    engine.keystack.clear();
+                } else if let Some(fen) = position.strip_prefix("fen ") {
+                    if let Err(err) = engine.set_board(fen) {
+                        println!("info string illegal position: {err}");
+                        continue;
+                    }
+                } else {
+                    println!("info string malformed position: {trimmed}");
+                    continue;
+                }
+                for m in moves.split_whitespace() {
+                    let Some(m) = engine.parse_move(m) else {
+                        println!("info string illegal move {m}");
+                        break;
+                    };
+                    engine.make_move(m);
+                }
+            }
+            "go" => {
+                let engine = &mut ctl.engine;
+                let mut tokens = args.split_whitespace();
+                let (mut wtime, mut btime, mut winc, mut binc) = (None, None, 0, 0);
+                let (mut movestogo, mut movetime) = (None, None);
+                let (mut ponder, mut infinite) = (false, false);
+                engine.depth_limit = 99;
+                engine.node_limit = None;
+                while let Some(token) = tokens.next() {
+                    let mut value = || tokens.next().and_then(|value| value.parse::<u64>().ok());
+                    match token {
+                        "wtime" => wtime = value(),
+                        "btime" => btime = value(),
+                        "winc" => winc = value().unwrap_or(0),
+                        "binc" => binc = value().unwrap_or(0),
+                        "movestogo" => movestogo = value(),
+                        "movetime" => movetime = value(),
+                        "depth" => engine.depth_limit = value().map_or(99, |depth| depth.min(99) as i32),
+                        "nodes" => engine.node_limit = value(),
+                        "ponder" => ponder = true,
+                        "infinite" => infinite = true,
+                        _ => {}
+                    }
+                }
+
+                let (time, inc) = if engine.board.side() == Colour::White { (wtime, winc) } else { (btime, binc) };
+                engine.tc = if let Some(movetime) = movetime {
+                    TimeControl::new(TimeMode::MoveTime(movetime as u32))
+                } else if let Some(time) = time {
+                    TimeControl::from_uci(time as u32, inc as u32, movestogo.map(|moves| moves as u32))
+                } else {
+                    // Infinite is the default when no time is given
+                    TimeControl::new(TimeMode::Infinite)
+                };
+
+                let mut worker = engine.clone();
+                if ponder {
+                    // The clock only starts on ponderhit
+                    worker.tc = TimeControl::new(TimeMode::Infinite);
+                    ctl.start_search(SearchKind::Ponder(None), worker);
+                } else if infinite {
+                    worker.tc = TimeControl::new(TimeMode::Infinite);
+                    ctl.start_search(SearchKind::Infinite, worker);
+                } else {
+                    ctl.start_search(SearchKind::Think, worker);
+                }
+            }
+            "stop" => {
+                if let Some((_, pv)) = ctl.stop_search() {
+                    uci_bestmove(&pv);
+                }
+            }
+            "ponderhit" => {
+                if let Some(SearchKind::Ponder(_)) = ctl.search_kind() {
+                    ctl.ponder_hit(ctl.engine.tc.search_time());
+                    // A ponder search that already finished can answer straight away
+                    if let Some((_, pv)) = ctl.take_result() {
+                        uci_bestmove(&pv);
+                    }
+                }
+            }
+            "d" => println!("{}", ctl.engine.board),
+            "quit" => break,
+            "" => {}
+            _ => println!("info string unknown command: {trimmed}"),
+        }
+    }
+    ctl.stop_search();
+    Ok(())
+}
+
+/// Play the move our search found, and start pondering on the reply it expects if asked to
+fn xboard_play(ctl: &mut Controller, pv: &ArrayVec<[Move; 32]>, ponder: bool) {
+    // No move means the game is already over
+    let Some(&m) = pv.first() else { return };
+    let engine = &mut ctl.engine;
+    // We must actually make the move locally too
+    engine.board = engine.board.make(m, &engine.zobrist);
+    println!("move {m}");
+    engine.claim_draw();
+    engine.keystack.push(engine.board.hash());
+    engine.tc.increment_moves();
+
+    if let (true, Some(&guess)) = (ponder, pv.get(1)) {
+        let mut worker = engine.clone();
+        worker.board = worker.board.make(guess, &worker.zobrist);
+        worker.keystack.push(worker.board.hash());
+        worker.tc = TimeControl::new(TimeMode::Infinite);
+        ctl.start_search(SearchKind::Ponder(Some(guess)), worker);
+    }
+}
+
+/// The xboard main loop, starting from the first line the GUI sent
+fn xboard(engine: Yukari, tt: Vec<TtEntry>, first_line: String) -> io::Result<()> {
+    let mut ctl = Controller::new(engine, tt);
+    ctl.backlog.push_back(first_line);
+    // Whether to think on the opponent's time
+    let mut ponder = false;
+    // Pings that arrived while we were thinking, answered once our move is out
+    let mut pongs = Vec::new();
+
+    loop {
+        let line = match ctl.next_event() {
+            Event::Input(line) => line,
+            Event::InputClosed => break,
+            Event::SearchDone => {
+                // A ponder search that finishes early waits for the opponent's move
+                if ctl.search_kind() == Some(SearchKind::Think) {
+                    if let Some((_, pv)) = ctl.take_result() {
+                        xboard_play(&mut ctl, &pv, ponder);
+                    }
+                    for pong in pongs.drain(..) {
+                        println!("pong {pong}");
+                    }
+                }
+                continue;
+            }
+        };
         let trimmed = line.trim();
         let (cmd, args) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
 
+        // Most commands change what we would be searching, so finish with the old search first
+        #[allow(clippy::match_same_arms)]
+        let keeps_search = match cmd {
+            "xboard" | "protover" | "accepted" | "rejected" | "random" | "computer" | "post" | "nopost" | "d" | "" => true,
+            "ping" | "time" | "otim" | "?" | "hard" | "bk" => true,
+            _ => ctl.engine.board.parse_uci_move(trimmed).or_else(|_| ctl.engine.board.parse_san(trimmed)).is_ok(),
+        };
+        if !keeps_search {
+            ctl.stop_search();
+        }
+
+        let engine = &mut ctl.engine;
         #[allow(clippy::match_same_arms)]
         match cmd {
             // Identification for engines that auto switch between protocols
@@ -288,64 +1255,104 @@ fn main() -> io::Result<()> {
                 println!("feature debug=1");
                 // We support hash table allocation sizing.
                 println!("feature memory=1");
+                // We can search with multiple threads.
+                println!("feature smp=1");
+                // Opening book settings.
+                println!("feature option=\"BookFile -file \"");
+                println!("feature option=\"BookSelection -combo best /// *weighted /// random\"");
+                // Evaluation settings.
+                println!("feature option=\"EvalFile -file \"");
+                println!("feature option=\"EvalParamsFile -file \"");
+                println!("feature option=\"UseNNUE -check {}\"", u8::from(built_in_network().is_some()));
+                // Search settings.
+                println!("feature option=\"SearchParamsFile -file \"");
                 // Tunables!
-                println!("feature option=\"RfpMarginBase -spin 0 0 100\"");
-                println!("feature option=\"RfpMarginMul -spin 75 0 1000\"");
-                println!("feature option=\"LmrBase -spin 100 0 500\"");
-                println!("feature option=\"LmrMul -spin 500 0 2000\"");
-                println!("feature option=\"HistBonusBase -spin 250 0 500\"");
-                println!("feature option=\"HistBonusMul -spin 300 0 600\"");
-                println!("feature option=\"HistPenaltyBase -spin 250 0 500\"");
-                println!("feature option=\"HistPenaltyMul -spin 300 0 600\"");
+                for tunable in SearchParams::TUNABLES {
+                    println!("feature option=\"{} -spin {} {} {}\"", tunable.name, tunable.default, tunable.min, tunable.max);
+                }
+                println!("feature option=\"QsearchChecks -check 0\"");
                 // Communicate that feature reporting is done
                 println!("feature done=1");
             }
             // Directly update the engine's board from a FEN
-            "setboard" => engine.set_board(args),
+            "setboard" => {
+                if let Err(err) = engine.set_board(args) {
+                    println!("tellusererror Illegal position: {err}");
+                }
+            }
             // Reset the entire state of the engine
-            "new" => engine = Yukari::new(),
+            "new" => engine.new_game(),
             // Parse our two time controls from the whole commmand lines
             // TODO: This is rather xboard specific
             "level" | "st" => engine.parse_tc(trimmed),
             // Allocate a hash table.
-            "memory" => {
-                let megabytes = args.parse::<usize>().unwrap();
-                tt = allocate_tt(megabytes);
-            }
+            "memory" => ctl.resize_tt(args.parse::<usize>().unwrap()),
+            // Set the number of search threads.
+            "cores" => engine.set_threads(args.parse::<usize>().unwrap()),
             "option" => {
                 let (name, value) = args.split_once("=").unwrap();
-                let value = value.parse::<i32>().unwrap();
+                if let Some((_, what, load)) = FILE_OPTIONS.iter().find(|(option, ..)| *option == name) {
+                    if let Err(err) = load(engine, value) {
+                        println!("tellusererror Cannot read {what} {value}: {err}");
+                    }
+                    continue;
+                }
                 match name {
-                    "RfpMarginBase" => engine.params.rfp_margin_base = value,
-                    "RfpMarginMul" => engine.params.rfp_margin_mul = value,
-                    "LmrBase" => engine.params.lmr_base = (value as f32) / 100.0,
-                    "LmrMul" => engine.params.lmr_mul = (value as f32) / 1000.0,
-                    "HistBonusBase" => engine.params.hist_bonus_base = value,
-                    "HistBonusMul" => engine.params.hist_bonus_mul = value,
-                    "HistPenaltyBase" => engine.params.hist_pen_base = value,
-                    "HistPenaltyMul" => engine.params.hist_pen_mul = value,
-                    _ => (),
+                    "BookSelection" => match value.parse() {
+                        Ok(selection) => engine.book_selection = selection,
+                        Err(()) => println!("tellusererror Invalid value for option {name}: {value}"),
+                    },
+                    "UseNNUE" => engine.set_use_nnue(value == "1"),
+                    _ => {
+                        engine.set_param(name, value.parse::<i32>().unwrap());
+                    }
+                }
+            }
+            // List the book moves of the current position
+            "bk" => {
+                let moves = engine.book.as_ref().map(|book| book.moves(&engine.board)).unwrap_or_default();
+                let total = moves.iter().map(|&(_, weight)| u32::from(weight)).sum::<u32>().max(1);
+                if moves.is_empty() {
+                    println!("\tNo book moves");
+                }
+                for (m, weight) in moves {
+                    let san = engine.board.move_to_san(m, &engine.zobrist);
+                    println!("\t{san} {}%", u32::from(weight) * 100 / total);
                 }
+                println!();
             }
-            // Hard would turn on thinking during opponent's time, easy would turn it off
-            // we don't do it, so it's unimportant
-            "hard" | "easy" => {}
+            // Hard turns on thinking during the opponent's time, which starts after our next move
+            "hard" => ponder = true,
+            // Easy turns it off again; any ponder search was already stopped above
+            "easy" => ponder = false,
             "quit" => {
                 break;
             }
             // Feature replies are just ignored since we don't turn anything off yet
             // TODO: Handle rejects we can't tolerate and abort early
             "accepted" | "rejected" => {}
-            // Ping expects a response with the correct tag once the commands prior to the ping are done
-            // That ends up being some GPU fence level synchronization nonsense if it were to send more than one
-            // so for now we just "handle it" by replying with pong immediately. For now this "works" because
-            // the engine is single threaded such that moves can never be passed by other commands
-            "ping" => println!("pong {args}"),
+            // Ping expects a response with the correct tag once the commands prior to the ping are done.
+            // While we are thinking that includes sending our move, so the pong waits for it.
+            "ping" => {
+                if ctl.thinking() {
+                    pongs.push(args.to_string());
+                } else {
+                    println!("pong {args}");
+                }
+            }
+            // Move now: stop thinking and play the best move found so far
+            "?" => {
+                if ctl.thinking() {
+                    ctl.move_now();
+                }
+            }
             // TODO: Should support randomization so we don't always play the same game
             // we can't todo!() because we cannot turn off getting this message
             "random" => {}
             // We don't implement games against computer players games differently
             "computer" => {}
+            // Thinking output is always on
+            "post" | "nopost" => {}
             // This report gives us info about what time we have left right now directly
             // the value is in centiseconds
             "time" => engine.set_remaining(f32::from_str(args).unwrap()),
@@ -354,78 +1361,62 @@ fn main() -> io::Result<()> {
             "go" => {
                 engine.mode = Mode::Normal;
                 // When we get go we should make a move immediately
-                let mut pv = ArrayVec::new();
-                engine.search(&mut pv, &mut tt);
-                // Choose the top move
-                let m = pv[0];
-                // We must actually make the move locally too
-                engine.board = engine.board.make(m, &engine.zobrist);
-                println!("move {m}");
-                if is_repetition_draw(&engine.keystack, engine.board.hash()) {
-                    println!("1/2-1/2 {{Draw by repetition}}");
-                }
-                engine.keystack.push(engine.board.hash());
+                ctl.think();
             }
             "force" => engine.mode = Mode::Force,
             "d" => println!("{}", engine.board),
             _ => {
-                // Always ascii
-                let chars = trimmed.as_bytes();
-                if chars[1].is_ascii_digit() && chars[3].is_ascii_digit() {
-                    // This is actually a move
-                    let from = Square::from_str(&cmd[..2]).unwrap();
-                    let dest = Square::from_str(&cmd[2..4]).unwrap();
-                    let prom = if chars.len() == 5 {
-                        match chars[4] {
-                            b'n' => Some(Piece::Knight),
-                            b'b' => Some(Piece::Bishop),
-                            b'r' => Some(Piece::Rook),
-                            b'q' => Some(Piece::Queen),
-                            _ => None,
+                // xboard sends coordinate notation unless asked for SAN, but accept either
+                match engine.board.parse_uci_move(trimmed).or_else(|_| engine.board.parse_san(trimmed)) {
+                    Ok(m) => {
+                        // Pondering on any other move was wasted effort
+                        let force = matches!(engine.mode, Mode::Force);
+                        if force || ctl.search_kind() != Some(SearchKind::Ponder(Some(m))) {
+                            ctl.stop_search();
                         }
-                    } else {
-                        None
-                    };
-                    match engine.mode {
-                        Mode::Normal => {
-                            // Find the move in the list
-                            let m = engine.find_move(from, dest, prom).expect("Attempted move not found!?");
-                            engine.board = engine.board.make(m, &engine.zobrist);
-                            if is_repetition_draw(&engine.keystack, engine.board.hash()) {
-                                println!("1/2-1/2 {{Draw by repetition}}");
-                            }
-                            engine.keystack.push(engine.board.hash());
-                            // Find the next move to make
-                            // TODO: Cleanups
-                            let pv: [Move; 32] = [Move::default(); 32];
-                            let mut pv = ArrayVec::from(pv);
-                            pv.set_len(0);
-                            engine.search(&mut pv, &mut tt);
-                            // Choose the top move
-                            let m = pv[0];
-                            // We must actually make the move locally too
-                            engine.board = engine.board.make(m, &engine.zobrist);
-                            println!("move {m}");
-                            if is_repetition_draw(&engine.keystack, engine.board.hash()) {
-                                println!("1/2-1/2 {{Draw by repetition}}");
-                            }
-                            engine.keystack.push(engine.board.hash());
-                        }
-                        Mode::Force => {
-                            let m = engine.find_move(from, dest, prom).expect("Attempted move not found!?");
-                            engine.board = engine.board.make(m, &engine.zobrist);
-                            if is_repetition_draw(&engine.keystack, engine.board.hash()) {
-                                println!("1/2-1/2 {{Draw by repetition}}");
+
+                        let engine = &mut ctl.engine;
+                        engine.board = engine.board.make(m, &engine.zobrist);
+                        engine.claim_draw();
+                        engine.keystack.push(engine.board.hash());
+                        if matches!(engine.mode, Mode::Normal) {
+                            if ctl.search.is_some() {
+                                ctl.ponder_hit(ctl.engine.tc.search_time());
+                                // The ponder search may already have finished
+                                if let Some((_, pv)) = ctl.take_result() {
+                                    xboard_play(&mut ctl, &pv, ponder);
+                                }
+                            } else {
+                                // Find the next move to make
+                                ctl.think();
                             }
-                            engine.keystack.push(engine.board.hash());
                         }
                     }
-                } else {
-                    // This may look like I chose the format, but it is a standard response
-                    println!("Error (unknown command): {trimmed}");
+                    Err(ParseMoveError::Malformed) => {
+                        // This may look like I chose the format, but it is a standard response
+                        println!("Error (unknown command): {trimmed}");
+                    }
+                    Err(_) => println!("Illegal move: {trimmed}"),
                 }
             }
         }
     }
+    ctl.stop_search();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_file_is_searched_with() {
+        let path = std::env::temp_dir().join(format!("yukari-eval-file-{}.nnue", std::process::id()));
+        std::fs::write(&path, vec![0; Network::FILE_SIZE]).unwrap();
+        let mut engine = Yukari::new();
+        assert_eq!(matches!(engine.evaluation(), Evaluation::Nnue(_)), built_in_network().is_some());
+        engine.use_eval_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(engine.evaluation(), Evaluation::Nnue(_)));
+    }
+}
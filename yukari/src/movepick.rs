@@ -0,0 +1,165 @@
+use tinyvec::ArrayVec;
+use yukari_movegen::{Board, Move, MoveType, Piece};
+
+/// The stages of move picking, in the order they are tried.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    TtMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Refutations,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+/// Most Valuable Victim, Least Valuable Attacker: prefer taking big pieces with small ones.
+fn mvv_lva(board: &Board, m: Move) -> i32 {
+    let victim = if m.kind == MoveType::EnPassant { Piece::Pawn } else { board.piece_from_square(m.dest).unwrap_or(Piece::Pawn) };
+    let attacker = board.piece_from_square(m.from).unwrap_or(Piece::Pawn);
+    let promotion = m.prom.map_or(0, |prom| prom as i32);
+    8 * (victim as i32 + promotion) - attacker as i32
+}
+
+/// Yields the legal moves of a position best-first, generating and sorting them only as far as the search gets.
+///
+/// The stages are the transposition table move; captures that do not lose material, by MVV-LVA; the killer moves and
+/// counter-move; quiet moves by history; and finally the captures that lose material.
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    /// The killer moves followed by the counter-move.
+    refutations: [Option<Move>; 3],
+    captures: ArrayVec<[Move; 256]>,
+    quiets: ArrayVec<[Move; 256]>,
+    /// Ordering scores of the moves of the current stage.
+    scores: [i32; 256],
+    /// The next move of the current stage.
+    index: usize,
+    /// Losing captures are moved to the front of `captures` as they are found, ending here.
+    bad_captures: usize,
+}
+
+impl MovePicker {
+    #[must_use]
+    pub fn new(tt_move: Option<Move>, killers: [Option<Move>; 2], counter: Option<Move>) -> Self {
+        let mut captures = ArrayVec::from([Move::default(); 256]);
+        captures.set_len(0);
+        let mut quiets = ArrayVec::from([Move::default(); 256]);
+        quiets.set_len(0);
+        Self {
+            stage: Stage::TtMove,
+            tt_move,
+            refutations: [killers[0], killers[1], counter],
+            captures,
+            quiets,
+            scores: [0; 256],
+            index: 0,
+            bad_captures: 0,
+        }
+    }
+
+    /// Swap the highest scoring of the remaining moves of `moves` into the next slot and return it.
+    fn pick_best(moves: &mut [Move], scores: &mut [i32], index: usize) -> Move {
+        let mut best = index;
+        for i in index + 1..moves.len() {
+            if scores[i] > scores[best] {
+                best = i;
+            }
+        }
+        moves.swap(index, best);
+        scores.swap(index, best);
+        moves[index]
+    }
+
+    /// The next move to search, or `None` once every legal move has been returned.
+    ///
+    /// Quiet moves are ordered by `quiet_score`, highest first.
+    pub fn next(&mut self, board: &Board, quiet_score: impl Fn(Move) -> i32) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    let Some(tt_move) = self.tt_move else { continue };
+                    // The move may come from another position that shares our hash key, so check it is legal here.
+                    if board.is_legal(tt_move) {
+                        return Some(tt_move);
+                    }
+                    self.tt_move = None;
+                }
+                Stage::GenerateCaptures => {
+                    board.generate_captures(&mut self.captures);
+                    for (score, &m) in self.scores.iter_mut().zip(self.captures.iter()) {
+                        *score = mvv_lva(board, m);
+                    }
+                    self.index = 0;
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    if self.index == self.captures.len() {
+                        self.index = 0;
+                        self.stage = Stage::Refutations;
+                        continue;
+                    }
+                    let m = Self::pick_best(&mut self.captures, &mut self.scores, self.index);
+                    self.index += 1;
+                    if Some(m) == self.tt_move {
+                        continue;
+                    }
+                    if !board.see_ge(m, 0) {
+                        self.captures[self.bad_captures] = m;
+                        self.bad_captures += 1;
+                        continue;
+                    }
+                    return Some(m);
+                }
+                Stage::Refutations => {
+                    if self.index == self.refutations.len() {
+                        self.stage = Stage::GenerateQuiets;
+                        continue;
+                    }
+                    let i = self.index;
+                    self.index += 1;
+                    let Some(m) = self.refutations[i] else { continue };
+                    // Refutations come from other positions, so they may not even be legal here.
+                    if Some(m) == self.tt_move || self.refutations[..i].contains(&Some(m)) || m.is_capture() || !board.is_legal(m) {
+                        self.refutations[i] = None;
+                        continue;
+                    }
+                    return Some(m);
+                }
+                Stage::GenerateQuiets => {
+                    board.generate_quiets(&mut self.quiets);
+                    for (score, &m) in self.scores.iter_mut().zip(self.quiets.iter()) {
+                        *score = quiet_score(m);
+                    }
+                    self.index = 0;
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    if self.index == self.quiets.len() {
+                        self.index = 0;
+                        self.stage = Stage::BadCaptures;
+                        continue;
+                    }
+                    let m = Self::pick_best(&mut self.quiets, &mut self.scores, self.index);
+                    self.index += 1;
+                    if Some(m) == self.tt_move || self.refutations.contains(&Some(m)) {
+                        continue;
+                    }
+                    return Some(m);
+                }
+                Stage::BadCaptures => {
+                    if self.index == self.bad_captures {
+                        self.stage = Stage::Done;
+                        continue;
+                    }
+                    self.index += 1;
+                    return Some(self.captures[self.index - 1]);
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+}
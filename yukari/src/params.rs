@@ -0,0 +1,236 @@
+//! The search parameters, and the registry of those that can be tuned.
+//!
+//! Each tunable is declared once, with its option name, default, range and SPSA step, and everything else is generated
+//! from that: the UCI and xboard options, the OpenBench SPSA input, parameter files and the local SPSA driver.
+
+use std::{
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+/// The learning rate at the end of tuning that OpenBench is told to use, `r_end` in its SPSA input.
+const OPENBENCH_R_END: f64 = 0.002;
+
+/// A search parameter that can be tuned, as it appears in engine options.
+///
+/// Options are integers, so a fractional parameter is exposed multiplied by `scale`.
+pub struct Tunable {
+    /// The option name.
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+    /// How far SPSA perturbs the option by the end of tuning, `c_end` in OpenBench.
+    pub step: f64,
+    /// How many option units make one unit of the parameter.
+    pub scale: i32,
+    get: fn(&SearchParams) -> i32,
+    set: fn(&mut SearchParams, i32),
+}
+
+impl Tunable {
+    /// The option value of this parameter in `params`.
+    #[must_use]
+    pub fn get(&self, params: &SearchParams) -> i32 {
+        (self.get)(params)
+    }
+
+    /// Set this parameter in `params` from an option value, clamped to the option's range.
+    pub fn set(&self, params: &mut SearchParams, value: i32) {
+        (self.set)(params, value.clamp(self.min, self.max));
+    }
+}
+
+/// A parameter type that can be set from an integer option.
+trait OptionValue: Copy {
+    fn from_option(value: i32, scale: i32) -> Self;
+    fn to_option(self, scale: i32) -> i32;
+}
+
+impl OptionValue for i32 {
+    fn from_option(value: i32, scale: i32) -> Self {
+        value / scale
+    }
+
+    fn to_option(self, scale: i32) -> i32 {
+        self * scale
+    }
+}
+
+impl OptionValue for f32 {
+    fn from_option(value: i32, scale: i32) -> Self {
+        value as f32 / scale as f32
+    }
+
+    fn to_option(self, scale: i32) -> i32 {
+        (self * scale as f32).round() as i32
+    }
+}
+
+/// Declare `SearchParams` from its tunable parameters, then the ones that are not tuned.
+macro_rules! search_params {
+    (
+        tunables {
+            $(
+                $(#[$attr:meta])*
+                pub $field:ident: $ty:ty = $default:literal, $name:literal, $min:literal..=$max:literal, step $step:literal $(, scale $scale:literal)?;
+            )*
+        }
+        fixed {
+            $(
+                $(#[$fixed_attr:meta])*
+                pub $fixed_field:ident: $fixed_ty:ty = $fixed_default:expr;
+            )*
+        }
+    ) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct SearchParams {
+            $($(#[$attr])* pub $field: $ty,)*
+            $($(#[$fixed_attr])* pub $fixed_field: $fixed_ty,)*
+        }
+
+        impl Default for SearchParams {
+            fn default() -> Self {
+                Self {
+                    $($field: OptionValue::from_option($default, search_params!(@scale $($scale)?)),)*
+                    $($fixed_field: $fixed_default,)*
+                }
+            }
+        }
+
+        impl SearchParams {
+            /// Every tunable parameter, in declaration order.
+            pub const TUNABLES: &'static [Tunable] = &[
+                $(Tunable {
+                    name: $name,
+                    default: $default,
+                    min: $min,
+                    max: $max,
+                    step: $step,
+                    scale: search_params!(@scale $($scale)?),
+                    get: |params| params.$field.to_option(search_params!(@scale $($scale)?)),
+                    set: |params, value| params.$field = OptionValue::from_option(value, search_params!(@scale $($scale)?)),
+                },)*
+            ];
+        }
+    };
+    (@scale) => { 1 };
+    (@scale $scale:literal) => { $scale };
+}
+
+search_params! {
+    tunables {
+        pub rfp_margin_base: i32 = 0, "RfpMarginBase", 0..=100, step 5.0;
+        pub rfp_margin_mul: i32 = 37, "RfpMarginMul", 0..=1000, step 5.0;
+        /// Reverse futility pruning only applies up to this depth.
+        pub rfp_max_depth: i32 = 6, "RfpMaxDepth", 0..=20, step 1.0;
+        pub futility_margin_base: i32 = 100, "FutilityMarginBase", 0..=500, step 10.0;
+        pub futility_margin_mul: i32 = 100, "FutilityMarginMul", 0..=500, step 10.0;
+        /// Futility pruning only applies up to this depth.
+        pub futility_max_depth: i32 = 6, "FutilityMaxDepth", 0..=20, step 1.0;
+        /// Quiet moves after the first `lmp_base + lmp_mul * depth * depth` are pruned.
+        pub lmp_base: i32 = 3, "LmpBase", 0..=20, step 1.0;
+        pub lmp_mul: i32 = 1, "LmpMul", 0..=8, step 0.5;
+        /// Late move pruning only applies up to this depth.
+        pub lmp_max_depth: i32 = 8, "LmpMaxDepth", 0..=20, step 1.0;
+        /// Nodes without a TT move are searched one ply shallower from this depth on.
+        pub iir_min_depth: i32 = 4, "IirMinDepth", 1..=20, step 1.0;
+        /// How far above beta a capture must score to cut off a ProbCut search.
+        pub probcut_margin: i32 = 200, "ProbcutMargin", 0..=1000, step 15.0;
+        pub probcut_min_depth: i32 = 5, "ProbcutMinDepth", 1..=20, step 1.0;
+        /// How much shallower than the node the ProbCut verification search is.
+        pub probcut_reduction: i32 = 4, "ProbcutReduction", 1..=10, step 1.0;
+        /// Singular extensions are only tried from this depth on.
+        pub singular_min_depth: i32 = 8, "SingularMinDepth", 1..=20, step 1.0;
+        /// The other moves must fail low against the TT score minus this much per ply of depth for the TT move to be singular.
+        pub singular_margin: i32 = 2, "SingularMargin", 0..=20, step 1.0;
+        /// A singular move is extended twice when the other moves fail low by this much more.
+        pub double_extension_margin: i32 = 20, "DoubleExtensionMargin", 0..=200, step 3.0;
+        /// The most double extensions a line may have, so they cannot blow up the search.
+        pub max_double_extensions: i32 = 6, "MaxDoubleExtensions", 0..=20, step 1.0;
+        pub lmr_base: f32 = 100, "LmrBase", 0..=500, step 10.0, scale 100;
+        pub lmr_mul: f32 = 500, "LmrMul", 0..=2000, step 25.0, scale 1000;
+        pub hist_bonus_base: i32 = 250, "HistBonusBase", 0..=500, step 15.0;
+        pub hist_bonus_mul: i32 = 300, "HistBonusMul", 0..=600, step 15.0;
+        pub hist_pen_base: i32 = 250, "HistPenaltyBase", 0..=500, step 15.0;
+        pub hist_pen_mul: i32 = 300, "HistPenaltyMul", 0..=600, step 15.0;
+    }
+    fixed {
+        /// Search quiet checks in the first ply of quiescence search.
+        pub qsearch_checks: bool = false;
+    }
+}
+
+impl SearchParams {
+    /// The tunable parameter with the option name `name`, if there is one.
+    #[must_use]
+    pub fn tunable(name: &str) -> Option<&'static Tunable> {
+        Self::TUNABLES.iter().find(|tunable| tunable.name == name)
+    }
+
+    /// Set a tunable parameter from an option value, returning false if there is no such option.
+    pub fn set(&mut self, name: &str, value: i32) -> bool {
+        Self::tunable(name).map(|tunable| tunable.set(self, value)).is_some()
+    }
+
+    /// The SPSA input for OpenBench: one `name, int, default, min, max, c_end, r_end` line per tunable.
+    #[must_use]
+    pub fn openbench() -> String {
+        Self::TUNABLES.iter().fold(String::new(), |mut block, tunable| {
+            let Tunable { name, default, min, max, step, .. } = tunable;
+            writeln!(block, "{name}, int, {default}, {min}, {max}, {step}, {OPENBENCH_R_END}").unwrap();
+            block
+        })
+    }
+
+    /// Read a parameter file: `name = value` lines, as [`SearchParams`] displays, starting from the defaults.
+    ///
+    /// Blank lines and lines starting with `#` are skipped. OpenBench's `name, value` results are read too, rounding
+    /// fractional values.
+    ///
+    /// # Errors
+    /// Returns an error if a line is malformed or names no tunable parameter.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut params = Self::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (name, value) = line
+                .split_once('=')
+                .or_else(|| line.split_once(','))
+                .ok_or_else(|| invalid(format!("expected `name = value`, got `{line}`")))?;
+            let (name, value) = (name.trim(), value.trim());
+            let value = value.parse::<f64>().map_err(|err| invalid(format!("{name} = {value}: {err}")))?;
+            if !params.set(name, value.round() as i32) {
+                return Err(invalid(format!("unknown parameter {name}")));
+            }
+        }
+        Ok(params)
+    }
+
+    /// Read a parameter file, see [`SearchParams::parse`].
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Write the tunable parameters to a file that [`SearchParams::load`] reads back.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+/// The tunable parameters as `name = value` lines of option values.
+impl fmt::Display for SearchParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tunable in Self::TUNABLES {
+            writeln!(f, "{} = {}", tunable.name, tunable.get(self))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::ops::{Add, Neg, Sub};
+
+/// The deepest ply the search reaches before returning the static evaluation.
+pub const MAX_PLY: i32 = 100;
+
+/// The score of delivering mate at the root; mates further away score one less per ply.
+const MATE_VALUE: i32 = 10_000;
+
+/// A search score from the point of view of the side to move: either an evaluation in centipawns, or a forced mate.
+///
+/// Mate scores count plies from the root, so that shorter mates score better than longer ones, and evaluations are
+/// kept clear of the range mate scores use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Score(i32);
+
+impl Score {
+    pub const DRAW: Self = Self(0);
+    /// A bound beyond any score the search can return.
+    pub const INFINITY: Self = Self(100_000);
+
+    /// An evaluation in centipawns, limited so it cannot be mistaken for a mate score.
+    #[must_use]
+    pub const fn centipawns(centipawns: i32) -> Self {
+        let limit = MATE_VALUE - MAX_PLY - 1;
+        Self(if centipawns > limit {
+            limit
+        } else if centipawns < -limit {
+            -limit
+        } else {
+            centipawns
+        })
+    }
+
+    /// The side to move delivers mate `ply` plies from the root.
+    #[must_use]
+    pub const fn mate_in(ply: i32) -> Self {
+        Self(MATE_VALUE - ply)
+    }
+
+    /// The side to move is checkmated `ply` plies from the root.
+    #[must_use]
+    pub const fn mated_in(ply: i32) -> Self {
+        Self(-MATE_VALUE + ply)
+    }
+
+    /// The score as a plain number, in centipawns if it is not a mate score.
+    #[must_use]
+    pub const fn get(self) -> i32 {
+        self.0
+    }
+
+    /// Returns true if this is the score of a forced mate, for either side.
+    #[must_use]
+    pub const fn is_mate(self) -> bool {
+        self.0.abs() >= MATE_VALUE - MAX_PLY && self.0.abs() <= MATE_VALUE
+    }
+
+    /// The number of moves until mate: positive if the side to move mates, and negative if it gets mated.
+    #[must_use]
+    pub const fn mate_moves(self) -> Option<i32> {
+        if !self.is_mate() {
+            return None;
+        }
+        let plies = MATE_VALUE - self.0.abs();
+        Some(if self.0 > 0 { (plies + 1) / 2 } else { -plies / 2 })
+    }
+
+    /// Convert a score found `ply` plies from the root into one relative to that node, for storing in the
+    /// transposition table, where it may be found again at another ply.
+    #[must_use]
+    pub const fn to_tt(self, ply: i32) -> i16 {
+        let score = if self.0 >= MATE_VALUE - MAX_PLY {
+            self.0 + ply
+        } else if self.0 <= -MATE_VALUE + MAX_PLY {
+            self.0 - ply
+        } else {
+            self.0
+        };
+        score as i16
+    }
+
+    /// Convert a node-relative score from the transposition table back into a root-relative one.
+    #[must_use]
+    pub const fn from_tt(score: i16, ply: i32) -> Self {
+        let score = score as i32;
+        Self(if score >= MATE_VALUE - MAX_PLY {
+            score - ply
+        } else if score <= -MATE_VALUE + MAX_PLY {
+            score + ply
+        } else {
+            score
+        })
+    }
+
+    /// The score as UCI reports it: `cp` followed by centipawns, or `mate` followed by moves to mate.
+    #[must_use]
+    pub fn to_uci(self) -> String {
+        self.mate_moves().map_or_else(|| format!("cp {}", self.0), |moves| format!("mate {moves}"))
+    }
+
+    /// The score as xboard reports it: centipawns, or 100000 plus the moves to mate, negated when getting mated.
+    #[must_use]
+    pub const fn to_xboard(self) -> i32 {
+        match self.mate_moves() {
+            Some(moves) if moves > 0 => 100_000 + moves,
+            Some(moves) => -100_000 + moves,
+            None => self.0,
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Add<i32> for Score {
+    type Output = Self;
+
+    fn add(self, rhs: i32) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl Sub<i32> for Score {
+    type Output = Self;
+
+    fn sub(self, rhs: i32) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+/// The difference between two scores, in centipawns.
+impl Sub for Score {
+    type Output = i32;
+
+    fn sub(self, rhs: Self) -> i32 {
+        self.0 - rhs.0
+    }
+}
@@ -1,35 +1,54 @@
-use std::{cmp::Ordering, i32, sync::atomic::AtomicU64, time::Instant};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64},
+    time::Instant,
+};
 
 use tinyvec::ArrayVec;
-use yukari_movegen::{Board, Move, Zobrist};
-
-const MATE_VALUE: i32 = 10_000;
-
-#[derive(Clone)]
-pub struct SearchParams {
-    pub rfp_margin_base: i32,
-    pub rfp_margin_mul: i32,
-    pub lmr_base: f32,
-    pub lmr_mul: f32,
-    pub hist_bonus_base: i32,
-    pub hist_bonus_mul: i32,
-    pub hist_pen_base: i32,
-    pub hist_pen_mul: i32,
+use yukari_movegen::{Board, Eval, Evaluator, Move, Piece, Square, Zobrist};
+
+use crate::{
+    movepick::MovePicker,
+    params::SearchParams,
+    score::{Score, MAX_PLY},
+};
+
+/// History scores are kept within this range by the gravity update.
+const HISTORY_MAX: i32 = 16384;
+
+/// History of quiet moves following a move of a piece to a square, indexed by `[piece][square]` of the earlier move
+/// and then of the later one.
+type ContinuationHistory = [[[[i16; 64]; 6]; 64]; 6];
+
+/// Move `entry` towards `delta`, by less the closer it already is to the history limit.
+fn apply_gravity(entry: &mut i16, delta: i32) {
+    let delta = delta.clamp(-HISTORY_MAX, HISTORY_MAX);
+    *entry += (delta - i32::from(*entry) * delta.abs() / HISTORY_MAX) as i16;
 }
 
-impl Default for SearchParams {
-    fn default() -> Self {
-        Self { rfp_margin_base: 0, rfp_margin_mul: 37, lmr_base: 1.0, lmr_mul: 0.5, hist_bonus_base: 250, hist_bonus_mul: 300, hist_pen_base: 250, hist_pen_mul: 300 }
-    }
+/// What the search knows about one ply of the line it is searching.
+#[derive(Clone, Copy, Default)]
+pub struct StackEntry {
+    /// The move being searched from this ply, or `None` for a null move.
+    pub current_move: Option<Move>,
+    /// The piece that made `current_move`.
+    pub moved_piece: Option<Piece>,
+    /// Quiet moves that recently caused a beta cutoff at this ply.
+    pub killers: [Option<Move>; 2],
+    /// The corrected static evaluation of the position at this ply.
+    pub static_eval: Score,
+    /// A move left out of the search of this ply, while checking whether it is singular.
+    pub excluded_move: Option<Move>,
+    /// How many double extensions the line up to this ply has had.
+    pub double_extensions: i32,
 }
 
-// TODO: when 50-move rule is implemented, this can be limited to searching from the last irreversible move.
+/// Positions before the last irreversible move can never repeat, so the scan stops there.
 #[must_use]
-pub fn is_repetition_draw(keystack: &[u64], hash: u64) -> bool {
-    keystack.iter().filter(|key| **key == hash).count() >= 3
+pub fn is_repetition_draw(keystack: &[u64], hash: u64, halfmove_clock: u16) -> bool {
+    keystack.iter().rev().take(usize::from(halfmove_clock) + 1).filter(|key| **key == hash).count() >= 3
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 #[repr(u8)]
 enum TtFlags {
     #[default]
@@ -49,6 +68,7 @@ pub struct TtEntry {
 struct TtData {
     flags: TtFlags,
     depth: u8,
+    /// Relative to the node, see `Score::to_tt`.
     score: i16,
     m: Option<Move>,
 }
@@ -56,6 +76,15 @@ struct TtData {
 const _TT_ENTRY_IS_16_BYTE: () = assert!(std::mem::size_of::<TtEntry>() == 16);
 const _TT_DATA_IS_8_BYTE: () = assert!(std::mem::size_of::<TtData>() == 8);
 
+impl TtEntry {
+    /// The depth stored in this entry, whichever position it is for.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> u8 {
+        let data: TtData = unsafe { std::mem::transmute(self.data.load(std::sync::atomic::Ordering::Relaxed)) };
+        data.depth
+    }
+}
+
 pub fn allocate_tt(megabytes: usize) -> Vec<TtEntry> {
     let target_bytes = megabytes * 1024 * 1024;
 
@@ -75,14 +104,26 @@ pub fn allocate_tt(megabytes: usize) -> Vec<TtEntry> {
     tt
 }
 
-pub struct Search<'a> {
+pub struct Search<'a, E: Evaluator = Eval> {
     nodes: u64,
     qnodes: u64,
     nullmove_attempts: u64,
     nullmove_success: u64,
     stop_after: Option<Instant>,
+    stop_nodes: Option<u64>,
+    stop_signal: Option<&'a AtomicBool>,
+    stopped: bool,
+    node_counter: Option<&'a AtomicU64>,
+    thread_index: usize,
     zobrist: &'a Zobrist,
     history: [[i16; 64]; 64],
+    /// Indexed by the origin and destination squares of the previous move.
+    counter_moves: Box<[[Option<Move>; 64]; 64]>,
+    /// Continuation history for the moves one and two plies back.
+    continuation_history: [Box<ContinuationHistory>; 2],
+    stack: Vec<StackEntry>,
+    /// The evaluator of the position at each ply of the line being searched.
+    evaluators: Vec<E>,
     tt: &'a [TtEntry],
     corrhist: &'a mut [[i32; 16384]; 2],
     params: &'a SearchParams,
@@ -91,65 +132,246 @@ pub struct Search<'a> {
 impl<'a> Search<'a> {
     #[must_use]
     pub fn new(stop_after: Option<Instant>, zobrist: &'a Zobrist, tt: &'a [TtEntry], corrhist: &'a mut [[i32; 16384]; 2], params: &'a SearchParams) -> Self {
-        Self { nodes: 0, qnodes: 0, nullmove_attempts: 0, nullmove_success: 0, stop_after, zobrist, history: [[0; 64]; 64], tt, corrhist, params }
+        Self::with_evaluator(stop_after, zobrist, tt, corrhist, params, Eval::new())
+    }
+}
+
+impl<'a, E: Evaluator> Search<'a, E> {
+    /// Create a search that evaluates positions with `evaluator`, which is refreshed from the root position of each search.
+    #[must_use]
+    pub fn with_evaluator(
+        stop_after: Option<Instant>, zobrist: &'a Zobrist, tt: &'a [TtEntry], corrhist: &'a mut [[i32; 16384]; 2], params: &'a SearchParams,
+        evaluator: E,
+    ) -> Self {
+        Self {
+            nodes: 0,
+            qnodes: 0,
+            nullmove_attempts: 0,
+            nullmove_success: 0,
+            stop_after,
+            stop_nodes: None,
+            stop_signal: None,
+            stopped: false,
+            node_counter: None,
+            thread_index: 0,
+            zobrist,
+            history: [[0; 64]; 64],
+            counter_moves: Box::new([[None; 64]; 64]),
+            continuation_history: [Box::new([[[[0; 64]; 6]; 64]; 6]), Box::new([[[[0; 64]; 6]; 64]; 6])],
+            stack: vec![StackEntry::default(); MAX_PLY as usize + 1],
+            evaluators: vec![evaluator; MAX_PLY as usize + 1],
+            tt,
+            corrhist,
+            params,
+        }
+    }
+
+    /// Stop the search once this many nodes (including quiescence nodes) have been searched.
+    pub fn set_node_limit(&mut self, nodes: Option<u64>) {
+        self.stop_nodes = nodes;
+    }
+
+    /// Stop the search once another thread sets this flag.
+    pub fn set_stop_signal(&mut self, stop: &'a AtomicBool) {
+        self.stop_signal = Some(stop);
+    }
+
+    /// Publish the node count (including quiescence nodes) to this counter as the search runs,
+    /// so other threads can report it.
+    pub fn set_node_counter(&mut self, counter: &'a AtomicU64) {
+        self.node_counter = Some(counter);
+    }
+
+    /// Mark this as a helper search in a multi-threaded search.
+    ///
+    /// Helpers order root moves differently from the main thread, so that the threads spread
+    /// out over the tree instead of all searching the same moves in the same order.
+    pub fn set_thread_index(&mut self, index: usize) {
+        self.thread_index = index;
+    }
+
+    /// The search stack, indexed by ply, describing the line currently being searched.
+    #[must_use]
+    pub fn stack(&self) -> &[StackEntry] {
+        &self.stack
+    }
+
+    /// The piece and destination square of the move `offset` plies before `ply`, if it was not a null move.
+    fn previous_move(&self, ply: i32, offset: i32) -> Option<(Piece, Square)> {
+        if ply < offset {
+            return None;
+        }
+        let entry = &self.stack[(ply - offset) as usize];
+        Some((entry.moved_piece?, entry.current_move?.dest))
+    }
+
+    /// How well the quiet move `m` has done before, from the butterfly and continuation histories.
+    fn quiet_history(&self, board: &Board, ply: i32, m: Move) -> i32 {
+        let mut score = i32::from(self.history[m.from.into_inner() as usize][m.dest.into_inner() as usize]);
+        let piece = board.piece_from_square(m.from).expect("move from empty square") as usize;
+        for (offset, table) in (1..).zip(&self.continuation_history) {
+            if let Some((prev_piece, prev_dest)) = self.previous_move(ply, offset) {
+                score += i32::from(table[prev_piece as usize][prev_dest.into_inner() as usize][piece][m.dest.into_inner() as usize]);
+            }
+        }
+        score
+    }
+
+    /// Reward (or with a negative `delta`, punish) the quiet move `m` in every history table.
+    fn update_quiet_history(&mut self, board: &Board, ply: i32, m: Move, delta: i32) {
+        apply_gravity(&mut self.history[m.from.into_inner() as usize][m.dest.into_inner() as usize], delta);
+        let piece = board.piece_from_square(m.from).expect("move from empty square") as usize;
+        for offset in 1..=2 {
+            if let Some((prev_piece, prev_dest)) = self.previous_move(ply, offset) {
+                let table = &mut self.continuation_history[offset as usize - 1];
+                apply_gravity(&mut table[prev_piece as usize][prev_dest.into_inner() as usize][piece][m.dest.into_inner() as usize], delta);
+            }
+        }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if let Some(counter) = self.node_counter {
+            counter.store(self.nodes + self.qnodes, std::sync::atomic::Ordering::Relaxed);
+        }
+        if let Some(stop) = self.stop_signal {
+            if stop.load(std::sync::atomic::Ordering::Relaxed) {
+                self.stopped = true;
+            }
+        }
+        if let Some(nodes) = self.stop_nodes {
+            if self.nodes + self.qnodes >= nodes {
+                self.stopped = true;
+            }
+        }
+        if self.nodes.trailing_zeros() >= 10 {
+            if let Some(time) = self.stop_after {
+                if Instant::now() >= time {
+                    self.stopped = true;
+                }
+            }
+        }
+        self.stopped
     }
 
     fn update_corrhist(&mut self, board: &Board, depth: i32, diff: i32) {
         const CORRHIST_GRAIN: i32 = 256;
         const CORRHIST_WEIGHT_SCALE: i32 = 256;
         const CORRHIST_MAX: i32 = 256 * 32;
-        let entry = &mut self.corrhist[board.side() as usize][board.hash_pawns(self.zobrist) as usize & 16383];
+        let entry = &mut self.corrhist[board.side() as usize][board.pawn_key() as usize & 16383];
         let diff = diff * CORRHIST_GRAIN;
         let weight = 16.min(depth + 1);
 
         *entry = ((*entry * (CORRHIST_WEIGHT_SCALE - weight) + diff * weight) / CORRHIST_WEIGHT_SCALE).clamp(-CORRHIST_MAX, CORRHIST_MAX);
     }
 
-    fn eval_with_corrhist(&self, board: &Board, eval: i32) -> i32 {
+    fn eval_with_corrhist(&self, board: &Board, eval: i32) -> Score {
         const CORRHIST_GRAIN: i32 = 256;
-        let entry = &self.corrhist[board.side() as usize][board.hash_pawns(self.zobrist) as usize & 16383];
-        (eval + entry / CORRHIST_GRAIN).clamp(-MATE_VALUE + 1, MATE_VALUE - 1)
+        let entry = &self.corrhist[board.side() as usize][board.pawn_key() as usize & 16383];
+        Score::centipawns(eval + entry / CORRHIST_GRAIN)
+    }
+
+    /// The corrected static evaluation of `board`, the position at `ply`.
+    fn static_eval(&self, board: &Board, ply: i32) -> Score {
+        self.eval_with_corrhist(board, self.evaluators[ply as usize].evaluate(board))
     }
 
-    fn quiesce(&mut self, board: &Board, mut alpha: i32, beta: i32, pv: &mut ArrayVec<[Move; 32]>) -> i32 {
-        let eval_int = self.eval_with_corrhist(board, board.eval(board.side()));
+    /// Make `m` on `board`, the position at `ply`, bringing the evaluator of the next ply up to date.
+    fn make(&mut self, board: &Board, m: Move, ply: i32) -> Board {
+        let (parents, children) = self.evaluators.split_at_mut(ply as usize + 1);
+        children[0].clone_from(&parents[ply as usize]);
+        children[0].make_move(board, m);
+        board.make(m, self.zobrist)
+    }
 
+    /// Pass the move on `board`, the position at `ply`.
+    fn make_null(&mut self, board: &Board, ply: i32) -> Board {
+        let (parents, children) = self.evaluators.split_at_mut(ply as usize + 1);
+        children[0].clone_from(&parents[ply as usize]);
+        board.make_null(self.zobrist)
+    }
+
+    /// Search captures until the position is quiet, so the static evaluation can be trusted.
+    ///
+    /// In check there is no standing pat: every evasion is searched, and having none is mate. With `checks`, quiet
+    /// moves that give check are searched after the captures.
+    fn quiesce(&mut self, board: &Board, mut alpha: Score, beta: Score, pv: &mut ArrayVec<[Move; 32]>, ply: i32, checks: bool) -> Score {
         pv.set_len(0);
 
+        if ply == MAX_PLY {
+            return self.static_eval(board, ply);
+        }
+
+        if board.in_check() {
+            let mut picker = MovePicker::new(None, [None; 2], None);
+            let mut any_moves = false;
+            while let Some(m) = picker.next(board, |_| 0) {
+                any_moves = true;
+                if self.quiesce_move(board, m, &mut alpha, beta, pv, ply) {
+                    return beta;
+                }
+            }
+            return if any_moves { alpha } else { Score::mated_in(ply) };
+        }
+
+        let eval_int = self.static_eval(board, ply);
         if eval_int >= beta {
             return beta;
         }
         alpha = alpha.max(eval_int);
 
+        let mut cutoff = false;
         board.generate_captures_incremental(|m| {
-            self.qnodes += 1;
+            // Captures that lose material are very unlikely to raise alpha.
+            if !board.see_ge(m, 0) {
+                return true;
+            }
+            cutoff = self.quiesce_move(board, m, &mut alpha, beta, pv, ply);
+            !cutoff
+        });
+        if cutoff {
+            return beta;
+        }
+
+        if checks {
+            let mut moves = ArrayVec::from([Move::default(); 256]);
+            moves.set_len(0);
+            board.generate_checks(&mut moves);
+            for m in moves {
+                if board.see_ge(m, 0) && self.quiesce_move(board, m, &mut alpha, beta, pv, ply) {
+                    return beta;
+                }
+            }
+        }
 
-            let board = board.make(m, self.zobrist);
+        alpha
+    }
 
-            let mut child_pv = ArrayVec::new();
-            let score = -self.quiesce(&board, -beta, -alpha, &mut child_pv);
+    /// Search `m` in quiescence, raising `alpha` and updating `pv` if it improves on it. Returns true on a beta cutoff.
+    fn quiesce_move(&mut self, board: &Board, m: Move, alpha: &mut Score, beta: Score, pv: &mut ArrayVec<[Move; 32]>, ply: i32) -> bool {
+        self.qnodes += 1;
 
-            if score >= beta {
-                alpha = beta;
-                return false;
-            }
+        let board = self.make(board, m, ply);
 
-            if score > alpha {
-                alpha = score;
-                pv.set_len(0);
+        let mut child_pv = ArrayVec::new();
+        let score = -self.quiesce(&board, -beta, -*alpha, &mut child_pv, ply + 1, false);
+
+        if score >= beta {
+            return true;
+        }
+
+        if score > *alpha {
+            *alpha = score;
+            pv.set_len(0);
+            pv.push(m);
+            for m in child_pv {
                 pv.push(m);
-                for m in child_pv {
-                    pv.push(m);
-                }
             }
+        }
 
-            true
-        });
-
-        alpha
+        false
     }
 
-    fn probe_tt(&self, board: &Board) -> Option<Move> {
+    fn probe_tt(&self, board: &Board) -> Option<TtData> {
         let entry = (board.hash() & ((self.tt.len() - 1) as u64)) as usize;
         let entry = &self.tt[entry];
         let entry_key = entry.key.load(std::sync::atomic::Ordering::Relaxed);
@@ -157,7 +379,7 @@ impl<'a> Search<'a> {
         let entry: TtData = unsafe { std::mem::transmute(entry_data) };
 
         if entry_key ^ entry_data == board.hash() {
-            return entry.m;
+            return Some(entry);
         }
         None
     }
@@ -172,33 +394,92 @@ impl<'a> Search<'a> {
 
     #[allow(clippy::too_many_arguments)]
     fn search(
-        &mut self, board: &Board, mut depth: i32, mut lower_bound: i32, upper_bound: i32,
+        &mut self, board: &Board, mut depth: i32, mut lower_bound: Score, mut upper_bound: Score,
         pv: &mut ArrayVec<[Move; 32]>, ply: i32, keystack: &mut Vec<u64>,
-    ) -> i32 {
+    ) -> Score {
         // Emergency bailout
-        if ply == 100 {
-            return self.eval_with_corrhist(board, board.eval(board.side()));
+        if ply == MAX_PLY {
+            return self.static_eval(board, ply);
         }
 
-        // Check extension
-        if board.in_check() {
+        // Check extension. A singular verification search re-enters a node that was already extended, so it is not
+        // extended again.
+        if board.in_check() && self.stack[ply as usize].excluded_move.is_none() {
             depth += 1;
         }
 
+        // Is this a fifty-move draw? Being checkmated takes priority, so that is handled after move generation.
+        if ply > 0 && board.halfmove_clock() >= 100 && !board.in_check() {
+            return Score::DRAW;
+        }
+
         if depth <= 0 {
-            return self.quiesce(board, lower_bound, upper_bound, pv);
+            return self.quiesce(board, lower_bound, upper_bound, pv, ply, self.params.qsearch_checks);
         }
 
         pv.set_len(0);
 
-        let tt_move = self.probe_tt(board);
-        let eval_int = self.eval_with_corrhist(board, board.eval(board.side()));
+        // Is this a repetition draw?
+        if ply > 0 && is_repetition_draw(keystack, board.hash(), board.halfmove_clock()) {
+            return Score::DRAW;
+        }
+
+        // Mate distance pruning: no mate from here can beat a shorter one already found, nor can being mated here do
+        // worse than one already escaped.
+        if ply > 0 {
+            lower_bound = lower_bound.max(Score::mated_in(ply));
+            upper_bound = upper_bound.min(Score::mate_in(ply + 1));
+            if lower_bound >= upper_bound {
+                return lower_bound;
+            }
+        }
+
+        // When checking whether a move is singular, this node searches every move but that one.
+        let excluded_move = self.stack[ply as usize].excluded_move;
+
+        let tt_entry = if excluded_move.is_some() { None } else { self.probe_tt(board) };
+        let tt_move = tt_entry.as_ref().and_then(|entry| entry.m);
+        let static_eval = self.static_eval(board, ply);
+        let mut eval_int = static_eval;
+        self.stack[ply as usize].static_eval = static_eval;
+        // Killers from a sibling's subtree are more relevant to our children than ones from further away.
+        self.stack[ply as usize + 1].killers = [None; 2];
+
+        if let Some(entry) = &tt_entry {
+            let score = Score::from_tt(entry.score, ply);
+
+            // A deep enough entry with a usable bound lets us skip searching this node entirely.
+            if ply > 0 && lower_bound == upper_bound - 1 && i32::from(entry.depth) >= depth {
+                match entry.flags {
+                    TtFlags::Exact => return score.clamp(lower_bound, upper_bound),
+                    TtFlags::Lower if score >= upper_bound => return upper_bound,
+                    TtFlags::Upper if score <= lower_bound => return lower_bound,
+                    _ => {}
+                }
+            }
+
+            // The search score is a better guess at the value of this node than static eval.
+            match entry.flags {
+                TtFlags::Exact => eval_int = score,
+                TtFlags::Lower if score > static_eval => eval_int = score,
+                TtFlags::Upper if score < static_eval => eval_int = score,
+                _ => {}
+            }
+        }
+
+        // Internal iterative reduction: without a TT move, move ordering is poor, so spend less effort here and let the
+        // next iteration come back with a move to try first.
+        if excluded_move.is_none() && tt_move.is_none() && depth >= self.params.iir_min_depth {
+            depth -= 1;
+        }
 
         const R: i32 = 3;
 
-        if !board.in_check() && depth >= 2 && eval_int >= upper_bound {
+        if ply > 0 && excluded_move.is_none() && !board.in_check() && depth >= 2 && eval_int >= upper_bound {
             keystack.push(board.hash());
-            let board = board.make_null(self.zobrist);
+            self.stack[ply as usize].current_move = None;
+            self.stack[ply as usize].moved_piece = None;
+            let board = self.make_null(board, ply);
             let mut child_pv = ArrayVec::new();
             let score = -self.search(&board, depth - 1 - R, -upper_bound, -upper_bound + 1, &mut child_pv, ply + 1, keystack);
             keystack.pop();
@@ -212,60 +493,172 @@ impl<'a> Search<'a> {
         }
 
         let rfp_margin = self.params.rfp_margin_base + self.params.rfp_margin_mul * depth;
-        if !board.in_check() && depth == 1 && eval_int - rfp_margin >= upper_bound {
+        if ply > 0
+            && excluded_move.is_none()
+            && lower_bound == upper_bound - 1
+            && !board.in_check()
+            && depth <= self.params.rfp_max_depth
+            && eval_int - rfp_margin >= upper_bound
+        {
             return upper_bound;
         }
 
-        let moves: [Move; 256] = [Move::default(); 256];
-        let mut moves = ArrayVec::from(moves);
-        moves.set_len(0);
-        board.generate(&mut moves);
+        // ProbCut: a capture that beats beta by a margin in quiescence, and then in a reduced search, very likely
+        // refutes this node.
+        let probcut_beta = upper_bound + self.params.probcut_margin;
+        if ply > 0
+            && excluded_move.is_none()
+            && lower_bound == upper_bound - 1
+            && !board.in_check()
+            && depth >= self.params.probcut_min_depth
+            && depth > self.params.probcut_reduction
+            && !upper_bound.is_mate()
+            && !tt_entry.as_ref().is_some_and(|entry| {
+                i32::from(entry.depth) > depth - self.params.probcut_reduction && Score::from_tt(entry.score, ply) < probcut_beta
+            })
+        {
+            let mut captures = ArrayVec::from([Move::default(); 256]);
+            captures.set_len(0);
+            board.generate_captures(&mut captures);
+            for m in captures {
+                if !board.see_ge(m, probcut_beta - static_eval) {
+                    continue;
+                }
 
-        // Is this checkmate or stalemate?
-        if moves.is_empty() {
-            pv.set_len(0);
-            if board.in_check() {
-                return -MATE_VALUE + ply;
+                self.nodes += 1;
+                keystack.push(board.hash());
+                self.stack[ply as usize].current_move = Some(m);
+                self.stack[ply as usize].moved_piece = board.piece_from_square(m.from);
+                let child_board = self.make(board, m, ply);
+                let mut child_pv = ArrayVec::new();
+                let mut score = -self.quiesce(&child_board, -probcut_beta, -probcut_beta + 1, &mut child_pv, ply + 1, false);
+                if score >= probcut_beta {
+                    let depth = depth - self.params.probcut_reduction;
+                    score = -self.search(&child_board, depth, -probcut_beta, -probcut_beta + 1, &mut child_pv, ply + 1, keystack);
+                }
+                keystack.pop();
+
+                if self.should_stop() {
+                    return lower_bound;
+                }
+
+                if score >= probcut_beta {
+                    self.write_tt(board, TtData {
+                        m: Some(m),
+                        score: probcut_beta.to_tt(ply),
+                        flags: TtFlags::Lower,
+                        depth: (depth - self.params.probcut_reduction + 1).max(0) as u8,
+                    });
+                    return upper_bound;
+                }
             }
-            return 0;
         }
 
-        // Is this a repetition draw?
-        if is_repetition_draw(keystack, board.hash()) {
-            return 0;
+        // A fifty-move draw while in check still needs a legal move, or it is checkmate.
+        if ply > 0 && board.halfmove_clock() >= 100 {
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            board.generate(&mut moves);
+            return if moves.is_empty() { Score::mated_in(ply) } else { Score::DRAW };
         }
 
-        moves.sort_by(|a, b| {
-            if let Some(tt_move) = tt_move {
-                if *a == tt_move {
-                    return Ordering::Less;
-                }
-                if *b == tt_move {
-                    return Ordering::Greater;
-                }
+        // The counter-move table is keyed by the opponent's last move.
+        let counter_move_key = if ply > 0 { self.stack[ply as usize - 1].current_move } else { None };
+        let counter_move = counter_move_key.and_then(|prev| self.counter_moves[prev.from.into_inner() as usize][prev.dest.into_inner() as usize]);
+        let mut picker = MovePicker::new(tt_move, self.stack[ply as usize].killers, counter_move);
+
+        // Helpers keep the best move first but rotate the rest by their index.
+        let mut root_moves = None;
+        if ply == 0 && self.thread_index > 0 {
+            let moves: [Move; 256] = [Move::default(); 256];
+            let mut moves = ArrayVec::from(moves);
+            moves.set_len(0);
+            while let Some(m) = picker.next(board, |m| self.quiet_history(board, ply, m)) {
+                moves.push(m);
             }
-
-            match (a.is_capture(), b.is_capture()) {
-                (false, false) => self.history[b.from.into_inner() as usize][b.dest.into_inner() as usize].cmp(&self.history[a.from.into_inner() as usize][a.dest.into_inner() as usize]),
-                (false, true) => Ordering::Greater,
-                (true, false) => Ordering::Less,
-                (true, true) => Ordering::Equal, // hack
+            if moves.len() > 2 {
+                let shift = self.thread_index % (moves.len() - 1);
+                moves[1..].rotate_left(shift);
             }
-        });
+            root_moves = Some(moves.into_iter());
+        }
 
         let mut best_move = None;
-        let mut best_score = i32::MIN;
+        let mut best_score = -Score::INFINITY;
         let mut finding_pv = true;
 
-        for (i, m) in moves.into_iter().enumerate() {
+        let quiets_tried: [Move; 256] = [Move::default(); 256];
+        let mut quiets_tried = ArrayVec::from(quiets_tried);
+        quiets_tried.set_len(0);
+
+        for i in 0.. {
+            let next = match &mut root_moves {
+                Some(moves) => moves.next(),
+                None => picker.next(board, |m| self.quiet_history(board, ply, m)),
+            };
+            let Some(m) = next else { break };
+            if Some(m) == excluded_move {
+                continue;
+            }
+
+            // Once a move has saved us from mate, quiet moves late in the list or far below alpha are not worth a look,
+            // unless they give check.
+            if ply > 0 && !board.in_check() && !m.is_capture() && m.prom.is_none() && best_score > Score::mated_in(MAX_PLY) {
+                let late = depth <= self.params.lmp_max_depth && i >= self.params.lmp_base + self.params.lmp_mul * depth * depth;
+                let futility_margin = self.params.futility_margin_base + self.params.futility_margin_mul * depth;
+                let futile = depth <= self.params.futility_max_depth && eval_int + futility_margin <= lower_bound;
+                if (late || futile) && !board.gives_check(m) {
+                    continue;
+                }
+            }
+
+            // Singular extension: if every other move fails low well below the TT score, the TT move is the only good one
+            // here and deserves a closer look. If instead the other moves beat beta even at reduced depth, this node
+            // will most likely fail high whatever the TT move does (multi-cut).
+            let mut extension = 0;
+            if let Some(entry) = tt_entry.as_ref().filter(|entry| ply > 0 && entry.m == Some(m)) {
+                let tt_score = Score::from_tt(entry.score, ply);
+                if depth >= self.params.singular_min_depth
+                    && i32::from(entry.depth) >= depth - 3
+                    && entry.flags != TtFlags::Upper
+                    && !tt_score.is_mate()
+                {
+                    let singular_beta = tt_score - self.params.singular_margin * depth;
+                    let mut singular_pv = ArrayVec::new();
+                    self.stack[ply as usize].excluded_move = Some(m);
+                    let score = self.search(board, (depth - 1) / 2, singular_beta - 1, singular_beta, &mut singular_pv, ply, keystack);
+                    self.stack[ply as usize].excluded_move = None;
+
+                    if self.should_stop() {
+                        return lower_bound;
+                    }
+
+                    if score < singular_beta {
+                        extension = 1;
+                        if lower_bound == upper_bound - 1
+                            && score < singular_beta - self.params.double_extension_margin
+                            && self.stack[ply as usize].double_extensions < self.params.max_double_extensions
+                        {
+                            extension = 2;
+                        }
+                    } else if singular_beta >= upper_bound {
+                        return upper_bound;
+                    }
+                }
+            }
+            self.stack[ply as usize + 1].double_extensions = self.stack[ply as usize].double_extensions + i32::from(extension == 2);
+
             self.nodes += 1;
 
             let mut child_pv = ArrayVec::new();
-            let child_board = board.make(m, self.zobrist);
-            let mut score = 0;
+            let child_board = self.make(board, m, ply);
+            let mut score = Score::DRAW;
 
             // Push the move to check for repetition draws
             keystack.push(board.hash());
+            self.stack[ply as usize].current_move = Some(m);
+            self.stack[ply as usize].moved_piece = board.piece_from_square(m.from);
 
             let mut reduction = 1;
 
@@ -277,10 +670,10 @@ impl<'a> Search<'a> {
 
             loop {
                 if !finding_pv {
-                    score = -self.search(&child_board, depth - reduction, -lower_bound - 1, -lower_bound, &mut child_pv, ply + 1, keystack);
+                    score = -self.search(&child_board, depth - reduction + extension, -lower_bound - 1, -lower_bound, &mut child_pv, ply + 1, keystack);
                 }
                 if finding_pv || (score > lower_bound && score < upper_bound) {
-                    score = -self.search(&child_board, depth - reduction, -upper_bound, -lower_bound, &mut child_pv, ply + 1, keystack);
+                    score = -self.search(&child_board, depth - reduction + extension, -upper_bound, -lower_bound, &mut child_pv, ply + 1, keystack);
                 }
             
                 if reduction > 1 && score > lower_bound {
@@ -297,41 +690,40 @@ impl<'a> Search<'a> {
                 best_score = score;
             }
 
-            if self.nodes.trailing_zeros() >= 10 {
-                if let Some(time) = self.stop_after {
-                    if Instant::now() >= time {
-                        return lower_bound;
-                    }
-                }
+            if self.should_stop() {
+                return lower_bound;
             }
 
             if score >= upper_bound {
-                const HISTORY_MAX: i32 = 16384;
-                let bonus = (self.params.hist_bonus_mul * depth - self.params.hist_bonus_base).clamp(-HISTORY_MAX, HISTORY_MAX);
-                let penalty = (self.params.hist_pen_mul * depth - self.params.hist_pen_base).clamp(-HISTORY_MAX, HISTORY_MAX);
+                let bonus = self.params.hist_bonus_mul * depth - self.params.hist_bonus_base;
+                let penalty = self.params.hist_pen_mul * depth - self.params.hist_pen_base;
                 if !m.is_capture() {
-                    for m in moves.into_iter().take(i) {
-                        if m.is_capture() {
-                            continue;
-                        }
-                        let history = &mut self.history[m.from.into_inner() as usize][m.dest.into_inner() as usize];
-                        let bonus = -penalty - (*history as i32) * penalty / HISTORY_MAX;
-                        *history += bonus as i16;
+                    for tried in quiets_tried {
+                        self.update_quiet_history(board, ply, tried, -penalty);
+                    }
+                    self.update_quiet_history(board, ply, m, bonus);
+
+                    let killers = &mut self.stack[ply as usize].killers;
+                    if killers[0] != Some(m) {
+                        killers[1] = killers[0];
+                        killers[0] = Some(m);
+                    }
+                    if let Some(prev) = counter_move_key {
+                        self.counter_moves[prev.from.into_inner() as usize][prev.dest.into_inner() as usize] = Some(m);
                     }
-                    let history = &mut self.history[m.from.into_inner() as usize][m.dest.into_inner() as usize];
-                    let bonus = bonus - (*history as i32) * bonus / HISTORY_MAX;
-                    *history += bonus as i16;
                 }
 
-                self.write_tt(board, TtData {
-                    m: best_move,
-                    score: upper_bound as i16,
-                    flags: TtFlags::Lower,
-                    depth: depth as u8,
-                });
+                if excluded_move.is_none() {
+                    self.write_tt(board, TtData {
+                        m: best_move,
+                        score: upper_bound.to_tt(ply),
+                        flags: TtFlags::Lower,
+                        depth: depth as u8,
+                    });
+                }
 
-                if !board.in_check() && !m.is_capture() && upper_bound >= eval_int {
-                    self.update_corrhist(board, depth, upper_bound - eval_int);
+                if excluded_move.is_none() && !board.in_check() && !m.is_capture() && upper_bound >= static_eval {
+                    self.update_corrhist(board, depth, upper_bound - static_eval);
                 }
 
                 return upper_bound;
@@ -346,24 +738,53 @@ impl<'a> Search<'a> {
                 }
                 finding_pv = false;
             }
+
+            if !m.is_capture() {
+                quiets_tried.push(m);
+            }
         }
 
-        self.write_tt(board, TtData {
-            m: best_move,
-            score: lower_bound as i16,
-            flags: if finding_pv { TtFlags::Upper } else { TtFlags::Exact },
-            depth: depth as u8,
-        });
+        // Is this checkmate or stalemate?
+        let Some(best_move) = best_move else {
+            // Having no other move to try is not mate when checking whether a move is singular.
+            if excluded_move.is_some() {
+                return lower_bound;
+            }
+            return if board.in_check() { Score::mated_in(ply) } else { Score::DRAW };
+        };
+
+        // The score of a search without the excluded move is not the score of this position.
+        if excluded_move.is_none() {
+            self.write_tt(board, TtData {
+                m: Some(best_move),
+                score: lower_bound.to_tt(ply),
+                flags: if finding_pv { TtFlags::Upper } else { TtFlags::Exact },
+                depth: depth as u8,
+            });
+        }
 
-        if !board.in_check() && !best_move.unwrap().is_capture() && (!finding_pv || lower_bound <= eval_int) {
-            self.update_corrhist(board, depth, lower_bound - eval_int);
+        if excluded_move.is_none() && !board.in_check() && !best_move.is_capture() && (!finding_pv || lower_bound <= static_eval) {
+            self.update_corrhist(board, depth, lower_bound - static_eval);
         }
 
         lower_bound
     }
 
-    pub fn search_root(&mut self, board: &Board, depth: i32, pv: &mut ArrayVec<[Move; 32]>, keystack: &mut Vec<u64>) -> i32 {
-        self.search(board, depth, -100_000, 100_000, pv, 0, keystack)
+    pub fn search_root(&mut self, board: &Board, depth: i32, pv: &mut ArrayVec<[Move; 32]>, keystack: &mut Vec<u64>) -> Score {
+        self.evaluators[0].refresh(board);
+        self.search(board, depth, -Score::INFINITY, Score::INFINITY, pv, 0, keystack)
+    }
+
+    /// Search the root position with the window `lower_bound..upper_bound`.
+    ///
+    /// A score at or below `lower_bound` only says the true score is no higher, and one at or above `upper_bound` that
+    /// it is no lower.
+    pub fn search_window(
+        &mut self, board: &Board, depth: i32, lower_bound: Score, upper_bound: Score, pv: &mut ArrayVec<[Move; 32]>,
+        keystack: &mut Vec<u64>,
+    ) -> Score {
+        self.evaluators[0].refresh(board);
+        self.search(board, depth, lower_bound, upper_bound, pv, 0, keystack)
     }
 
     #[must_use]
@@ -376,6 +797,12 @@ impl<'a> Search<'a> {
         self.qnodes
     }
 
+    /// True if the search bailed out early because of a time or node limit.
+    #[must_use]
+    pub const fn stopped(&self) -> bool {
+        self.stopped
+    }
+
     #[must_use]
     pub fn nullmove_success(&self) -> f64 {
         100.0 * (self.nullmove_success as f64) / (self.nullmove_attempts as f64)
@@ -0,0 +1,258 @@
+//! Local SPSA tuning of the search parameters, by short self-play matches between perturbed parameter sets.
+//!
+//! Each iteration nudges every tunable up or down at random, plays game pairs between the parameters nudged one way
+//! and those nudged the other, and moves the parameters towards whichever side won. The step sizes shrink over the run
+//! as in OpenBench, so a tunable's `step` is how far it is perturbed in the last iteration.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use yukari_movegen::{Board, Book, Colour, Evaluator, GameResult, Zobrist};
+
+use crate::{
+    allocate_tt,
+    datagen::{game_over, opening, winner, Resign, STACK_SIZE},
+    IterativeDeepening, Search, SearchParams, TtEntry,
+};
+
+/// How the tuning matches are played, and how fast the parameters move.
+#[derive(Clone)]
+pub struct SpsaConfig {
+    /// Number of iterations of the whole run; the step sizes depend on it.
+    pub iterations: u64,
+    /// Game pairs played each iteration, each pair from one opening with colours swapped.
+    pub pairs: u64,
+    /// Number of games played at once.
+    pub threads: usize,
+    /// Transposition table size of each player, in megabytes.
+    pub hash_mb: usize,
+    /// Stop searching each move after this many nodes.
+    pub nodes: Option<u64>,
+    /// Random moves played from the start position, or from the end of the book line, to make the openings.
+    pub random_plies: usize,
+    pub seed: u64,
+    /// The learning rate at the end of the run, `r_end` in OpenBench.
+    pub r_end: f64,
+    /// The stability constant, as a fraction of the iterations.
+    pub a_ratio: f64,
+    /// How fast the learning rate decays.
+    pub alpha: f64,
+    /// How fast the perturbations shrink.
+    pub gamma: f64,
+    /// Adjudicate a win once both sides have scored the position beyond this for `resign_plies` plies in a row.
+    pub resign_score: i32,
+    pub resign_plies: u32,
+    /// Adjudicate a draw after this many plies.
+    pub max_plies: u32,
+}
+
+impl Default for SpsaConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 1000,
+            pairs: 8,
+            threads: 1,
+            hash_mb: 4,
+            nodes: Some(5000),
+            random_plies: 8,
+            seed: 0,
+            r_end: 0.002,
+            a_ratio: 0.1,
+            alpha: 0.602,
+            gamma: 0.101,
+            resign_score: 1000,
+            resign_plies: 6,
+            max_plies: 400,
+        }
+    }
+}
+
+/// The transposition table and correction history of one side of a tuning match. They are allocated once per match
+/// thread and cleared before every game.
+struct Tables {
+    tt: Vec<TtEntry>,
+    corrhist: Box<[[i32; 16384]; 2]>,
+}
+
+impl Tables {
+    fn new(hash_mb: usize) -> Self {
+        Self { tt: allocate_tt(hash_mb), corrhist: Box::new([[0; 16384]; 2]) }
+    }
+
+    fn clear(&mut self) {
+        self.tt.iter_mut().for_each(|entry| *entry = TtEntry::default());
+        *self.corrhist = [[0; 16384]; 2];
+    }
+}
+
+/// Play a game from `opening` between `white` and `black`, each searching with its parameters and tables.
+fn play_game<E: Evaluator>(
+    zobrist: &Zobrist, config: &SpsaConfig, evaluator: &E, opening: &Board, white: (&SearchParams, &mut Tables),
+    black: (&SearchParams, &mut Tables),
+) -> GameResult {
+    let (white_params, white_tables) = white;
+    let (black_params, black_tables) = black;
+    white_tables.clear();
+    black_tables.clear();
+    let mut board = opening.clone();
+    let mut keystack = vec![board.hash()];
+    let mut resign = Resign::default();
+    for _ in 0..config.max_plies {
+        if let Some(result) = game_over(&board, &keystack) {
+            return result;
+        }
+
+        let (params, tables) =
+            if board.side() == Colour::White { (white_params, &mut *white_tables) } else { (black_params, &mut *black_tables) };
+        let mut search = Search::with_evaluator(None, zobrist, &tables.tt, &mut tables.corrhist, params, evaluator.clone());
+        search.set_node_limit(config.nodes);
+        let Some(iteration) = IterativeDeepening::new(99).run(&mut search, &board, &mut keystack, |_| {}) else {
+            return GameResult::Draw;
+        };
+        let Some(&m) = iteration.pv.first() else {
+            return GameResult::Draw;
+        };
+
+        let score = if board.side() == Colour::White { iteration.score } else { -iteration.score };
+        if score.is_mate() {
+            return winner(score);
+        }
+        if let Some(result) = resign.update(score, config.resign_score, config.resign_plies) {
+            return result;
+        }
+
+        board = board.make(m, zobrist);
+        keystack.push(board.hash());
+    }
+    GameResult::Draw
+}
+
+/// Tunes the search parameters by SPSA.
+pub struct Spsa<'a, E: Evaluator> {
+    config: SpsaConfig,
+    evaluator: E,
+    book: Option<&'a Book>,
+    zobrist: Zobrist,
+    pool: rayon::ThreadPool,
+    rng: StdRng,
+    /// The parameters the tunables are set on, which keep any that are not tuned.
+    base: SearchParams,
+    /// The tunables' option values, unrounded.
+    theta: Vec<f64>,
+    /// The tables of both sides, for each match thread.
+    tables: Vec<Mutex<[Tables; 2]>>,
+    iteration: u64,
+}
+
+impl<'a, E: Evaluator + Send + Sync> Spsa<'a, E> {
+    /// Start tuning from `start`, playing with `evaluator` from openings out of `book`, if any.
+    ///
+    /// # Errors
+    /// Returns an error if the match threads cannot be started.
+    pub fn new(config: SpsaConfig, start: &SearchParams, evaluator: E, book: Option<&'a Book>) -> io::Result<Self> {
+        let pool =
+            rayon::ThreadPoolBuilder::new().num_threads(config.threads).stack_size(STACK_SIZE).build().map_err(io::Error::other)?;
+        let hash_mb = config.hash_mb;
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            evaluator,
+            book,
+            zobrist: Zobrist::polyglot(),
+            base: start.clone(),
+            theta: SearchParams::TUNABLES.iter().map(|tunable| f64::from(tunable.get(start))).collect(),
+            iteration: 0,
+            tables: (0..pool.current_num_threads()).map(|_| Mutex::new([Tables::new(hash_mb), Tables::new(hash_mb)])).collect(),
+            pool,
+        })
+    }
+
+    /// The parameters with the tunables at `theta`.
+    fn params_at(&self, theta: impl IntoIterator<Item = f64>) -> SearchParams {
+        let mut params = self.base.clone();
+        for (tunable, value) in SearchParams::TUNABLES.iter().zip(theta) {
+            tunable.set(&mut params, value.round() as i32);
+        }
+        params
+    }
+
+    /// The current parameters, rounded to option values.
+    #[must_use]
+    pub fn params(&self) -> SearchParams {
+        self.params_at(self.theta.iter().copied())
+    }
+
+    /// Play one iteration and update the parameters, returning the points the side perturbed upwards won by.
+    pub fn step(&mut self) -> f64 {
+        self.iteration += 1;
+        let (k, n) = (self.iteration as f64, self.config.iterations as f64);
+        let big_a = self.config.a_ratio * n;
+
+        // Per tunable: the perturbation and the learning rate of this iteration.
+        let rates = SearchParams::TUNABLES
+            .iter()
+            .map(|tunable| {
+                let c = tunable.step * n.powf(self.config.gamma);
+                let a = self.config.r_end * tunable.step * tunable.step * (big_a + n).powf(self.config.alpha);
+                let c_k = c / k.powf(self.config.gamma);
+                let a_k = a / (big_a + k).powf(self.config.alpha);
+                (c_k, a_k / (c_k * c_k))
+            })
+            .collect::<Vec<_>>();
+        let signs = (0..self.theta.len()).map(|_| if self.rng.gen() { 1.0 } else { -1.0 }).collect::<Vec<f64>>();
+
+        let plus =
+            self.params_at(self.theta.iter().zip(&rates).zip(&signs).map(|((theta, (c_k, _)), sign)| sign.mul_add(*c_k, *theta)));
+        let minus = self
+            .params_at(self.theta.iter().zip(&rates).zip(&signs).map(|((theta, (c_k, _)), sign)| (-sign).mul_add(*c_k, *theta)));
+
+        let openings = (0..self.config.pairs)
+            .map(|_| loop {
+                if let Some(board) = opening(&self.zobrist, self.book, self.config.random_plies, &mut self.rng) {
+                    break board;
+                }
+            })
+            .collect::<Vec<_>>();
+        // Each thread plays pairs until the openings run out, scoring the points `plus` won less those `minus` won.
+        let next = AtomicUsize::new(0);
+        let (zobrist, config, evaluator) = (&self.zobrist, &self.config, &self.evaluator);
+        let points = |result| match result {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => -1.0,
+            GameResult::Draw | GameResult::Unknown => 0.0,
+        };
+        let result = self
+            .pool
+            .broadcast(|context| {
+                let mut tables = self.tables[context.index()].lock().unwrap();
+                let [plus_tables, minus_tables] = &mut *tables;
+                let mut result = 0.0;
+                while let Some(opening) = openings.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    result += points(play_game(zobrist, config, evaluator, opening, (&plus, plus_tables), (&minus, minus_tables)));
+                    result -= points(play_game(zobrist, config, evaluator, opening, (&minus, minus_tables), (&plus, plus_tables)));
+                }
+                result
+            })
+            .into_iter()
+            .sum::<f64>();
+
+        for (((theta, (c_k, r_k)), sign), tunable) in self.theta.iter_mut().zip(&rates).zip(&signs).zip(SearchParams::TUNABLES) {
+            *theta = (r_k * c_k * result).mul_add(*sign, *theta).clamp(f64::from(tunable.min), f64::from(tunable.max));
+        }
+        result
+    }
+
+    /// Run the remaining iterations, calling `on_iteration` with the iteration number and result after each.
+    pub fn run(&mut self, mut on_iteration: impl FnMut(u64, f64, &Self)) {
+        while self.iteration < self.config.iterations {
+            let result = self.step();
+            on_iteration(self.iteration, result, self);
+        }
+    }
+}
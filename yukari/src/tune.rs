@@ -0,0 +1,324 @@
+//! Texel tuning of the hand-crafted evaluation's weights against labeled positions.
+//!
+//! The evaluation is linear in its material and piece-square weights once the game phase is fixed, so each position is
+//! reduced to the weights it uses and how often, and the search is never run. The phase weights are kept as they are.
+
+use std::{
+    io::{self, BufRead},
+    ops::Range,
+};
+
+use rayon::prelude::*;
+use yukari_movegen::{Board, Colour, EvalParams, Zobrist};
+
+/// Weights of one half of the evaluation: material, then the piece-square tables.
+const HALF: usize = 6 + 6 * 64;
+
+/// Middlegame weights, then endgame ones.
+const NUM_PARAMS: usize = 2 * HALF;
+
+/// Scores are divided by this before scaling by K, as in the original Texel tuning.
+const SCORE_SCALE: f64 = 400.0;
+
+/// A labeled position, reduced to what the evaluation of it depends on.
+pub struct Position {
+    /// The indices of the middlegame weights used, with the number of white pieces using them less the number of black
+    /// ones. The endgame weights are `HALF` further on.
+    features: Vec<(u16, i8)>,
+    /// How much the middlegame weights count; the endgame ones count for the rest.
+    mg: f64,
+    /// The search score in centipawns, from white's point of view.
+    score: f64,
+    /// The fraction of a point white scored.
+    result: f64,
+}
+
+impl Position {
+    /// Reduce `board` to the weights it uses.
+    #[must_use]
+    pub fn new(board: &Board, phase: &[i32; 6], score: f64, result: f64) -> Self {
+        let mut features = Vec::with_capacity(64);
+        let mut total_phase = 0;
+        for bit in board.pieces() {
+            let piece = board.piece_from_bit(bit) as usize;
+            let square = board.square_of_piece(bit);
+            let (square, coefficient) = if bit.colour() == Colour::White { (square.flip(), 1) } else { (square, -1) };
+            features.push((piece as u16, coefficient));
+            features.push(((6 + 64 * piece + square.into_inner() as usize) as u16, coefficient));
+            total_phase += phase[piece];
+        }
+
+        // Merge pieces sharing a weight, so each index appears once.
+        features.sort_unstable_by_key(|&(index, _)| index);
+        features.dedup_by(|(index, coefficient), (kept_index, kept_coefficient)| {
+            let same = index == kept_index;
+            if same {
+                *kept_coefficient += *coefficient;
+            }
+            same
+        });
+        features.retain(|&(_, coefficient)| coefficient != 0);
+
+        Self { features, mg: f64::from(total_phase) / 24.0, score, result }
+    }
+
+    /// The evaluation with `params`, from white's point of view.
+    fn evaluate(&self, params: &[f64]) -> f64 {
+        self.features
+            .iter()
+            .map(|&(index, coefficient)| {
+                let index = index as usize;
+                f64::from(coefficient) * params[index].mul_add(self.mg, params[HALF + index] * (1.0 - self.mg))
+            })
+            .sum()
+    }
+
+    /// Call `f` with the index of each weight the evaluation depends on and the derivative of the evaluation by it.
+    fn for_each_derivative(&self, mut f: impl FnMut(usize, f64)) {
+        for &(index, coefficient) in &self.features {
+            f(index as usize, f64::from(coefficient) * self.mg);
+            f(HALF + index as usize, f64::from(coefficient) * (1.0 - self.mg));
+        }
+    }
+}
+
+/// Parse a labeled position: `<fen> | <score> | <result>`, as `yukari label` and `yukari datagen` write them.
+///
+/// # Errors
+/// Returns an error if the line is not in that form.
+pub fn parse_position(line: &str, zobrist: &Zobrist, phase: &[i32; 6]) -> io::Result<Position> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let [fen, score, result] = line.split('|').map(str::trim).collect::<Vec<_>>()[..] else {
+        return Err(invalid(format!("expected `<fen> | <score> | <result>`, got `{line}`")));
+    };
+    let board = Board::from_fen(fen, zobrist).map_err(|err| invalid(format!("{fen}: {err}")))?;
+    let score = score.parse::<f64>().map_err(|err| invalid(format!("score {score}: {err}")))?;
+    let result = result.parse::<f64>().map_err(|err| invalid(format!("result {result}: {err}")))?;
+    Ok(Position::new(&board, phase, score, result))
+}
+
+/// Read labeled positions, one per line, parsing them on all threads.
+///
+/// # Errors
+/// Returns an error if reading fails or a line cannot be parsed.
+pub fn load_positions<R: BufRead>(reader: R, phase: &[i32; 6]) -> io::Result<Vec<Position>> {
+    let zobrist = Zobrist::new();
+    let lines = reader.lines().collect::<io::Result<Vec<_>>>()?;
+    lines.par_iter().filter(|line| !line.trim().is_empty()).map(|line| parse_position(line, &zobrist, phase)).collect()
+}
+
+/// The expected score of a position evaluated at `eval` centipawns, as a fraction of a point.
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10.0_f64.powf(-k * eval / SCORE_SCALE))
+}
+
+/// Solve `a x = b` for a symmetric positive definite `a`, stored by rows, with a Cholesky decomposition.
+fn cholesky_solve(mut a: Vec<f64>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for j in 0..n {
+        let diagonal = (a[j * n + j] - (0..j).map(|k| a[j * n + k] * a[j * n + k]).sum::<f64>()).max(f64::MIN_POSITIVE).sqrt();
+        a[j * n + j] = diagonal;
+        for i in j + 1..n {
+            let dot = (0..j).map(|k| a[i * n + k] * a[j * n + k]).sum::<f64>();
+            a[i * n + j] = (a[i * n + j] - dot) / diagonal;
+        }
+    }
+    for i in 0..n {
+        b[i] = (b[i] - (0..i).map(|k| a[i * n + k] * b[k]).sum::<f64>()) / a[i * n + i];
+    }
+    for i in (0..n).rev() {
+        b[i] = (b[i] - (i + 1..n).map(|k| a[k * n + i] * b[k]).sum::<f64>()) / a[i * n + i];
+    }
+    b
+}
+
+/// Fits the evaluation weights to the results of labeled positions.
+///
+/// Each position's target is a blend of its game result and its search score, passed through the same sigmoid as the
+/// evaluation: `lambda` of 1 uses only results, 0 only scores.
+pub struct Tuner {
+    positions: Vec<Position>,
+    params: Vec<f64>,
+    phase: [i32; 6],
+    k: f64,
+    lambda: f64,
+}
+
+impl Tuner {
+    /// Start tuning from `start`.
+    #[must_use]
+    pub fn new(positions: Vec<Position>, start: &EvalParams, lambda: f64) -> Self {
+        let mut params = Vec::with_capacity(NUM_PARAMS);
+        for (material, tables) in [(&start.mat_mg, &start.pst_mg), (&start.mat_eg, &start.pst_eg)] {
+            params.extend(material.iter().map(|&value| f64::from(value)));
+            params.extend(tables.iter().flatten().map(|&value| f64::from(value)));
+        }
+        Self { positions, params, phase: start.phase, k: 1.0, lambda }
+    }
+
+    #[must_use]
+    pub const fn k(&self) -> f64 {
+        self.k
+    }
+
+    pub fn set_k(&mut self, k: f64) {
+        self.k = k;
+    }
+
+    /// The position's target, as a fraction of a point.
+    fn target(&self, position: &Position) -> f64 {
+        self.lambda.mul_add(position.result, (1.0 - self.lambda) * sigmoid(self.k, position.score))
+    }
+
+    /// The mean squared error of the predicted results with `params` and scaling constant `k`.
+    fn error_with(&self, params: &[f64], k: f64) -> f64 {
+        let sum = self
+            .positions
+            .par_iter()
+            .map(|position| {
+                let target = self.lambda.mul_add(position.result, (1.0 - self.lambda) * sigmoid(k, position.score));
+                (sigmoid(k, position.evaluate(params)) - target).powi(2)
+            })
+            .sum::<f64>();
+        sum / self.positions.len() as f64
+    }
+
+    /// The mean squared error of the predicted results with the current weights.
+    #[must_use]
+    pub fn error(&self) -> f64 {
+        self.error_with(&self.params, self.k)
+    }
+
+    /// Find the scaling constant K that best predicts the results with the current weights, by golden-section search.
+    pub fn fit_k(&mut self, mut range: Range<f64>) -> f64 {
+        let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+        while range.end - range.start > 1e-4 {
+            let lower = ratio.mul_add(-(range.end - range.start), range.end);
+            let upper = ratio.mul_add(range.end - range.start, range.start);
+            if self.error_with(&self.params, lower) < self.error_with(&self.params, upper) {
+                range.end = upper;
+            } else {
+                range.start = lower;
+            }
+        }
+        self.k = (range.start + range.end) / 2.0;
+        self.k
+    }
+
+    /// The gradient of the error by each weight.
+    #[must_use]
+    pub fn gradient(&self) -> Vec<f64> {
+        let scale = self.k * std::f64::consts::LN_10 / SCORE_SCALE;
+        let sum = self
+            .positions
+            .par_iter()
+            .fold(
+                || vec![0.0; NUM_PARAMS],
+                |mut gradient, position| {
+                    let predicted = sigmoid(self.k, position.evaluate(&self.params));
+                    let slope = 2.0 * (predicted - self.target(position)) * predicted * (1.0 - predicted) * scale;
+                    position.for_each_derivative(|index, derivative| gradient[index] += slope * derivative);
+                    gradient
+                },
+            )
+            .reduce(|| vec![0.0; NUM_PARAMS], |a, b| a.iter().zip(&b).map(|(a, b)| a + b).collect());
+        sum.into_iter().map(|value| value / self.positions.len() as f64).collect()
+    }
+
+    /// Optimise the weights with Adam for `epochs` full passes at learning rate `rate`, calling `on_epoch` after each.
+    pub fn adam(&mut self, epochs: usize, rate: f64, mut on_epoch: impl FnMut(usize, &Self)) {
+        const BETA1: f64 = 0.9;
+        const BETA2: f64 = 0.999;
+        const EPSILON: f64 = 1e-8;
+
+        let mut momentum = vec![0.0; NUM_PARAMS];
+        let mut velocity = vec![0.0; NUM_PARAMS];
+        for epoch in 1..=epochs {
+            let gradient = self.gradient();
+            let correction1 = 1.0 - BETA1.powi(epoch as i32);
+            let correction2 = 1.0 - BETA2.powi(epoch as i32);
+            for (((param, gradient), momentum), velocity) in
+                self.params.iter_mut().zip(gradient).zip(&mut momentum).zip(&mut velocity)
+            {
+                *momentum = BETA1.mul_add(*momentum, (1.0 - BETA1) * gradient);
+                *velocity = BETA2.mul_add(*velocity, (1.0 - BETA2) * gradient * gradient);
+                *param -= rate * (*momentum / correction1) / ((*velocity / correction2).sqrt() + EPSILON);
+            }
+            on_epoch(epoch, self);
+        }
+    }
+
+    /// Optimise the weights with damped Gauss-Newton (Levenberg-Marquardt) steps, calling `on_iteration` after each.
+    ///
+    /// Each step solves for the weights that minimise the error of a linear model of the sigmoid around the current
+    /// ones. Weights no position uses, and the freedom to move value between material and piece-square tables, are
+    /// held in place by the damping.
+    pub fn gauss_newton(&mut self, iterations: usize, mut on_iteration: impl FnMut(usize, &Self)) {
+        let scale = self.k * std::f64::consts::LN_10 / SCORE_SCALE;
+        let mut damping: f64 = 1e-3;
+        for iteration in 1..=iterations {
+            // Accumulate the normal equations: JᵀJ and Jᵀr, with r the residuals and J their derivatives.
+            let (jtj, jtr) = self
+                .positions
+                .par_iter()
+                .fold(
+                    || (vec![0.0; NUM_PARAMS * NUM_PARAMS], vec![0.0; NUM_PARAMS]),
+                    |(mut jtj, mut jtr), position| {
+                        let predicted = sigmoid(self.k, position.evaluate(&self.params));
+                        let residual = predicted - self.target(position);
+                        let slope = predicted * (1.0 - predicted) * scale;
+                        let mut jacobian = Vec::with_capacity(2 * position.features.len());
+                        position.for_each_derivative(|index, derivative| jacobian.push((index, slope * derivative)));
+                        for &(i, di) in &jacobian {
+                            jtr[i] = di.mul_add(residual, jtr[i]);
+                            for &(j, dj) in &jacobian {
+                                jtj[i * NUM_PARAMS + j] = di.mul_add(dj, jtj[i * NUM_PARAMS + j]);
+                            }
+                        }
+                        (jtj, jtr)
+                    },
+                )
+                .reduce(
+                    || (vec![0.0; NUM_PARAMS * NUM_PARAMS], vec![0.0; NUM_PARAMS]),
+                    |(a, x), (b, y)| {
+                        (a.iter().zip(&b).map(|(a, b)| a + b).collect(), x.iter().zip(&y).map(|(x, y)| x + y).collect())
+                    },
+                );
+
+            let error = self.error();
+            loop {
+                let mut a = jtj.clone();
+                for i in 0..NUM_PARAMS {
+                    a[i * NUM_PARAMS + i] += damping.mul_add(jtj[i * NUM_PARAMS + i], 1e-9);
+                }
+                let step = cholesky_solve(a, jtr.iter().map(|value| -value).collect());
+                let params = self.params.iter().zip(&step).map(|(param, step)| param + step).collect::<Vec<_>>();
+                if self.error_with(&params, self.k) < error {
+                    self.params = params;
+                    damping /= 10.0;
+                    break;
+                }
+                damping *= 10.0;
+                if damping > 1e9 {
+                    return;
+                }
+            }
+            on_iteration(iteration, self);
+        }
+    }
+
+    /// The current weights, rounded to whole centipawns.
+    #[must_use]
+    pub fn params(&self) -> EvalParams {
+        let round = |value: f64| value.round() as i32;
+        let half = |offset: usize| {
+            let material = std::array::from_fn(|piece| round(self.params[offset + piece]));
+            let tables =
+                std::array::from_fn(|piece| std::array::from_fn(|square| round(self.params[offset + 6 + 64 * piece + square])));
+            (material, tables)
+        };
+        let (mat_mg, pst_mg) = half(0);
+        let (mat_eg, pst_eg) = half(HALF);
+        EvalParams { mat_mg, mat_eg, pst_mg, pst_eg, phase: self.phase }
+    }
+}